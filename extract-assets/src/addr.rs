@@ -6,9 +6,16 @@ use std::{
 
 use zerocopy::{BigEndian, FromBytes, U32};
 
-pub struct _PhysAddr(u32);
+/// An offset into the ROM file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PhysAddr(pub u32);
+impl Display for PhysAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010X}", self.0)
+    }
+}
 
-#[derive(Copy, Clone, Default, FromBytes)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, FromBytes)]
 pub struct RawVirtAddr(U32<BigEndian>);
 impl RawVirtAddr {
     pub fn new(n: u32) -> Self {
@@ -22,6 +29,17 @@ impl RawVirtAddr {
     pub fn segment_offset(&self) -> u32 {
         self.0.get() & 0x00FFFFFF
     }
+
+    pub fn is_null(&self) -> bool {
+        self.0.get() == 0
+    }
+
+    /// The ROM offset of the address, given the ROM offset each segment was
+    /// read from
+    pub fn to_phys(&self, segment_offsets: &[Option<u32>]) -> Option<PhysAddr> {
+        let start = (*segment_offsets.get(self.segment_number() as usize)?)?;
+        Some(PhysAddr(start + self.segment_offset()))
+    }
 }
 impl Display for RawVirtAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -49,10 +67,16 @@ impl Add<i32> for RawVirtAddr {
 
 #[derive(Default, FromBytes)]
 pub struct VirtAddr<T>(RawVirtAddr, PhantomData<T>);
-impl<T> VirtAddr<T> {}
+impl<T> VirtAddr<T> {
+    /// Offsets the address by a number of bytes rather than elements, such as
+    /// to point into the middle of a `T`
+    pub fn offset_bytes(self, bytes: i32) -> VirtAddr<T> {
+        Self(self.0 + bytes, PhantomData)
+    }
+}
 impl<T> Clone for VirtAddr<T> {
     fn clone(&self) -> VirtAddr<T> {
-        Self(self.0, PhantomData)
+        *self
     }
 }
 impl<T> Copy for VirtAddr<T> {}
@@ -66,6 +90,7 @@ impl<T> Debug for VirtAddr<T> {
         Debug::fmt(&self.0, f)
     }
 }
+/// Offsets the address by a number of `T` elements, as when indexing an array
 impl<T> Add<i32> for VirtAddr<T> {
     type Output = VirtAddr<T>;
 
@@ -83,3 +108,24 @@ impl<T> From<VirtAddr<T>> for RawVirtAddr {
         value.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_elements_but_offsets_bytes() {
+        let addr = VirtAddr::<[u32; 3]>::from(RawVirtAddr::new(0x06000100));
+
+        assert_eq!(RawVirtAddr::from(addr + 2), RawVirtAddr::new(0x06000118));
+        assert_eq!(RawVirtAddr::from(addr + -1), RawVirtAddr::new(0x060000F4));
+        assert_eq!(
+            RawVirtAddr::from(addr.offset_bytes(2)),
+            RawVirtAddr::new(0x06000102)
+        );
+        assert_eq!(
+            RawVirtAddr::from(addr.offset_bytes(-4)),
+            RawVirtAddr::new(0x060000FC)
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! The stable surface of the crate. Addresses are segmented addresses, e.g.
+//! `0x06009D74` for offset `0x9D74` of the object segment.
+
+use std::mem;
+
+use anyhow::Result;
+use gltf::json::{self, animation::Property, validation::Checked::Valid};
+
+use crate::{addr::RawVirtAddr, buffer, mesh, scene, skeleton};
+
+pub use crate::{
+    mesh::MeshOptions,
+    report::ExtractionReport,
+    rom::{Reader, RomError, Segment},
+    skeleton::Progress,
+    skeleton_animation::{AnimationAddr, AnimationOptions, NamedAnimation},
+};
+
+/// Extracts a skeleton along with its named animations into a new glTF root
+pub fn extract_skeleton_to_gltf(
+    reader: &Reader,
+    skeleton: u32,
+    animations: &[(String, u32)],
+) -> Result<json::Root> {
+    Extraction::new(reader)
+        .skeleton(skeleton)
+        .animations(animations)
+        .to_gltf()
+}
+
+/// Extracts the mesh drawn by a single display list into a new glTF root
+pub fn extract_display_list_to_gltf(reader: &Reader, display_list: u32) -> Result<json::Root> {
+    Extraction::new(reader).display_list(display_list).to_gltf()
+}
+
+#[derive(Clone)]
+enum Source {
+    Skeleton {
+        addr: u32,
+        animations: Vec<NamedAnimation>,
+        joint_limits: Option<u32>,
+    },
+    DisplayList(u32),
+    Room,
+}
+
+/// Builds an extraction of skeletons and display lists, with the options to
+/// extract them with. Each of them becomes a root node of the scene.
+pub struct Extraction<'a> {
+    reader: &'a Reader,
+    sources: Vec<Source>,
+    mesh_options: MeshOptions,
+    animation_options: AnimationOptions,
+}
+impl<'a> Extraction<'a> {
+    pub fn new(reader: &'a Reader) -> Self {
+        Self {
+            reader,
+            sources: Vec::new(),
+            mesh_options: Default::default(),
+            animation_options: Default::default(),
+        }
+    }
+
+    pub fn skeleton(mut self, addr: u32) -> Self {
+        self.sources.push(Source::Skeleton {
+            addr,
+            animations: Vec::new(),
+            joint_limits: None,
+        });
+        self
+    }
+
+    pub fn display_list(mut self, addr: u32) -> Self {
+        self.sources.push(Source::DisplayList(addr));
+        self
+    }
+
+    /// Adds the geometry of the room loaded into the room segment
+    pub fn room(mut self) -> Self {
+        self.sources.push(Source::Room);
+        self
+    }
+
+    /// Adds named animations of the skeleton added last, ignored unless the
+    /// last thing added is a skeleton
+    pub fn animations(self, animations: &[(String, u32)]) -> Self {
+        animations.iter().fold(self, |extraction, (name, addr)| {
+            extraction.animation(name, *addr, None)
+        })
+    }
+
+    /// Adds an animation like `animations`, playing back at `fps` frames per
+    /// second instead of the rate of the animation options when given
+    pub fn animation(self, name: &str, addr: u32, fps: Option<f32>) -> Self {
+        self.push_animation(
+            name,
+            AnimationAddr::Standard(RawVirtAddr::new(addr).into()),
+            fps,
+        )
+    }
+
+    /// Adds one of Link's animations like `animation`. Its frames are read
+    /// from the link animation segment, which has to be loaded as well.
+    pub fn link_animation(self, name: &str, addr: u32, fps: Option<f32>) -> Self {
+        self.push_animation(
+            name,
+            AnimationAddr::Link(RawVirtAddr::new(addr).into()),
+            fps,
+        )
+    }
+
+    fn push_animation(mut self, name: &str, addr: AnimationAddr, fps: Option<f32>) -> Self {
+        if let Some(Source::Skeleton { animations, .. }) = self.sources.last_mut() {
+            animations.push(NamedAnimation {
+                name: name.to_owned(),
+                addr,
+                fps,
+            });
+        }
+        self
+    }
+
+    /// Sets the table of rotation limits of the joints of the skeleton added
+    /// last, exported to the extras of the limb nodes
+    pub fn joint_limits(mut self, addr: u32) -> Self {
+        if let Some(Source::Skeleton { joint_limits, .. }) = self.sources.last_mut() {
+            *joint_limits = Some(addr);
+        }
+        self
+    }
+
+    pub fn mesh_options(mut self, options: MeshOptions) -> Self {
+        self.mesh_options = options;
+        self
+    }
+
+    pub fn animation_options(mut self, options: AnimationOptions) -> Self {
+        self.animation_options = options;
+        self
+    }
+
+    /// Extracts into a new glTF root with a scene holding the extracted nodes
+    pub fn to_gltf(&self) -> Result<json::Root> {
+        self.to_gltf_with_report().map(|(root, _)| root)
+    }
+
+    /// Like `to_gltf`, also reporting what was extracted and what was left
+    /// out
+    pub fn to_gltf_with_report(&self) -> Result<(json::Root, ExtractionReport)> {
+        self.to_gltf_with_progress(&mut |_| ())
+    }
+
+    /// Like `to_gltf`, leaving the animations out. Each of them is instead
+    /// extracted into a root of its own named after it, holding only the
+    /// nodes of its skeleton and the animation. Morph target weights need
+    /// the meshes and are left out of those.
+    pub fn to_gltf_per_animation(&self) -> Result<(json::Root, Vec<(String, json::Root)>)> {
+        let without_animations = self
+            .sources
+            .iter()
+            .map(|source| match source {
+                Source::Skeleton {
+                    addr, joint_limits, ..
+                } => Source::Skeleton {
+                    addr: *addr,
+                    animations: Vec::new(),
+                    joint_limits: *joint_limits,
+                },
+                source => source.clone(),
+            })
+            .collect();
+        let base = self.with_sources(without_animations).to_gltf()?;
+
+        let mut animation_roots = Vec::new();
+        for source in &self.sources {
+            let Source::Skeleton {
+                addr,
+                animations,
+                joint_limits,
+            } = source
+            else {
+                continue;
+            };
+
+            for animation in animations {
+                let mut root = self
+                    .with_sources(vec![Source::Skeleton {
+                        addr: *addr,
+                        animations: vec![animation.clone()],
+                        joint_limits: *joint_limits,
+                    }])
+                    .to_gltf()?;
+                keep_only_animations(&mut root)?;
+                animation_roots.push((animation.name.clone(), root));
+            }
+        }
+
+        Ok((base, animation_roots))
+    }
+
+    fn with_sources(&self, sources: Vec<Source>) -> Self {
+        Self {
+            reader: self.reader,
+            sources,
+            mesh_options: self.mesh_options.clone(),
+            animation_options: self.animation_options.clone(),
+        }
+    }
+
+    /// Like `to_gltf_with_report`, calling `progress` as the limbs and
+    /// animations of skeletons are read
+    pub fn to_gltf_with_progress(
+        &self,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(json::Root, ExtractionReport)> {
+        if self.sources.is_empty() {
+            anyhow::bail!("Nothing to extract, add a skeleton, a display list or a room");
+        }
+
+        let mut root = buffer::new_root();
+        let mut report = ExtractionReport::default();
+        let mut scene_nodes = Vec::new();
+        for source in &self.sources {
+            match source {
+                Source::Skeleton {
+                    addr,
+                    animations,
+                    joint_limits,
+                } => {
+                    let first_node = root.nodes.len() as u32;
+                    report.merge(skeleton::read_into_gltf(
+                        &mut root,
+                        self.reader,
+                        RawVirtAddr::new(*addr).into(),
+                        animations,
+                        &self.mesh_options,
+                        &self.animation_options,
+                        Some(&mut *progress),
+                    )?);
+
+                    if let Some(limits_addr) = joint_limits {
+                        skeleton::read_joint_limits_into_gltf(
+                            &mut root,
+                            self.reader,
+                            RawVirtAddr::new(*addr).into(),
+                            RawVirtAddr::new(*limits_addr).into(),
+                            first_node,
+                        )?;
+                    }
+                    // The root limb, and the skinned mesh outside of the limb
+                    // hierarchy
+                    scene_nodes.push(json::Index::new(first_node));
+                    scene_nodes.extend(
+                        (first_node..root.nodes.len() as u32)
+                            .filter(|&node| root.nodes[node as usize].skin.is_some())
+                            .map(json::Index::new),
+                    );
+                }
+                Source::DisplayList(addr) => {
+                    let mut mesh = mesh::read_display_list(
+                        self.reader,
+                        RawVirtAddr::new(*addr),
+                        &self.mesh_options,
+                    )?;
+                    mesh.apply_options(&self.mesh_options);
+                    let meshes = root.meshes.len();
+                    let node = mesh.write_node_into_gltf(
+                        &mut root,
+                        &format!("{}", RawVirtAddr::new(*addr)),
+                        &self.mesh_options,
+                    );
+
+                    report.merge(mem::take(&mut mesh.report));
+                    report.meshes += root.meshes.len() - meshes;
+                    scene_nodes.push(node);
+                }
+                Source::Room => {
+                    let (node, room_report) =
+                        scene::read_room_into_gltf(&mut root, self.reader, &self.mesh_options)?;
+                    report.merge(room_report);
+                    scene_nodes.push(node);
+                }
+            }
+        }
+
+        root.scenes.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: scene_nodes,
+        });
+        buffer::merge_buffers(&mut root)?;
+
+        Ok((root, report))
+    }
+}
+
+/// Strips `root` down to its nodes and the animations of them, leaving out
+/// the channels of morph target weights
+fn keep_only_animations(root: &mut json::Root) -> Result<()> {
+    let mut accessors = Vec::new();
+    for animation in &mut root.animations {
+        let mut samplers = Vec::new();
+        animation.channels.retain_mut(|channel| {
+            if channel.target.path == Valid(Property::MorphTargetWeights) {
+                return false;
+            }
+            samplers.push(animation.samplers[channel.sampler.value()].clone());
+            channel.sampler = json::Index::new(samplers.len() as u32 - 1);
+            true
+        });
+
+        for sampler in &mut samplers {
+            for accessor in [&mut sampler.input, &mut sampler.output] {
+                let index = match accessors.iter().position(|&a| a == accessor.value() as u32) {
+                    Some(index) => index,
+                    None => {
+                        accessors.push(accessor.value() as u32);
+                        accessors.len() - 1
+                    }
+                };
+                *accessor = json::Index::new(index as u32);
+            }
+        }
+        animation.samplers = samplers;
+    }
+    buffer::retain_accessors(root, &accessors)?;
+
+    for node in &mut root.nodes {
+        node.mesh = None;
+        node.skin = None;
+        node.weights = None;
+    }
+    root.meshes.clear();
+    root.skins.clear();
+    root.materials.clear();
+    root.textures.clear();
+    root.samplers.clear();
+    root.images.clear();
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, fmt::Write as _, fs, io, path::Path};
+
+use anyhow::{Context, Result};
+use gltf::json;
+
+use crate::{addr::RawVirtAddr, buffer, display_list, instancing, mesh, rom, skeleton};
+
+/// What happened to a single dmadata file during a bulk extraction
+pub enum Outcome {
+    Extracted(String),
+    Skipped(String),
+    Failed(anyhow::Error),
+}
+
+/// Extracts every dmadata file that looks like an object into `out`, one
+/// glTF per object, and writes a `summary.txt` of the outcome of each file
+pub fn extract_all(
+    reader: &mut rom::Reader,
+    out: &Path,
+    mesh_options: &mesh::MeshOptions,
+) -> Result<Vec<Outcome>> {
+    fs::create_dir_all(out).with_context(|| format!("Failed to create {}", out.display()))?;
+
+    let mut outcomes = Vec::new();
+    let mut summary = String::new();
+    for index in 0..reader.file_count() {
+        // A file whose data trips up decoding must not abort the remaining
+        // files
+        let outcome = extract_file(
+            reader,
+            mesh_options,
+            index,
+            &out.join(format!("object_{:04}.gltf", index)),
+        )
+        .unwrap_or_else(Outcome::Failed);
+
+        let line = match &outcome {
+            Outcome::Extracted(what) => format!("{:04}: extracted {}", index, what),
+            Outcome::Skipped(why) => format!("{:04}: skipped, {}", index, why),
+            Outcome::Failed(error) => format!("{:04}: failed, {:#}", index, error),
+        };
+        log::info!("{}", line);
+        writeln!(summary, "{}", line)?;
+
+        outcomes.push(outcome);
+    }
+
+    fs::write(out.join("summary.txt"), summary)?;
+
+    Ok(outcomes)
+}
+
+fn extract_file(
+    reader: &mut rom::Reader,
+    mesh_options: &mesh::MeshOptions,
+    index: usize,
+    path: &Path,
+) -> Result<Outcome> {
+    if let Err(error) = reader.load_file(rom::Segment::Object, index) {
+        return Ok(Outcome::Skipped(format!("{:#}", error)));
+    }
+    let data = reader.slice_from(RawVirtAddr::new((rom::Segment::Object as u32) << 24))?;
+    if let Some(why) = implausible_object(data) {
+        return Ok(Outcome::Skipped(why.to_owned()));
+    }
+
+    let mut root = buffer::new_root();
+    let what = if let Some(skeleton_addr) =
+        skeleton::find_skeletons(reader, rom::Segment::Object)?.first()
+    {
+        skeleton::read_into_gltf(
+            &mut root,
+            reader,
+            *skeleton_addr,
+            &[],
+            mesh_options,
+            &Default::default(),
+            None,
+        )?;
+        root.scenes.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: vec![json::Index::new(0)],
+        });
+        format!("skeleton at {}", skeleton_addr)
+    } else {
+        let dlists = find_display_lists(reader, rom::Segment::Object)?;
+        if dlists.is_empty() {
+            return Ok(Outcome::Skipped(String::from(
+                "no skeleton or display list",
+            )));
+        }
+
+        // Identical display lists draw identical meshes, as they refer to the
+        // same absolute addresses, so their nodes share a single mesh
+        let mut meshes = HashMap::<&[u8], json::Index<json::Mesh>>::new();
+        let mut nodes = Vec::new();
+        for dlist in &dlists {
+            let data = reader.display_list_from(*dlist)?;
+            let data = &data[..display_list::display_list_len(data).unwrap_or(data.len())];
+            let node = match meshes.get(data) {
+                Some(mesh) => mesh::push_mesh_node(&mut root, *mesh, format!("{}", dlist)),
+                None => {
+                    let mut mesh = mesh::read_display_list(reader, *dlist, mesh_options)?;
+                    mesh.apply_options(mesh_options);
+                    let node =
+                        mesh.write_node_into_gltf(&mut root, &format!("{}", dlist), mesh_options);
+                    if let Some(mesh) = root.nodes[node.value()].mesh {
+                        meshes.insert(data, mesh);
+                    }
+                    node
+                }
+            };
+            nodes.push(node);
+        }
+        root.scenes.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes,
+        });
+        format!("{} display lists", dlists.len())
+    };
+
+    let instanced = instancing::collapse_shared_meshes(&mut root);
+    buffer::merge_buffers(&mut root)?;
+
+    let writer = io::BufWriter::new(
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+    );
+    buffer::write_gltf(&root, &instanced, writer)?;
+
+    Ok(Outcome::Extracted(what))
+}
+
+/// Tells why `data` can't be an object, if it can't. This is cheaper than
+/// looking for skeletons and display lists, and keeps code whose instructions
+/// happen to decode as either from being extracted.
+fn implausible_object(data: &[u8]) -> Option<&'static str> {
+    // `jr $ra` ends every function, but is no display list instruction
+    const JR_RA: u32 = 0x03E00008;
+
+    let segment = rom::Segment::Object as u32;
+    let segment_end = (segment << 24) + data.len() as u32;
+    let mut points_into_itself = false;
+    for word in data
+        .chunks_exact(4)
+        .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+    {
+        if word == JR_RA {
+            return Some("looks like code");
+        }
+        points_into_itself |= word >> 24 == segment && word < segment_end;
+    }
+
+    // Display lists load their vertices through object segment addresses
+    (!points_into_itself).then_some("no address in the object segment")
+}
+
+/// Finds display lists that draw geometry, i.e. that load vertices and draw
+/// triangles with them, skipping past each list once found
+fn find_display_lists(reader: &rom::Reader, segment: rom::Segment) -> Result<Vec<RawVirtAddr>> {
+    let segment_start = RawVirtAddr::new((segment as u32) << 24);
+    let data = reader.slice_from(segment_start)?;
+
+    let mut dlists = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        match display_list::display_list_len(&data[offset..]) {
+            Some(len)
+                if data[offset] != display_list::Opcode::SPNOOP.byte()
+                    && draws_geometry(&data[offset..offset + len]) =>
+            {
+                dlists.push(segment_start + offset as i32);
+                offset += len;
+            }
+            _ => offset += 8,
+        }
+    }
+
+    Ok(dlists)
+}
+
+fn draws_geometry(dlist: &[u8]) -> bool {
+    let has = |opcode: display_list::Opcode| {
+        dlist
+            .chunks_exact(8)
+            .any(|instruction| instruction[0] == opcode.byte())
+    };
+    has(display_list::Opcode::VTX)
+        && (has(display_list::Opcode::TRI1) || has(display_list::Opcode::TRI2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{self, object_addr, SegmentBuilder, TempRom};
+
+    #[test]
+    fn extracts_every_object_and_summarizes() {
+        let skeleton = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 1)
+            .u32s(0x10, &[object_addr(0x20)])
+            .skin_limb(0x20, [0, 0, 0], [0xFF, 0xFF], 11, object_addr(0x100));
+        let skeleton = test_rom::triangle_list(skeleton, 0x100, 0x200).into_bytes();
+        let dlist = test_rom::triangle_list(SegmentBuilder::new(), 0x00, 0x100).into_bytes();
+        // A function returning a pointer into the object segment
+        let code = SegmentBuilder::new()
+            .u32s(0x00, &[0x3C020600, 0x03E00008, 0x24420100, 0x06000000])
+            .into_bytes();
+        let rom = TempRom::new(
+            "extract-all",
+            &test_rom::rom(b"NZLE", &[&skeleton, &dlist, &code]),
+        );
+
+        let out =
+            std::env::temp_dir().join(format!("armos-test-{}-extract-all", std::process::id()));
+        let mut reader = rom::Reader::from_rom(rom.path()).unwrap();
+        let outcomes = extract_all(&mut reader, &out, &Default::default()).unwrap();
+
+        let extracted = outcomes
+            .iter()
+            .enumerate()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Extracted(_)))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        assert_eq!(extracted, [2, 3]);
+        assert!(out.join("object_0002.gltf").exists());
+        assert!(out.join("object_0003.gltf").exists());
+
+        let summary = fs::read_to_string(out.join("summary.txt")).unwrap();
+        let lines = summary.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[1], "0001: skipped, no address in the object segment");
+        assert_eq!(lines[2], "0002: extracted skeleton at 0x06000000");
+        assert_eq!(lines[3], "0003: extracted 1 display lists");
+        assert_eq!(lines[4], "0004: skipped, looks like code");
+        assert!(!out.join("object_0004.gltf").exists());
+
+        fs::remove_dir_all(&out).unwrap();
+    }
+}
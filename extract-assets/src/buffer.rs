@@ -0,0 +1,308 @@
+use std::{borrow::Cow, io::Write};
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use gltf::json;
+
+use crate::instancing;
+
+const DATA_URI_PREFIX: &str = "data:application/octet-stream;base64,";
+const PNG_DATA_URI_PREFIX: &str = "data:image/png;base64,";
+
+/// An empty glTF root, with the asset naming this crate as its generator
+pub fn new_root() -> json::Root {
+    let mut root = json::Root::default();
+    root.asset.generator = Some(format!("armos {}", env!("CARGO_PKG_VERSION")));
+    root
+}
+
+/// Encodes buffer data as a glTF data URI. Every writer goes through this so
+/// that all buffers use the same base64 engine and (padded) alphabet.
+pub fn data_uri(bytes: &[u8]) -> String {
+    format!("{}{}", DATA_URI_PREFIX, BASE64_STANDARD.encode(bytes))
+}
+
+/// Encodes a PNG image as a glTF data URI
+pub fn png_data_uri(png: &[u8]) -> String {
+    format!("{}{}", PNG_DATA_URI_PREFIX, BASE64_STANDARD.encode(png))
+}
+
+/// Removes the data URIs of every buffer of `root`, returning the decoded
+/// data of each buffer in order
+pub fn take_buffers(root: &mut json::Root) -> Result<Vec<Vec<u8>>> {
+    root.buffers
+        .iter_mut()
+        .enumerate()
+        .map(|(index, buffer)| {
+            let uri = buffer.uri.take().unwrap_or_default();
+            let data = uri
+                .strip_prefix(DATA_URI_PREFIX)
+                .with_context(|| format!("Buffer {} does not have a data URI", index))?;
+            BASE64_STANDARD
+                .decode(data)
+                .with_context(|| format!("Failed to decode buffer {}", index))
+        })
+        .collect()
+}
+
+/// Accumulates the data of many buffers into one, each part aligned to 4
+/// bytes as accessors require
+#[derive(Default)]
+pub struct BufferBuilder {
+    data: Vec<u8>,
+}
+impl BufferBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes`, returning the offset they start at
+    pub fn push(&mut self, bytes: &[u8]) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(bytes);
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        offset
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Packs `buffers`, the data of each of the buffers of `root`, into one
+/// buffer that the buffer views then refer to by offset. Returns the data of
+/// the packed buffer, which is left without a URI.
+fn pack_buffers(root: &mut json::Root, buffers: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut builder = BufferBuilder::new();
+    let offsets = buffers
+        .iter()
+        .map(|buffer| builder.push(buffer))
+        .collect::<Vec<_>>();
+    let data = builder.into_inner();
+
+    for view in &mut root.buffer_views {
+        let offset = *offsets
+            .get(view.buffer.value())
+            .with_context(|| format!("No data for buffer {}", view.buffer.value()))?;
+        view.buffer = json::Index::new(0);
+        view.byte_offset = Some(offset + view.byte_offset.unwrap_or(0));
+    }
+
+    root.buffers = if data.is_empty() {
+        Vec::new()
+    } else {
+        vec![json::Buffer {
+            byte_length: data.len() as _,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: None,
+        }]
+    };
+
+    Ok(data)
+}
+
+/// Replaces the buffers of `root`, which every writer adds one of per
+/// accessor, with a single buffer holding all of their data
+pub fn merge_buffers(root: &mut json::Root) -> Result<()> {
+    if root.buffers.len() <= 1 {
+        return Ok(());
+    }
+
+    let buffers = take_buffers(root)?;
+    let data = pack_buffers(root, &buffers)?;
+    if let Some(buffer) = root.buffers.first_mut() {
+        buffer.uri = Some(data_uri(&data));
+    }
+    Ok(())
+}
+
+/// Removes every accessor of `root` but those in `keep`, which take the
+/// indices of their position in it, repacking the data they view into one
+/// buffer
+pub fn retain_accessors(root: &mut json::Root, keep: &[u32]) -> Result<()> {
+    let buffers = take_buffers(root)?;
+
+    let mut builder = BufferBuilder::new();
+    let mut accessors = Vec::with_capacity(keep.len());
+    let mut views = Vec::with_capacity(keep.len());
+    for &index in keep {
+        let mut accessor = root
+            .accessors
+            .get(index as usize)
+            .with_context(|| format!("No accessor {}", index))?
+            .clone();
+        if let Some(view_index) = accessor.buffer_view {
+            let view = &root.buffer_views[view_index.value()];
+            let start = view.byte_offset.unwrap_or(0) as usize;
+            let data = buffers
+                .get(view.buffer.value())
+                .and_then(|buffer| buffer.get(start..start + view.byte_length as usize))
+                .with_context(|| format!("Buffer view {} is out of bounds", view_index))?;
+
+            views.push(json::buffer::View {
+                buffer: json::Index::new(0),
+                byte_offset: Some(builder.push(data)),
+                ..view.clone()
+            });
+            accessor.buffer_view = Some(json::Index::new(views.len() as u32 - 1));
+        }
+        accessors.push(accessor);
+    }
+
+    let data = builder.into_inner();
+    root.accessors = accessors;
+    root.buffer_views = views;
+    root.buffers = if data.is_empty() {
+        Vec::new()
+    } else {
+        vec![json::Buffer {
+            byte_length: data.len() as _,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: Some(data_uri(&data)),
+        }]
+    };
+
+    Ok(())
+}
+
+/// Writes `root` as glTF JSON, with the instancing extension on the
+/// `instanced` nodes returned by `instancing::collapse_shared_meshes`. It's
+/// serialized through a `json::Value`, whose objects keep their keys sorted,
+/// so that maps such as the attributes of primitives are written in the same
+/// order every time.
+pub fn write_gltf(
+    root: &json::Root,
+    instanced: &[instancing::InstancedNode],
+    writer: impl Write,
+) -> Result<()> {
+    json::serialize::to_writer_pretty(writer, &instancing::to_value(root, instanced)?)?;
+    Ok(())
+}
+
+/// Writes `root` as binary glTF. `buffers` holds the data of each of the
+/// root's buffers, which are packed into the binary chunk as one buffer.
+pub fn write_glb(root: &json::Root, buffers: &[Vec<u8>], writer: impl Write) -> Result<()> {
+    let mut root = root.clone();
+    let bin = pack_buffers(&mut root, buffers)?;
+
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: 0,
+        },
+        json: Cow::Owned(json::serialize::to_vec(&json::serialize::to_value(&root)?)?),
+        bin: (!bin.is_empty()).then_some(Cow::Owned(bin)),
+    };
+    glb.to_writer(writer)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addr::RawVirtAddr,
+        mesh,
+        test_rom::{self, object_addr, SegmentBuilder},
+    };
+
+    /// A root with a buffer of each of `buffers`, and a view of every buffer
+    fn root_of(buffers: &[&[u8]]) -> json::Root {
+        let mut root = json::Root::default();
+        for bytes in buffers {
+            root.buffers.push(json::Buffer {
+                byte_length: bytes.len() as _,
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: None,
+                uri: Some(data_uri(bytes)),
+            });
+            root.buffer_views.push(json::buffer::View {
+                buffer: json::Index::new(root.buffers.len() as u32 - 1),
+                byte_length: bytes.len() as _,
+                byte_offset: None,
+                byte_stride: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: None,
+                target: None,
+            });
+        }
+        root
+    }
+
+    /// The base64 part of a data URI
+    fn payload(uri: &str) -> &str {
+        uri.split_once(',').unwrap().1
+    }
+
+    #[test]
+    fn encodes_the_same_bytes_the_same_everywhere() {
+        // Four bytes, which need padding but no alignment
+        let bytes = b"armo";
+        let expected = "YXJtbw==";
+
+        assert_eq!(payload(&data_uri(bytes)), expected);
+
+        let mut root = root_of(&[bytes]);
+        assert_eq!(take_buffers(&mut root).unwrap(), [bytes.to_vec()]);
+        assert_eq!(root.buffers[0].uri, None);
+    }
+
+    #[test]
+    fn packs_every_buffer_into_the_binary_chunk() {
+        let mut root = root_of(&[b"abc", b"defg"]);
+        let buffers = take_buffers(&mut root).unwrap();
+
+        let mut glb = Vec::new();
+        write_glb(&root, &buffers, &mut glb).unwrap();
+        let glb = gltf::binary::Glb::from_slice(&glb).unwrap();
+        assert_eq!(&glb.header.magic, b"glTF");
+        assert_eq!(glb.header.version, 2);
+        assert_eq!(glb.bin.as_deref(), Some(&b"abc\0defg"[..]));
+
+        let packed: json::Root = json::deserialize::from_slice(&glb.json).unwrap();
+        assert_eq!(packed.buffers.len(), 1);
+        assert_eq!(packed.buffers[0].uri, None);
+        assert_eq!(packed.buffers[0].byte_length, 8);
+        let offsets = packed
+            .buffer_views
+            .iter()
+            .map(|view| (view.buffer.value(), view.byte_offset))
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [(0, Some(0)), (0, Some(4))]);
+    }
+
+    #[test]
+    fn writes_the_same_model_the_same_every_time() {
+        let reader = test_rom::triangle_list(SegmentBuilder::new(), 0, 0x100).into_object_reader();
+        let write = || {
+            let mut root = new_root();
+            let mesh = mesh::read_display_list(
+                &reader,
+                RawVirtAddr::new(object_addr(0)),
+                &Default::default(),
+            )
+            .unwrap();
+            mesh.write_into_gltf(&mut root, "triangle");
+            let mut gltf = Vec::new();
+            write_gltf(&root, &[], &mut gltf).unwrap();
+            gltf
+        };
+
+        let gltf = write();
+        assert_eq!(gltf, write());
+
+        let root: json::Root = json::deserialize::from_slice(&gltf).unwrap();
+        assert_eq!(
+            root.asset.generator,
+            Some(format!("armos {}", env!("CARGO_PKG_VERSION")))
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Appending to the lists of a glTF root, handing back the index of what was
+//! appended so that it never has to be worked out from the list lengths
+
+use gltf::json::{self, validation::Checked::Valid};
+
+use crate::buffer;
+
+/// An item of one of the lists of `json::Root`
+pub trait RootItem: Sized {
+    fn list(root: &mut json::Root) -> &mut Vec<Self>;
+}
+
+macro_rules! root_items {
+    ($($item:ty => $list:ident,)*) => {
+        $(
+            impl RootItem for $item {
+                fn list(root: &mut json::Root) -> &mut Vec<Self> {
+                    &mut root.$list
+                }
+            }
+        )*
+    };
+}
+root_items! {
+    json::Accessor => accessors,
+    json::Animation => animations,
+    json::Buffer => buffers,
+    json::buffer::View => buffer_views,
+    json::Image => images,
+    json::Material => materials,
+    json::Mesh => meshes,
+    json::Node => nodes,
+    json::Scene => scenes,
+    json::Skin => skins,
+    json::Texture => textures,
+}
+
+pub trait GltfBuilder {
+    /// Appends `item` to the list of its kind, returning its index
+    fn add<T: RootItem>(&mut self, item: T) -> json::Index<T>;
+
+    /// Appends `data` as a buffer of its own along with a view of all of it,
+    /// returning the view. Every writer adds a buffer per view, which
+    /// `buffer::merge_buffers` packs together in the end.
+    fn add_buffer_view(
+        &mut self,
+        data: &[u8],
+        byte_stride: Option<usize>,
+        target: Option<json::buffer::Target>,
+    ) -> json::Index<json::buffer::View>;
+
+    /// Appends `data` as a buffer and view of its own, and `accessor` viewing
+    /// it, returning the accessor
+    fn add_accessor(
+        &mut self,
+        data: &[u8],
+        target: Option<json::buffer::Target>,
+        accessor: json::Accessor,
+    ) -> json::Index<json::Accessor>;
+}
+impl GltfBuilder for json::Root {
+    fn add<T: RootItem>(&mut self, item: T) -> json::Index<T> {
+        let list = T::list(self);
+        list.push(item);
+        json::Index::new(list.len() as u32 - 1)
+    }
+
+    fn add_buffer_view(
+        &mut self,
+        data: &[u8],
+        byte_stride: Option<usize>,
+        target: Option<json::buffer::Target>,
+    ) -> json::Index<json::buffer::View> {
+        let buffer = self.add(json::Buffer {
+            byte_length: data.len() as _,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: Some(buffer::data_uri(data)),
+        });
+        self.add(json::buffer::View {
+            buffer,
+            byte_length: data.len() as _,
+            byte_offset: None,
+            byte_stride: byte_stride.map(|stride| stride as _),
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: target.map(Valid),
+        })
+    }
+
+    fn add_accessor(
+        &mut self,
+        data: &[u8],
+        target: Option<json::buffer::Target>,
+        accessor: json::Accessor,
+    ) -> json::Index<json::Accessor> {
+        let view = self.add_buffer_view(data, None, target);
+        self.add(json::Accessor {
+            buffer_view: Some(view),
+            ..accessor
+        })
+    }
+}
+
+/// An accessor of `count` elements from the start of no view yet, without
+/// bounds, for filling in the rest with struct update syntax
+pub fn accessor(
+    count: usize,
+    component_type: json::accessor::ComponentType,
+    type_: json::accessor::Type,
+) -> json::Accessor {
+    json::Accessor {
+        buffer_view: None,
+        byte_offset: 0,
+        count: count as _,
+        component_type: Valid(json::accessor::GenericComponentType(component_type)),
+        extensions: Default::default(),
+        extras: Default::default(),
+        type_: Valid(type_),
+        min: None,
+        max: None,
+        name: None,
+        normalized: false,
+        sparse: None,
+    }
+}
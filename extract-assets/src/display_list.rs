@@ -1,7 +1,10 @@
-use std::fmt::{Debug, Display};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    io,
+};
 
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
+use anyhow::{bail, Context, Result};
 use zerocopy::{BigEndian, LayoutVerified, U64};
 
 use crate::{
@@ -9,24 +12,128 @@ use crate::{
     rom,
 };
 
-pub fn dump() -> impl FnMut(Instruction) -> () {
+pub fn dump() -> impl FnMut(Instruction) {
     |instruction| log::trace!(target: "display_list::dump", "  {:?}", instruction)
 }
 
-#[derive(Copy, Clone, FromPrimitive, Debug, PartialEq)]
-#[allow(non_camel_case_types)]
-pub enum Opcode {
+/// Writes a listing of the display list at `addr` to `w`, one instruction per
+/// line with its address and raw value. Instructions of called lists follow
+/// their call, indented by the depth of the call.
+pub fn disassemble<W: io::Write>(
+    reader: &rom::Reader,
+    addr: RawVirtAddr,
+    max_depth: usize,
+    w: &mut W,
+) -> Result<()> {
+    let mut stream = InstructionStream::new(reader, addr, max_depth)?;
+    loop {
+        let depth = stream.depth();
+        let Some(instruction) = stream.next() else {
+            break;
+        };
+        let instruction = instruction?;
+        let addr = stream.last_addr().unwrap_or_default();
+        writeln!(
+            w,
+            "{}: {:016X} {:indent$}{}",
+            addr,
+            instruction.0,
+            "",
+            instruction,
+            indent = depth.saturating_sub(1) * 2
+        )?;
+    }
+    Ok(())
+}
+
+/// Counts how often each opcode is run by the display list at `addr`,
+/// including the lists it calls, each time they are called
+pub fn opcode_histogram(
+    reader: &rom::Reader,
+    addr: RawVirtAddr,
+    max_depth: usize,
+) -> Result<BTreeMap<Opcode, usize>> {
+    let mut histogram = BTreeMap::new();
+    for instruction in InstructionStream::new(reader, addr, max_depth)? {
+        *histogram.entry(instruction?.opcode()).or_default() += 1;
+    }
+    Ok(histogram)
+}
+
+/// Returns the length in bytes of the display list at the start of `data`,
+/// provided it consists only of known instructions terminated by `ENDDL` or
+/// by a branch to another list
+pub fn display_list_len(data: &[u8]) -> Option<usize> {
+    for (index, chunk) in data.chunks_exact(8).enumerate() {
+        match Opcode::from_byte(chunk[0]) {
+            Opcode::Unknown(_) => return None,
+            Opcode::ENDDL => return Some((index + 1) * 8),
+            Opcode::DL if chunk[1] == Dl::NOPUSH => return Some((index + 1) * 8),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Declares `Opcode` with a variant for each of the given command bytes, and
+/// the conversions between them
+macro_rules! opcodes {
+    ($($(#[$meta:meta])* $name:ident = $byte:literal,)*) => {
+        #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        pub enum Opcode {
+            $($(#[$meta])* $name,)*
+            /// A command byte that isn't modelled
+            Unknown(u8),
+        }
+        impl Opcode {
+            pub fn from_byte(byte: u8) -> Self {
+                match byte {
+                    $($byte => Self::$name,)*
+                    byte => Self::Unknown(byte),
+                }
+            }
+
+            pub fn byte(&self) -> u8 {
+                match self {
+                    $(Self::$name => $byte,)*
+                    Self::Unknown(byte) => *byte,
+                }
+            }
+        }
+        impl Debug for Opcode {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $(Self::$name => f.write_str(stringify!($name)),)*
+                    Self::Unknown(byte) => write!(f, "Unknown({:#04X})", byte),
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    /// No-op, also used as padding between commands
+    SPNOOP = 0x00,
     VTX = 0x01,
+    /// Ends the list when the given vertices are off screen. Without a
+    /// camera nothing is culled, so it's a no-op.
+    CULLDL = 0x03,
     TRI1 = 0x05,
     TRI2 = 0x06,
     TEXTURE = 0xD7,
+    POPMTX = 0xD8,
     GEOMETRYMODE = 0xD9,
+    MTX = 0xDA,
+    DL = 0xDE,
     ENDDL = 0xDF,
     SETOTHERMODE_L = 0xE2,
     SETOTHERMODE_H = 0xE3,
     RDPLOADSYNC = 0xE6,
     RDPPIPESYNC = 0xE7,
     RDPTILESYNC = 0xE8,
+    SETSCISSOR = 0xED,
+    SETPRIMDEPTH = 0xEE,
     LOADTLUT = 0xF0,
     SETTILESIZE = 0xF2,
     LOADBLOCK = 0xF3,
@@ -34,22 +141,17 @@ pub enum Opcode {
     SETPRIMCOLOR = 0xFA,
     SETCOMBINE = 0xFC,
     SETTIMG = 0xFD,
+    SETZIMG = 0xFE,
 }
 
 pub struct Instruction(u64);
 impl Instruction {
     pub fn new(data: u64) -> Self {
-        let instruction = Self(data);
-        let _ = instruction.opcode();
-        instruction
+        Self(data)
     }
 
     pub fn opcode(&self) -> Opcode {
-        let opcode = self.0 >> 56;
-        match Opcode::from_u64(opcode) {
-            Some(opcode) => opcode,
-            _ => panic!("Unknown opcode: {:#04X}", opcode),
-        }
+        Opcode::from_byte((self.0 >> 56) as u8)
     }
 }
 impl Display for Instruction {
@@ -60,8 +162,22 @@ impl Display for Instruction {
 
         match opcode {
             Opcode::VTX => write!(f, " {:?}", Vtx::new(self))?,
+            Opcode::DL => write!(f, " {:?}", Dl::new(self))?,
+            Opcode::MTX => write!(f, " {:?}", Mtx::new(self))?,
+            Opcode::TEXTURE => write!(f, " {:?}", Texture::new(self))?,
+            Opcode::GEOMETRYMODE => write!(f, " {:?}", GeometryMode::new(self))?,
+            Opcode::SETOTHERMODE_L => write!(f, " {:?}", SetOtherModeL::new(self))?,
+            Opcode::SETTILESIZE => write!(f, " {:?}", SetTileSize::new(self))?,
+            Opcode::SETTIMG => write!(f, " {:?}", SetTimg::new(self))?,
+            Opcode::SETTILE => write!(f, " {:?}", SetTile::new(self))?,
+            Opcode::LOADTLUT => write!(f, " {:?}", LoadTlut::new(self))?,
+            Opcode::LOADBLOCK => write!(f, " {:?}", LoadBlock::new(self))?,
+            Opcode::POPMTX => write!(f, " {:?}", PopMtx::new(self))?,
             Opcode::TRI1 => write!(f, " {:?}", Tri1::new(self))?,
             Opcode::TRI2 => write!(f, " {:?}", Tri2::new(self))?,
+            Opcode::SETSCISSOR => write!(f, " {:?}", SetScissor::new(self))?,
+            Opcode::SETPRIMDEPTH => write!(f, " {:?}", SetPrimDepth::new(self))?,
+            Opcode::SETPRIMCOLOR => write!(f, " {:?}", SetPrimColor::new(self))?,
             _ => (),
         }
 
@@ -74,29 +190,133 @@ impl Debug for Instruction {
     }
 }
 
+/// Iterates over the instructions of a display list, following calls and
+/// branches into other display lists. Calls nested deeper than `max_depth`
+/// end the iteration with an error, guarding against lists that (indirectly)
+/// call themselves.
 #[derive(Clone)]
-pub struct InstructionStream<'a>(&'a [u8]);
+pub struct InstructionStream<'a> {
+    reader: &'a rom::Reader,
+    max_depth: usize,
+
+    /// The remaining instructions of the current list and their address,
+    /// preceded by those of every list that called into it
+    stack: Vec<(RawVirtAddr, &'a [u8])>,
+
+    /// How many bytes of the starting list were read, until it ended or
+    /// branched elsewhere
+    offset: usize,
+    branched: bool,
+
+    /// The address of the instruction yielded last
+    last_addr: Option<RawVirtAddr>,
+}
 impl<'a> InstructionStream<'a> {
-    pub fn new(data: &'a [u8]) -> Self {
-        Self(data)
+    pub fn new(reader: &'a rom::Reader, addr: RawVirtAddr, max_depth: usize) -> Result<Self> {
+        let data = reader
+            .display_list_from(addr)
+            .with_context(|| format!("Could not read display list at {}", addr))?;
+
+        Ok(Self {
+            reader,
+            max_depth,
+            stack: vec![(addr, data)],
+            offset: 0,
+            branched: false,
+            last_addr: None,
+        })
+    }
+
+    /// The address of the instruction yielded next
+    pub fn next_addr(&self) -> Option<RawVirtAddr> {
+        self.stack
+            .iter()
+            .rev()
+            .find(|(_, data)| data.len() >= 8)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// The address of the instruction yielded last
+    pub fn last_addr(&self) -> Option<RawVirtAddr> {
+        self.last_addr
+    }
+
+    /// The offset into the starting list where reading it stopped, or is
+    /// to continue once the lists it calls return
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The number of lists being executed, 1 for the top-level list
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn follow(&mut self, dl: &Dl) -> Result<()> {
+        let data = match self.reader.display_list_from(dl.addr()) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Not following display list {}: {:#}", dl.addr(), e);
+                return Ok(());
+            }
+        };
+
+        if !dl.push() {
+            self.branched |= self.stack.len() == 1;
+            self.stack.pop();
+        } else if self.stack.len() >= self.max_depth {
+            self.stack.clear();
+            bail!(
+                "Display list {} is nested deeper than the maximum depth of {}",
+                dl.addr(),
+                self.max_depth
+            );
+        }
+        self.stack.push((dl.addr(), data));
+
+        Ok(())
     }
 }
 impl Iterator for InstructionStream<'_> {
-    type Item = Instruction;
+    type Item = Result<Instruction>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0.is_empty() {
-            return None;
-        }
+        loop {
+            let (addr, data) = self.stack.last_mut()?;
+            let Some((lv, rest)) = LayoutVerified::<_, U64<BigEndian>>::new_from_prefix(*data)
+            else {
+                self.stack.pop();
+                continue;
+            };
+            *data = rest;
+            let instruction_addr = *addr;
+            *addr = *addr + 8;
+            self.last_addr = Some(instruction_addr);
+            if self.stack.len() == 1 && !self.branched {
+                self.offset += 8;
+            }
 
-        let (lv, rest) = LayoutVerified::<_, U64<BigEndian>>::new_from_prefix(self.0)?;
-        let instruction = Instruction::new(lv.read().get());
-        self.0 = if instruction.opcode() == Opcode::ENDDL {
-            &[]
-        } else {
-            rest
-        };
-        Some(instruction)
+            let instruction = Instruction::new(lv.read().get());
+            match instruction.opcode() {
+                // Returns to the list that called this one, which ends the
+                // stream only once the top-level list itself ends
+                Opcode::ENDDL => {
+                    self.stack.pop();
+                }
+                Opcode::DL => {
+                    if let Err(e) = self.follow(&Dl::new(&instruction)) {
+                        return Some(Err(e));
+                    }
+                }
+                // Yielded like any other command, so that interpreters can
+                // pass over it as an 8 byte no-op
+                Opcode::Unknown(byte) => {
+                    log::debug!("Unknown opcode {:#04X} at {}", byte, instruction_addr);
+                }
+                _ => (),
+            }
+            return Some(Ok(instruction));
+        }
     }
 }
 
@@ -116,13 +336,479 @@ impl Vtx {
     pub fn aa(&self) -> u32 {
         ((self.0 & 0x000000FF00000000u64) >> 32) as _
     }
+
+    /// The number of vertices loaded
+    pub fn count(&self) -> u32 {
+        self.nn()
+    }
+
+    /// The vertex cache slot the first vertex is loaded into. `aa` holds the
+    /// slot just past the last vertex, doubled.
+    pub fn dest_index(&self) -> u32 {
+        (self.aa() / 2).saturating_sub(self.nn())
+    }
 }
 impl Debug for Vtx {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "addr:{} nn:{} aa:{}", self.addr(), self.nn(), self.aa())
+        write!(
+            f,
+            "addr:{} count:{} dest_index:{}",
+            self.addr(),
+            self.count(),
+            self.dest_index()
+        )
+    }
+}
+
+pub struct Dl(u64);
+impl Dl {
+    /// Parameter of a branch, which doesn't return to the calling list
+    const NOPUSH: u8 = 0x01;
+
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// Whether the list is called, returning to the current list at its end
+    pub fn push(&self) -> bool {
+        ((self.0 & 0x00FF000000000000u64) >> 48) as u8 != Self::NOPUSH
+    }
+
+    pub fn addr(&self) -> RawVirtAddr {
+        RawVirtAddr::new(self.0 as _)
+    }
+}
+impl Debug for Dl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "addr:{} push:{}", self.addr(), self.push())
+    }
+}
+
+pub struct Texture(u64);
+impl Texture {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The tile that primitives are drawn with
+    pub fn tile(&self) -> u32 {
+        ((self.0 & 0x0000070000000000u64) >> 40) as _
+    }
+
+    pub fn on(&self) -> bool {
+        self.0 & 0x000000FE00000000u64 != 0
+    }
+}
+impl Debug for Texture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tile:{} on:{}", self.tile(), self.on())
+    }
+}
+
+pub struct GeometryMode(u64);
+impl GeometryMode {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The flags that are kept, all others are cleared
+    pub fn keep(&self) -> GeometryFlags {
+        GeometryFlags(((self.0 & 0x00FFFFFF00000000u64) >> 32) as u32 | 0xFF000000)
+    }
+
+    /// The flags that are set after clearing
+    pub fn set(&self) -> GeometryFlags {
+        GeometryFlags(self.0 as u32)
+    }
+
+    pub fn apply(&self, flags: GeometryFlags) -> GeometryFlags {
+        GeometryFlags((flags.0 & self.keep().0) | self.set().0)
+    }
+}
+impl Debug for GeometryMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "keep:{:?} set:{:?}", self.keep(), self.set())
+    }
+}
+
+/// The `G_*` geometry mode flags of F3DEX2
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct GeometryFlags(pub u32);
+impl GeometryFlags {
+    pub const ZBUFFER: u32 = 0x00000001;
+    /// Vertices are shaded by their color or lighting, instead of flat white
+    pub const SHADE: u32 = 0x00000004;
+    pub const CULL_FRONT: u32 = 0x00000200;
+    pub const CULL_BACK: u32 = 0x00000400;
+    /// Vertices carry normals that are lit, rather than colors
+    pub const LIGHTING: u32 = 0x00020000;
+    pub const SHADING_SMOOTH: u32 = 0x00200000;
+
+    pub fn contains(&self, flags: u32) -> bool {
+        self.0 & flags == flags
+    }
+}
+impl Default for GeometryFlags {
+    /// The mode the game sets up before drawing actors
+    fn default() -> Self {
+        Self(Self::ZBUFFER | Self::SHADE | Self::CULL_BACK | Self::LIGHTING | Self::SHADING_SMOOTH)
+    }
+}
+impl Debug for GeometryFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#08X}", self.0)
+    }
+}
+
+pub struct SetOtherModeL(u64);
+impl SetOtherModeL {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The number of bits that are set
+    pub fn length(&self) -> u32 {
+        ((self.0 & 0x000000FF00000000u64) >> 32) as u32 + 1
+    }
+
+    /// The lowest of the bits that are set. The command holds it as
+    /// `32 - shift - length`.
+    pub fn shift(&self) -> u32 {
+        let encoded = ((self.0 & 0x0000FF0000000000u64) >> 40) as u32;
+        32u32.saturating_sub(encoded + self.length())
+    }
+
+    pub fn data(&self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn apply(&self, mode: OtherModeL) -> OtherModeL {
+        let mask = ((1u64 << self.length().min(32)) - 1) << self.shift();
+        let mask = mask as u32;
+        OtherModeL((mode.0 & !mask) | (self.data() & mask))
+    }
+}
+impl Debug for SetOtherModeL {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "shift:{} length:{} data:{:?}",
+            self.shift(),
+            self.length(),
+            OtherModeL(self.data())
+        )
+    }
+}
+
+/// The low word of the RDP other mode, holding the alpha compare mode and
+/// the render mode
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct OtherModeL(pub u32);
+impl OtherModeL {
+    /// The alpha compare mode of the two lowest bits that discards pixels
+    /// below an alpha threshold
+    pub const ALPHA_COMPARE_THRESHOLD: u32 = 0x00000001;
+    /// Coverage is multiplied by alpha and alpha taken from coverage, which
+    /// cuts out texels by their alpha
+    pub const CVG_X_ALPHA: u32 = 0x00001000;
+    pub const ALPHA_CVG_SEL: u32 = 0x00002000;
+    /// Blending applies to every pixel, not only to the edges of triangles
+    pub const FORCE_BL: u32 = 0x00004000;
+
+    /// The `G_BL_CLR_MEM` and `G_BL_1MA` blender inputs
+    const BLEND_MEMORY: u32 = 1;
+    const BLEND_ONE_MINUS_ALPHA: u32 = 0;
+
+    pub fn contains(&self, flags: u32) -> bool {
+        self.0 & flags == flags
+    }
+
+    /// Whether either cycle of the blender mixes the pixel with the
+    /// framebuffer by the pixel's alpha, as `G_RM_*_XLU_*` render modes do
+    pub fn is_translucent(&self) -> bool {
+        self.contains(Self::FORCE_BL)
+            && [0, 2].iter().any(|cycle_shift| {
+                let m = (self.0 >> (22 - cycle_shift)) & 0x3;
+                let b = (self.0 >> (18 - cycle_shift)) & 0x3;
+                m == Self::BLEND_MEMORY && b == Self::BLEND_ONE_MINUS_ALPHA
+            })
+    }
+
+    /// Whether pixels are cut out by their alpha, by alpha compare or by
+    /// coverage as `G_RM_*_TEX_EDGE` render modes do
+    pub fn is_alpha_tested(&self) -> bool {
+        self.0 & 0x3 == Self::ALPHA_COMPARE_THRESHOLD
+            || self.contains(Self::CVG_X_ALPHA | Self::ALPHA_CVG_SEL)
+    }
+}
+impl Debug for OtherModeL {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010X}", self.0)
+    }
+}
+
+pub struct SetTileSize(u64);
+impl SetTileSize {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    pub fn tile(&self) -> u32 {
+        ((self.0 & 0x0000000007000000u64) >> 24) as _
+    }
+
+    /// The upper left texel in 10.2 fixed point
+    pub fn ul(&self) -> [u32; 2] {
+        [
+            ((self.0 & 0x00FFF00000000000u64) >> 44) as _,
+            ((self.0 & 0x00000FFF00000000u64) >> 32) as _,
+        ]
+    }
+
+    /// The lower right texel in 10.2 fixed point
+    pub fn lr(&self) -> [u32; 2] {
+        [
+            ((self.0 & 0x0000000000FFF000u64) >> 12) as _,
+            (self.0 & 0x0000000000000FFFu64) as _,
+        ]
+    }
+
+    /// The width and height of the tile in texels
+    pub fn size(&self) -> [f32; 2] {
+        let [uls, ult] = self.ul();
+        let [lrs, lrt] = self.lr();
+        [
+            lrs.saturating_sub(uls) as f32 / 4.0 + 1.0,
+            lrt.saturating_sub(ult) as f32 / 4.0 + 1.0,
+        ]
+    }
+}
+impl Debug for SetTileSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [width, height] = self.size();
+        write!(f, "tile:{} size:{}x{}", self.tile(), width, height)
+    }
+}
+
+pub struct SetTimg(u64);
+impl SetTimg {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    pub fn format(&self) -> u32 {
+        ((self.0 & 0x00E0000000000000u64) >> 53) as _
+    }
+
+    pub fn siz(&self) -> u32 {
+        ((self.0 & 0x0018000000000000u64) >> 51) as _
+    }
+
+    /// The width of the image in texels
+    pub fn width(&self) -> u32 {
+        ((self.0 & 0x00000FFF00000000u64) >> 32) as u32 + 1
+    }
+
+    /// The image that following loads copy into TMEM
+    pub fn addr(&self) -> RawVirtAddr {
+        RawVirtAddr::new(self.0 as u32)
+    }
+}
+impl Debug for SetTimg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "format:{} siz:{} width:{} addr:{}",
+            self.format(),
+            self.siz(),
+            self.width(),
+            self.addr()
+        )
     }
 }
 
+pub struct SetTile(u64);
+impl SetTile {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    pub fn format(&self) -> u32 {
+        ((self.0 & 0x00E0000000000000u64) >> 53) as _
+    }
+
+    pub fn siz(&self) -> u32 {
+        ((self.0 & 0x0018000000000000u64) >> 51) as _
+    }
+
+    /// The size of a row of the tile in TMEM, in 64 bit words
+    pub fn line(&self) -> u32 {
+        ((self.0 & 0x0003FE0000000000u64) >> 41) as _
+    }
+
+    /// Where in TMEM the tile starts, in 64 bit words
+    pub fn tmem(&self) -> u32 {
+        ((self.0 & 0x000001FF00000000u64) >> 32) as _
+    }
+
+    pub fn tile(&self) -> u32 {
+        ((self.0 & 0x0000000007000000u64) >> 24) as _
+    }
+
+    /// The palette of 4 bit color indexed tiles
+    pub fn palette(&self) -> u32 {
+        ((self.0 & 0x0000000000F00000u64) >> 20) as _
+    }
+}
+impl Debug for SetTile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tile:{} format:{} siz:{} line:{} tmem:{} palette:{}",
+            self.tile(),
+            self.format(),
+            self.siz(),
+            self.line(),
+            self.tmem(),
+            self.palette()
+        )
+    }
+}
+
+pub struct LoadTlut(u64);
+impl LoadTlut {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The tile whose TMEM address the palette is loaded to
+    pub fn tile(&self) -> u32 {
+        ((self.0 & 0x0000000007000000u64) >> 24) as _
+    }
+
+    /// How many palette entries are loaded
+    pub fn count(&self) -> u32 {
+        ((self.0 & 0x0000000000FFC000u64) >> 14) as u32 + 1
+    }
+}
+impl Debug for LoadTlut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tile:{} count:{}", self.tile(), self.count())
+    }
+}
+
+pub struct LoadBlock(u64);
+impl LoadBlock {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The tile whose TMEM address the texels are loaded to
+    pub fn tile(&self) -> u32 {
+        ((self.0 & 0x0000000007000000u64) >> 24) as _
+    }
+
+    /// The S and T coordinates of the first texel loaded
+    pub fn uls(&self) -> u32 {
+        ((self.0 & 0x00FFF00000000000u64) >> 44) as _
+    }
+
+    pub fn ult(&self) -> u32 {
+        ((self.0 & 0x00000FFF00000000u64) >> 32) as _
+    }
+
+    /// How many texels are loaded
+    pub fn texels(&self) -> u32 {
+        ((self.0 & 0x0000000000FFF000u64) >> 12) as u32 + 1
+    }
+
+    /// The increment of T per 64 bit word, in 1.11 fixed point
+    pub fn dxt(&self) -> u32 {
+        (self.0 & 0x0000000000000FFFu64) as _
+    }
+}
+impl Debug for LoadBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tile:{} uls:{} ult:{} texels:{} dxt:{:#05X}",
+            self.tile(),
+            self.uls(),
+            self.ult(),
+            self.texels(),
+            self.dxt()
+        )
+    }
+}
+
+pub struct Mtx(u64);
+impl Mtx {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The parameters are stored with the push flag inverted
+    fn params(&self) -> u8 {
+        ((self.0 & 0x000000FF00000000u64) >> 32) as u8 ^ 0x01
+    }
+
+    /// Whether the current matrix is pushed before being replaced
+    pub fn push(&self) -> bool {
+        self.params() & 0x01 != 0
+    }
+
+    /// Whether the matrix replaces the current one rather than multiplying it
+    pub fn load(&self) -> bool {
+        self.params() & 0x02 != 0
+    }
+
+    pub fn projection(&self) -> bool {
+        self.params() & 0x04 != 0
+    }
+
+    pub fn addr(&self) -> VirtAddr<rom::Mtx> {
+        RawVirtAddr::new(self.0 as _).into()
+    }
+}
+impl Debug for Mtx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "addr:{} push:{} load:{} projection:{}",
+            self.addr(),
+            self.push(),
+            self.load(),
+            self.projection()
+        )
+    }
+}
+
+pub struct PopMtx(u64);
+impl PopMtx {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The number of matrices to pop off the modelview stack
+    pub fn count(&self) -> u32 {
+        (self.0 as u32) / 64
+    }
+}
+impl Debug for PopMtx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "count:{}", self.count())
+    }
+}
+
+/// The vertex cache slot of a corner of `Tri1` and `Tri2`. F3DEX2 stores the
+/// slots doubled, as `gsSP1Triangle(0, 1, 2, 0)` is `0x0500020400000000`;
+/// the doubled values are byte offsets into the cache as seen by the
+/// microcode, not slots.
+pub fn tri_index(byte: u8) -> u32 {
+    byte as u32 / 2
+}
+
+/// Draws a triangle from the vertex cache
 pub struct Tri1(u64);
 impl Tri1 {
     pub fn new(instruction: &Instruction) -> Self {
@@ -130,13 +816,13 @@ impl Tri1 {
     }
 
     pub fn aa(&self) -> u32 {
-        (((self.0 & 0x00FF000000000000u64) >> 48) / 2) as _
+        tri_index((self.0 >> 48) as u8)
     }
     pub fn bb(&self) -> u32 {
-        (((self.0 & 0x0000FF0000000000u64) >> 40) / 2) as _
+        tri_index((self.0 >> 40) as u8)
     }
     pub fn cc(&self) -> u32 {
-        (((self.0 & 0x000000FF00000000u64) >> 32) / 2) as _
+        tri_index((self.0 >> 32) as u8)
     }
 }
 impl Debug for Tri1 {
@@ -145,29 +831,30 @@ impl Debug for Tri1 {
     }
 }
 
+/// Draws two triangles from the vertex cache, with slots stored like `Tri1`
 pub struct Tri2(u64);
 impl Tri2 {
     pub fn new(instruction: &Instruction) -> Self {
         Self(instruction.0)
     }
     pub fn aa(&self) -> u32 {
-        (((self.0 & 0x00FF000000000000u64) >> 48) / 2) as _
+        tri_index((self.0 >> 48) as u8)
     }
     pub fn bb(&self) -> u32 {
-        (((self.0 & 0x0000FF0000000000u64) >> 40) / 2) as _
+        tri_index((self.0 >> 40) as u8)
     }
     pub fn cc(&self) -> u32 {
-        (((self.0 & 0x000000FF00000000u64) >> 32) / 2) as _
+        tri_index((self.0 >> 32) as u8)
     }
 
     pub fn dd(&self) -> u32 {
-        (((self.0 & 0x0000000000FF0000u64) >> 16) / 2) as _
+        tri_index((self.0 >> 16) as u8)
     }
     pub fn ee(&self) -> u32 {
-        (((self.0 & 0x000000000000FF00u64) >> 8) / 2) as _
+        tri_index((self.0 >> 8) as u8)
     }
     pub fn ff(&self) -> u32 {
-        (((self.0 & 0x00000000000000FFu64) >> 0) / 2) as _
+        tri_index(self.0 as u8)
     }
 }
 
@@ -185,3 +872,225 @@ impl Debug for Tri2 {
         )
     }
 }
+
+pub struct SetScissor(u64);
+impl SetScissor {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    pub fn mode(&self) -> u32 {
+        ((self.0 & 0x00000000FF000000u64) >> 24) as _
+    }
+
+    /// The upper left corner in 10.2 fixed point
+    pub fn ul(&self) -> [u32; 2] {
+        [
+            ((self.0 & 0x00FFF00000000000u64) >> 44) as _,
+            ((self.0 & 0x00000FFF00000000u64) >> 32) as _,
+        ]
+    }
+
+    /// The lower right corner in 10.2 fixed point
+    pub fn lr(&self) -> [u32; 2] {
+        [
+            ((self.0 & 0x0000000000FFF000u64) >> 12) as _,
+            (self.0 & 0x0000000000000FFFu64) as _,
+        ]
+    }
+}
+impl Debug for SetScissor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [ulx, uly] = self.ul();
+        let [lrx, lry] = self.lr();
+        write!(
+            f,
+            "mode:{} ul:({}, {}) lr:({}, {})",
+            self.mode(),
+            ulx as f32 / 4.0,
+            uly as f32 / 4.0,
+            lrx as f32 / 4.0,
+            lry as f32 / 4.0
+        )
+    }
+}
+
+pub struct SetPrimDepth(u64);
+impl SetPrimDepth {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    pub fn z(&self) -> i16 {
+        ((self.0 & 0x00000000FFFF0000u64) >> 16) as _
+    }
+    pub fn dz(&self) -> i16 {
+        (self.0 & 0x000000000000FFFFu64) as _
+    }
+}
+impl Debug for SetPrimDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "z:{} dz:{}", self.z(), self.dz())
+    }
+}
+
+pub struct SetPrimColor(u64);
+impl SetPrimColor {
+    pub fn new(instruction: &Instruction) -> Self {
+        Self(instruction.0)
+    }
+
+    /// The RGBA color, in the low word
+    pub fn color(&self) -> [u8; 4] {
+        (self.0 as u32).to_be_bytes()
+    }
+}
+impl Debug for SetPrimColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [r, g, b, a] = self.color();
+        write!(f, "r:{} g:{} b:{} a:{}", r, g, b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{self, object_addr, SegmentBuilder};
+
+    #[test]
+    fn decodes_the_scissor_rectangle() {
+        // gsDPSetScissor(G_SC_NON_INTERLACE, 0, 0, 320, 240)
+        let scissor = SetScissor::new(&Instruction::new(0xED000000_005003C0));
+        assert_eq!(scissor.mode(), 0);
+        assert_eq!(scissor.ul(), [0, 0]);
+        assert_eq!(scissor.lr(), [320 << 2, 240 << 2]);
+
+        let depth = SetPrimDepth::new(&Instruction::new(0xEE000000_0010FFFF));
+        assert_eq!((depth.z(), depth.dz()), (0x10, -1));
+    }
+
+    /// Every instruction of the stream from `addr`, as its raw command
+    fn trace(reader: &rom::Reader, addr: usize) -> Vec<u64> {
+        InstructionStream::new(reader, RawVirtAddr::new(object_addr(addr)), 32)
+            .unwrap()
+            .map(|instruction| instruction.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn follows_calls_and_branches() {
+        let reader = SegmentBuilder::new()
+            .commands(
+                0x00,
+                &[
+                    test_rom::g_dl(object_addr(0x40)),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_branch_list(object_addr(0x80)),
+                    test_rom::g_tri1(9, 9, 9),
+                    test_rom::g_enddl(),
+                ],
+            )
+            .commands(0x40, &[test_rom::g_tri1(3, 4, 5), test_rom::g_enddl()])
+            .commands(0x80, &[test_rom::g_tri1(6, 7, 8), test_rom::g_enddl()])
+            .into_object_reader();
+
+        assert_eq!(
+            trace(&reader, 0),
+            [
+                test_rom::g_dl(object_addr(0x40)),
+                test_rom::g_tri1(3, 4, 5),
+                test_rom::g_enddl(),
+                test_rom::g_tri1(0, 1, 2),
+                test_rom::g_branch_list(object_addr(0x80)),
+                test_rom::g_tri1(6, 7, 8),
+                test_rom::g_enddl(),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_the_maximum_depth() {
+        // Five lists, each but the last calling the next
+        let reader = (0..5)
+            .fold(SegmentBuilder::new(), |builder, i| {
+                let next = test_rom::g_dl(object_addr((i + 1) * 0x10));
+                let commands = if i < 4 {
+                    [next, test_rom::g_enddl()]
+                } else {
+                    [test_rom::g_enddl(), 0]
+                };
+                builder.commands(i * 0x10, &commands)
+            })
+            .into_object_reader();
+        let run = |max_depth| {
+            InstructionStream::new(&reader, RawVirtAddr::new(object_addr(0)), max_depth)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+        };
+
+        assert_eq!(run(5).unwrap().len(), 9);
+        let error = run(4).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Display list 0x06000040 is nested deeper than the maximum depth of 4"
+        );
+    }
+
+    #[test]
+    fn decodes_the_vertex_cache_slots_of_vtx() {
+        // gsSPVertex(0x06000100, 4, 2)
+        let vtx = Vtx::new(&Instruction::new(0x0100400C_06000100));
+        assert_eq!(vtx.count(), 4);
+        assert_eq!(vtx.dest_index(), 2);
+        assert_eq!(RawVirtAddr::from(vtx.addr()), RawVirtAddr::new(0x06000100));
+
+        // gsSPVertex(0x06000000, 32, 0), filling the whole cache
+        let vtx = Vtx::new(&Instruction::new(0x01020040_06000000));
+        assert_eq!((vtx.count(), vtx.dest_index()), (32, 0));
+    }
+
+    #[test]
+    fn decodes_the_fields_of_tiles() {
+        // gsDPSetTile(G_IM_FMT_CI, G_IM_SIZ_4b, 2, 0x100, 7, 3, ...)
+        let tile = SetTile::new(&Instruction::new(0xF5400500_07300000));
+        assert_eq!((tile.format(), tile.siz()), (2, 0));
+        assert_eq!((tile.line(), tile.tmem()), (2, 0x100));
+        assert_eq!((tile.tile(), tile.palette()), (7, 3));
+
+        // gsDPSetTile(G_IM_FMT_RGBA, G_IM_SIZ_16b, 8, 0, G_TX_RENDERTILE, 0, ...)
+        let tile = SetTile::new(&Instruction::new(0xF5101000_00000000));
+        assert_eq!((tile.format(), tile.siz(), tile.line()), (0, 2, 8));
+
+        // gsDPSetTileSize(1, 4 << 2, 8 << 2, 31 << 2, 63 << 2)
+        let size = SetTileSize::new(&Instruction::new(0xF2010020_0107C0FC));
+        assert_eq!(size.tile(), 1);
+        assert_eq!(size.ul(), [0x10, 0x20]);
+        assert_eq!(size.lr(), [0x7C, 0xFC]);
+        assert_eq!(size.size(), [28.0, 56.0]);
+
+        let size = SetTileSize::new(&Instruction::new(test_rom::g_settilesize(0, 32, 64)));
+        assert_eq!(size.size(), [32.0, 64.0]);
+    }
+
+    #[test]
+    fn halves_the_vertex_slots_of_triangles() {
+        assert_eq!(tri_index(0x3E), 31);
+
+        // gsSP1Triangle(0, 1, 2, 0)
+        let tri = Tri1::new(&Instruction::new(0x05000204_00000000));
+        assert_eq!([tri.aa(), tri.bb(), tri.cc()], [0, 1, 2]);
+
+        // gsSP1Triangle(3, 31, 7, 0)
+        let tri = Tri1::new(&Instruction::new(0x05063E0E_00000000));
+        assert_eq!([tri.aa(), tri.bb(), tri.cc()], [3, 31, 7]);
+
+        // gsSP2Triangles(0, 1, 2, 0, 4, 5, 6, 0)
+        let tri = Tri2::new(&Instruction::new(0x06000204_00080A0C));
+        assert_eq!([tri.aa(), tri.bb(), tri.cc()], [0, 1, 2]);
+        assert_eq!([tri.dd(), tri.ee(), tri.ff()], [4, 5, 6]);
+
+        let tri = Tri2::new(&Instruction::new(test_rom::g_tri2([9, 8, 7], [30, 20, 10])));
+        assert_eq!([tri.aa(), tri.bb(), tri.cc()], [9, 8, 7]);
+        assert_eq!([tri.dd(), tri.ee(), tri.ff()], [30, 20, 10]);
+    }
+}
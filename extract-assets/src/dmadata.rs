@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use zerocopy::LayoutVerified;
+
+use crate::rom::DmaEntry;
+
+/// The first entry (makerom) and the start of the second (boot) are the same
+/// in every version, which makes them usable as a signature for the table
+const SIGNATURE: [u8; 0x14] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x10, 0x60,
+];
+
+/// Finds the offset of dmadata in a ROM
+pub fn find(rom: &[u8]) -> Option<usize> {
+    (0..rom.len())
+        .step_by(0x10)
+        .find(|offset| rom[*offset..].starts_with(&SIGNATURE))
+}
+
+/// Reads the entries of dmadata, up to the terminating empty entry
+pub fn read(rom: &[u8]) -> Result<Vec<DmaEntry>> {
+    let offset = find(rom).context("Could not find dmadata in ROM")?;
+
+    let entries = rom[offset..]
+        .chunks_exact(std::mem::size_of::<DmaEntry>())
+        .flat_map(LayoutVerified::<_, DmaEntry>::new)
+        .map(|lv| lv.read())
+        .take_while(|entry| entry.vrom_end.get() != 0)
+        .collect::<Vec<_>>();
+
+    log::debug!(
+        "Found {} dmadata entries at {:#010X}",
+        entries.len(),
+        offset
+    );
+
+    Ok(entries)
+}
@@ -0,0 +1,24 @@
+use anyhow::{Context, Result};
+use gltf::json;
+
+/// Converts a value to the `extras` of a glTF object
+pub fn to_extras(value: json::Value) -> Result<json::Extras> {
+    Ok(Some(json::extras::RawValue::from_string(
+        json::serialize::to_string(&value)?,
+    )?))
+}
+
+/// Adds a value to the `extras` of a glTF object under `key`, keeping the
+/// values already there
+pub fn insert(extras: &mut json::Extras, key: &str, value: json::Value) -> Result<()> {
+    let mut object = match extras {
+        Some(raw) => json::deserialize::from_str::<json::Value>(raw.get())?
+            .as_object()
+            .cloned()
+            .context("Extras are not an object")?,
+        None => Default::default(),
+    };
+    object.insert(key.to_owned(), value);
+    *extras = to_extras(json::Value::Object(object))?;
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use glam::{Quat, Vec3};
+use gltf::json;
+use zerocopy::AsBytes;
+
+use crate::builder::{self, GltfBuilder};
+
+const EXTENSION: &str = "EXT_mesh_gpu_instancing";
+
+/// A node drawing its mesh once per instance, with the accessors holding
+/// the transform of each instance
+pub struct InstancedNode {
+    node: u32,
+    translation: u32,
+    rotation: u32,
+    scale: u32,
+}
+
+/// Replaces scene root nodes that share a mesh with one node per mesh, which
+/// instances the mesh at the transform of each of the replaced nodes. Nodes
+/// with children, a skin or a matrix, and nodes targeted by animations or
+/// used as joints, are left as they are.
+///
+/// The instancing extension can't be expressed by `json::Root`, so the
+/// returned nodes have to be passed on to `to_value` when serializing.
+pub fn collapse_shared_meshes(root: &mut json::Root) -> Vec<InstancedNode> {
+    let referenced = root
+        .animations
+        .iter()
+        .flat_map(|animation| animation.channels.iter().map(|c| c.target.node.value()))
+        .chain(
+            root.skins
+                .iter()
+                .flat_map(|skin| skin.joints.iter().chain(&skin.skeleton))
+                .map(|node| node.value()),
+        )
+        .collect::<HashSet<_>>();
+
+    let mut by_mesh = BTreeMap::<usize, Vec<usize>>::new();
+    let scene_roots = root
+        .scenes
+        .iter()
+        .flat_map(|scene| scene.nodes.iter().map(|node| node.value()))
+        .collect::<HashSet<_>>();
+    for index in scene_roots {
+        let node = &root.nodes[index];
+        match node.mesh {
+            Some(mesh)
+                if node.children.is_none()
+                    && node.skin.is_none()
+                    && node.matrix.is_none()
+                    && !referenced.contains(&index) =>
+            {
+                by_mesh.entry(mesh.value()).or_default().push(index)
+            }
+            _ => (),
+        }
+    }
+
+    let mut instanced = Vec::new();
+    let mut removed = HashSet::new();
+    for mut nodes in by_mesh.into_values().filter(|nodes| nodes.len() > 1) {
+        nodes.sort_unstable();
+        log::debug!("Instancing mesh of nodes {:?}", nodes);
+
+        let translations = nodes
+            .iter()
+            .map(|node| root.nodes[*node].translation.unwrap_or_default())
+            .collect::<Vec<_>>();
+        let rotations = nodes
+            .iter()
+            .map(|node| {
+                root.nodes[*node]
+                    .rotation
+                    .map_or(Quat::IDENTITY.to_array(), |rotation| rotation.0)
+            })
+            .collect::<Vec<_>>();
+        let scales = nodes
+            .iter()
+            .map(|node| root.nodes[*node].scale.unwrap_or(Vec3::ONE.to_array()))
+            .collect::<Vec<_>>();
+
+        let node = &mut root.nodes[nodes[0]];
+        node.translation = None;
+        node.rotation = None;
+        node.scale = None;
+
+        instanced.push(InstancedNode {
+            node: nodes[0] as _,
+            translation: push_accessor(root, translations.as_bytes(), json::accessor::Type::Vec3),
+            rotation: push_accessor(root, rotations.as_bytes(), json::accessor::Type::Vec4),
+            scale: push_accessor(root, scales.as_bytes(), json::accessor::Type::Vec3),
+        });
+        removed.extend(nodes.into_iter().skip(1));
+    }
+
+    if instanced.is_empty() {
+        return instanced;
+    }
+
+    let remap = remove_nodes(root, &removed);
+    for instanced_node in &mut instanced {
+        instanced_node.node = remap[&(instanced_node.node as usize)] as _;
+    }
+
+    root.extensions_used.push(String::from(EXTENSION));
+
+    instanced
+}
+
+/// Serializes `root`, adding the instancing extension to the given nodes
+pub fn to_value(root: &json::Root, instanced: &[InstancedNode]) -> Result<json::Value> {
+    let mut value = json::serialize::to_value(root)?;
+
+    for instanced_node in instanced {
+        let attributes = json::Value::from_iter([
+            ("TRANSLATION", instanced_node.translation),
+            ("ROTATION", instanced_node.rotation),
+            ("SCALE", instanced_node.scale),
+        ]);
+        value["nodes"][instanced_node.node as usize]["extensions"] = json::Value::from_iter([(
+            EXTENSION,
+            json::Value::from_iter([("attributes", attributes)]),
+        )]);
+    }
+
+    Ok(value)
+}
+
+fn push_accessor(root: &mut json::Root, bytes: &[u8], type_: json::accessor::Type) -> u32 {
+    let components = match type_ {
+        json::accessor::Type::Vec4 => 4,
+        _ => 3,
+    };
+
+    root.add_accessor(
+        bytes,
+        None,
+        builder::accessor(
+            bytes.len() / (components * 4),
+            json::accessor::ComponentType::F32,
+            type_,
+        ),
+    )
+    .value() as _
+}
+
+/// Removes the given nodes, which must not be referenced by animations or
+/// skins, and updates the node indices everywhere else. Returns the new
+/// index of every kept node.
+fn remove_nodes(root: &mut json::Root, removed: &HashSet<usize>) -> HashMap<usize, usize> {
+    let remap = (0..root.nodes.len())
+        .filter(|index| !removed.contains(index))
+        .enumerate()
+        .map(|(new, old)| (old, new))
+        .collect::<HashMap<_, _>>();
+    let remap_all = |nodes: &mut Vec<json::Index<json::Node>>| {
+        *nodes = nodes
+            .iter()
+            .filter_map(|node| remap.get(&node.value()))
+            .map(|node| json::Index::new(*node as _))
+            .collect();
+    };
+
+    let mut index = 0;
+    root.nodes.retain(|_| {
+        index += 1;
+        !removed.contains(&(index - 1))
+    });
+
+    for scene in &mut root.scenes {
+        remap_all(&mut scene.nodes);
+    }
+    for node in &mut root.nodes {
+        if let Some(children) = node.children.as_mut() {
+            remap_all(children);
+        }
+    }
+    for skin in &mut root.skins {
+        remap_all(&mut skin.joints);
+        skin.skeleton = skin
+            .skeleton
+            .map(|node| json::Index::new(remap[&node.value()] as _));
+    }
+    for channel in root
+        .animations
+        .iter_mut()
+        .flat_map(|animation| animation.channels.iter_mut())
+    {
+        channel.target.node = json::Index::new(remap[&channel.target.node.value()] as _);
+    }
+
+    remap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom;
+
+    #[test]
+    fn instances_a_mesh_shared_by_three_nodes() {
+        let mut root = json::Root::default();
+        root.meshes.push(json::Mesh {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            primitives: Vec::new(),
+            weights: None,
+        });
+        let translations = [[1.0f32, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 3.0]];
+        for translation in translations {
+            root.nodes.push(json::Node {
+                camera: None,
+                children: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+                matrix: None,
+                mesh: Some(json::Index::new(0)),
+                name: None,
+                rotation: None,
+                scale: None,
+                translation: Some(translation),
+                skin: None,
+                weights: None,
+            });
+        }
+        root.scenes.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: (0..3).map(json::Index::new).collect(),
+        });
+
+        let instanced = collapse_shared_meshes(&mut root);
+        assert_eq!(instanced.len(), 1);
+        assert_eq!(root.meshes.len(), 1);
+        assert_eq!(root.nodes.len(), 1);
+        assert_eq!(root.nodes[0].translation, None);
+        assert_eq!(root.scenes[0].nodes, [json::Index::new(0)]);
+        assert_eq!(root.extensions_used, [EXTENSION]);
+
+        let value = to_value(&root, &instanced).unwrap();
+        let attributes = &value["nodes"][0]["extensions"][EXTENSION]["attributes"];
+        let accessor = |name: &str| json::Index::new(attributes[name].as_u64().unwrap() as u32);
+        for name in ["TRANSLATION", "ROTATION", "SCALE"] {
+            assert_eq!(root.accessors[accessor(name).value()].count, 3);
+        }
+        let instance_translations = test_rom::accessor_elements(&root, accessor("TRANSLATION"));
+        assert_eq!(instance_translations.concat(), translations.as_bytes());
+    }
+}
@@ -0,0 +1,45 @@
+//! Extraction of models and animations from Ocarina of Time ROMs to glTF.
+//!
+//! The supported entry points live in [`api`]. The remaining modules are
+//! internals shared with the `extract-assets` binary and change freely.
+
+pub mod api;
+
+#[doc(hidden)]
+pub mod addr;
+#[doc(hidden)]
+pub mod batch;
+#[doc(hidden)]
+pub mod buffer;
+#[doc(hidden)]
+pub mod builder;
+#[doc(hidden)]
+pub mod display_list;
+#[doc(hidden)]
+pub mod dmadata;
+#[doc(hidden)]
+pub mod extras;
+#[doc(hidden)]
+pub mod instancing;
+#[doc(hidden)]
+pub mod math;
+#[doc(hidden)]
+pub mod mesh;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod rom;
+#[doc(hidden)]
+pub mod scene;
+#[doc(hidden)]
+pub mod skeleton;
+#[doc(hidden)]
+pub mod skeleton_animation;
+#[cfg(test)]
+mod test_rom;
+#[doc(hidden)]
+pub mod texture;
+#[doc(hidden)]
+pub mod version;
+#[doc(hidden)]
+pub mod yaz0;
@@ -1,57 +1,757 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
-use gltf::json;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
 
-use crate::addr::RawVirtAddr;
+use extract_assets::{
+    addr::RawVirtAddr,
+    api::{self, Extraction},
+    batch, buffer, display_list, scene, skeleton, version,
+};
 
-mod addr;
-mod display_list;
-mod math;
-mod mesh;
-mod rom;
-mod skeleton;
-mod skeleton_animation;
+/// Virtual ROM address of Epona's object, the default extraction
+const EPONA_OBJECT: u32 = 0x010DB000;
+const EPONA_SKELETON: u32 = 0x06009D74;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The ROM to read. Defaults to the one *.z64, *.v64 or *.n64 file in the
+    /// current directory or its subdirectories.
+    #[arg(long, global = true)]
+    rom: Option<PathBuf>,
+
+    /// The ROM range of the object to load into the object segment, as
+    /// START:END in hex. Defaults to Epona's object.
+    #[arg(long, value_parser = parse_range)]
+    object_range: Option<Range<u32>>,
+
+    /// The ID (in hex) of the object to load into the object segment, looked
+    /// up in the ROM's object table, instead of --object-range. Only versions
+    /// whose object table address is known support it.
+    #[arg(long, value_parser = parse_object_id, conflicts_with = "object_range")]
+    object: Option<u16>,
+
+    /// Address (in hex) of the skeleton to extract. Defaults to Epona's.
+    #[arg(long, value_parser = parse_hex)]
+    skeleton: Option<u32>,
+
+    /// An animation of the skeleton to extract, as NAME=ADDR with the
+    /// address in hex, optionally followed by @FPS to override --fps.
+    /// Defaults to Epona's when no skeleton is given.
+    #[arg(long = "animation", value_parser = parse_animation)]
+    animations: Vec<(String, u32, Option<f32>)>,
+
+    /// One of Link's animations to extract, as NAME=ADDR[@FPS] like
+    /// --animation. Its frames are read from segment 7, into which the link
+    /// animation file has to be loaded, e.g. with --extra-object.
+    #[arg(long = "link-animation", value_parser = parse_animation)]
+    link_animations: Vec<(String, u32, Option<f32>)>,
+
+    /// Extract only the skeleton and its meshes in the rest pose, without
+    /// Epona's animations when no skeleton is given
+    #[arg(long, conflicts_with_all = ["animations", "link_animations"])]
+    no_animations: bool,
+
+    /// The file to write, defaults to out.gltf (or out.glb with --glb)
+    #[arg(long, short, global = true)]
+    output: Option<PathBuf>,
+
+    /// Load a ROM range of the form START:END (in hex) holding a scene file
+    /// followed by its rooms, into the scene segment and the first room into
+    /// the room segment
+    #[arg(long, value_parser = parse_range)]
+    preload: Vec<Range<u32>>,
+
+    /// Load another object into a segment, as SEGMENT=START:END in hex, for
+    /// models spanning several objects
+    #[arg(long = "extra-object", value_parser = parse_object)]
+    extra_objects: Vec<(u8, Range<u32>)>,
+
+    /// Load a file into a segment, as SEGMENT=FILE with the segment in hex,
+    /// or only a range of the file with SEGMENT=FILE@START:END. Loaded after
+    /// the object, so it can replace any segment.
+    #[arg(long = "segment", global = true, value_parser = parse_segment)]
+    segments: Vec<(u8, PathBuf, Option<Range<u32>>)>,
+
+    /// Write the data of every loaded segment to DIR/segment_NN.bin, for
+    /// inspecting it with other tools
+    #[arg(long, global = true, value_name = "DIR")]
+    dump_segments: Option<PathBuf>,
+
+    /// Address (in hex) of another skeleton to extract into the same scene,
+    /// without animations
+    #[arg(long = "extra-skeleton", value_parser = parse_hex)]
+    extra_skeletons: Vec<u32>,
+
+    /// Export faceted meshes, with every triangle carrying its face normal
+    #[arg(long, global = true)]
+    flat_normals: bool,
+
+    /// Write every draw of a display list as its own node and mesh
+    #[arg(long, global = true)]
+    split_draws: bool,
+
+    /// How many display lists deep calls are followed before giving up
+    #[arg(long, global = true, default_value_t = 32)]
+    max_dl_depth: usize,
+
+    /// Keep triangles that don't have three distinct vertices
+    #[arg(long, global = true)]
+    keep_degenerate_triangles: bool,
+
+    /// Write the rest pose transform of every limb node relative to its
+    /// skeleton to the node's extras
+    #[arg(long)]
+    world_matrix_extras: bool,
+
+    /// Merge the limb meshes into one mesh deformed by the limbs through a
+    /// glTF skin, instead of attaching each mesh to its limb node
+    #[arg(long, conflicts_with = "skin_morph_targets")]
+    skin: bool,
+
+    /// Factor every position is scaled by, e.g. 0.01 to shrink the model
+    #[arg(long, global = true, default_value_t = 1.0)]
+    scale: f32,
+
+    /// Reverse the winding of every triangle, for models that come out inside
+    /// out
+    #[arg(long, global = true)]
+    flip_winding: bool,
+
+    /// Factor converting raw vertex S/T coordinates to texels
+    #[arg(long, global = true, default_value_t = 1.0 / 32.0)]
+    texcoord_scale: f32,
+
+    /// Address (in hex) of a table of joint rotation limits, one per limb
+    #[arg(long, value_parser = parse_hex)]
+    joint_limits: Option<u32>,
+
+    /// Write binary glTF (.glb) instead of glTF with embedded buffers
+    #[arg(long, global = true)]
+    glb: bool,
+
+    /// Leave out rotation channels of limbs that do not move
+    #[arg(long)]
+    skip_constant_channels: bool,
+
+    /// Mark the animations as looping (true) or playing once (false) in their
+    /// extras, instead of only looping the ones named idle
+    #[arg(long)]
+    loop_animations: Option<bool>,
+
+    /// Write each animation to a glTF of its own named after it, next to
+    /// --output, holding only the skeleton nodes and the animation
+    #[arg(long)]
+    animation_files: bool,
+
+    /// Sample animated skin limbs in every frame into morph targets
+    #[arg(long)]
+    skin_morph_targets: bool,
+
+    /// Write the values of every joint in every frame as read from the
+    /// animation, before their conversion to quaternions, to the extras of
+    /// the animations, for comparing them with the game's
+    #[arg(long)]
+    euler_extras: bool,
+
+    /// How animations interpolate between frames, linear or step
+    #[arg(long, value_parser = parse_interpolation, default_value = "linear")]
+    interpolation: gltf::json::animation::Interpolation,
+
+    /// Frames per second the animations play back at
+    #[arg(long, default_value_t = 20.0)]
+    fps: f32,
+
+    /// Extract from ROMs that are not a known release, e.g. modified ones
+    #[arg(long, global = true)]
+    allow_unknown_version: bool,
+
+    /// Refuse ROMs whose checksum doesn't match their header, or whose header
+    /// CRCs aren't those of their release, instead of only warning about them
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Log more than warnings and errors, -v for info output, -vv for debug
+    /// output and -vvv for trace output such as instruction dumps. RUST_LOG
+    /// takes precedence when set.
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log nothing, not even errors
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract every object file listed in dmadata, one glTF per object
+    ExtractAll {
+        /// The directory to write the glTF files and summary.txt to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Extract the geometry of a room of a scene
+    ExtractRoom {
+        /// The ROM range of the room file, as START:END in hex
+        #[arg(value_parser = parse_range)]
+        room: Range<u32>,
+
+        /// The ROM range of the scene file the room belongs to, as START:END
+        /// in hex, for rooms drawing with data of their scene
+        #[arg(long, value_parser = parse_range)]
+        scene: Option<Range<u32>>,
+    },
+    /// Write a listing of a display list of the object, to --output or
+    /// standard output
+    Disassemble {
+        /// Address (in hex) of the display list
+        #[arg(value_parser = parse_hex)]
+        addr: u32,
+    },
+    /// Count the opcodes run by a display list of the object and the lists
+    /// it calls
+    Opcodes {
+        /// Address (in hex) of the display list
+        #[arg(value_parser = parse_hex)]
+        addr: u32,
+    },
+}
 
 fn main() -> Result<()> {
-    pretty_env_logger::init();
-
-    let rom_path = get_rom_path()?;
-    let mut rom_file = fs::File::open(rom_path)?;
-
-    let mut reader = rom::Reader::new();
-    reader.read_segment(rom::Segment::Object, &mut rom_file, 0x010DB000..0x010E8F10)?;
-
-    let mut root = gltf::json::Root::default();
-    skeleton::read_into_gltf(
-        &mut root,
-        &reader,
-        RawVirtAddr::new(0x06009D74).into(),
-        &[
-            // gEponaGallopingAnim
-            RawVirtAddr::new(0x06001E2C).into(),
-            // gEponaJumpingAnim
-            RawVirtAddr::new(0x06002470).into(),
-        ],
-    )?;
-
-    root.scenes.push(json::Scene {
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        nodes: vec![json::Index::new(0)],
-    });
-
-    let writer = fs::File::create("epona.gltf")?;
-    gltf::json::serialize::to_writer_pretty(writer, &root)?;
+    let args = Args::parse();
+    init_logger(&args);
+
+    let mesh_options = api::MeshOptions {
+        flat_normals: args.flat_normals,
+        split_draws: args.split_draws,
+        max_dl_depth: args.max_dl_depth,
+        texcoord_scale: args.texcoord_scale,
+        keep_degenerate_triangles: args.keep_degenerate_triangles,
+        world_matrix_extras: args.world_matrix_extras,
+        skin: args.skin,
+        scale: args.scale,
+        flip_winding: args.flip_winding,
+    };
+
+    let animation_options = api::AnimationOptions {
+        frame_duration: 1.0 / args.fps,
+        skip_constant_channels: args.skip_constant_channels,
+        looping: args.loop_animations,
+        skin_morph_targets: args.skin_morph_targets,
+        interpolation: args.interpolation,
+        euler_extras: args.euler_extras,
+        ..Default::default()
+    };
+
+    match args.command {
+        None => extract_object(&args, &mesh_options, &animation_options),
+        Some(Command::ExtractAll { ref out }) => extract_all(&args, out, &mesh_options),
+        Some(Command::ExtractRoom {
+            ref room,
+            ref scene,
+        }) => extract_room(&args, room.clone(), scene.clone(), &mesh_options),
+        Some(Command::Disassemble { addr }) => disassemble(&args, addr),
+        Some(Command::Opcodes { addr }) => print_opcodes(&args, addr),
+    }
+}
+
+fn print_opcodes(args: &Args, addr: u32) -> Result<()> {
+    let reader = open_object(args)?;
+    let histogram =
+        display_list::opcode_histogram(&reader, RawVirtAddr::new(addr), args.max_dl_depth)?;
+    for (opcode, count) in histogram {
+        println!("{:02X} {:?}: {}", opcode.byte(), opcode, count);
+    }
+    Ok(())
+}
+
+fn disassemble(args: &Args, addr: u32) -> Result<()> {
+    let reader = open_object(args)?;
+    match &args.output {
+        Some(output) => {
+            let mut writer = io::BufWriter::new(
+                fs::File::create(output)
+                    .with_context(|| format!("Failed to create {}", output.display()))?,
+            );
+            display_list::disassemble(
+                &reader,
+                RawVirtAddr::new(addr),
+                args.max_dl_depth,
+                &mut writer,
+            )
+        }
+        None => display_list::disassemble(
+            &reader,
+            RawVirtAddr::new(addr),
+            args.max_dl_depth,
+            &mut io::stdout().lock(),
+        ),
+    }
+}
+
+fn extract_all(args: &Args, out: &Path, mesh_options: &api::MeshOptions) -> Result<()> {
+    // Every object is loaded into the object segment on its own, so there is
+    // no single set of segments these could apply to
+    if !args.segments.is_empty() || args.dump_segments.is_some() {
+        bail!("--segment and --dump-segments can't be used with extract-all");
+    }
+
+    let rom_path = get_rom_path(args.rom.as_deref())?;
+
+    let mut reader = api::Reader::from_rom(rom_path)?;
+    check_version(&reader, args.allow_unknown_version, args.strict)?;
+    let outcomes = batch::extract_all(&mut reader, out, mesh_options)?;
+
+    let extracted = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, batch::Outcome::Extracted(_)))
+        .count();
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, batch::Outcome::Failed(_)))
+        .count();
+    println!(
+        "Extracted {} objects, {} failed, {} skipped (see {})",
+        extracted,
+        failed,
+        outcomes.len() - extracted - failed,
+        out.join("summary.txt").display()
+    );
+
+    Ok(())
+}
+
+/// Opens the ROM with the object and the other segments given by the
+/// arguments loaded
+fn open_object(args: &Args) -> Result<api::Reader> {
+    let rom_path = get_rom_path(args.rom.as_deref())?;
+
+    let mut reader = api::Reader::from_rom(&rom_path)?;
+    check_version(&reader, args.allow_unknown_version, args.strict)?;
+    match (&args.object_range, args.object) {
+        (Some(range), _) => reader.read_compressed_segment(
+            api::Segment::Object,
+            &mut fs::File::open(&rom_path)?,
+            range.clone(),
+        )?,
+        (None, Some(object_id)) => reader.load_object(object_id)?,
+        (None, None) => {
+            let object = reader
+                .find_file(EPONA_OBJECT)
+                .context("Object file not found in dmadata")?;
+            reader.load_file(api::Segment::Object, object)?;
+        }
+    }
 
+    for range in &args.preload {
+        scene::load_scene_with_rooms(
+            &mut reader,
+            &mut fs::File::open(&rom_path)?,
+            range.clone(),
+            0,
+        )?;
+    }
+    reader.read_objects(&mut fs::File::open(&rom_path)?, &args.extra_objects)?;
+    load_segments(&mut reader, &args.segments)?;
+    if let Some(dir) = &args.dump_segments {
+        dump_segments(&reader, dir)?;
+    }
+
+    Ok(reader)
+}
+
+fn extract_object(
+    args: &Args,
+    mesh_options: &api::MeshOptions,
+    animation_options: &api::AnimationOptions,
+) -> Result<()> {
+    let reader = open_object(args)?;
+
+    let (skeleton, animations) = match args.skeleton {
+        _ if args.no_animations => (args.skeleton.unwrap_or(EPONA_SKELETON), Vec::new()),
+        Some(skeleton) => (skeleton, args.animations.clone()),
+        None if args.animations.is_empty() && args.link_animations.is_empty() => (
+            EPONA_SKELETON,
+            vec![
+                (String::from("gEponaGallopingAnim"), 0x06001E2C, None),
+                (String::from("gEponaJumpingAnim"), 0x06002470, None),
+            ],
+        ),
+        None => (EPONA_SKELETON, args.animations.clone()),
+    };
+
+    let mut extraction = Extraction::new(&reader).skeleton(skeleton);
+    for (name, addr, fps) in &animations {
+        extraction = extraction.animation(name, *addr, *fps);
+    }
+    for (name, addr, fps) in &args.link_animations {
+        extraction = extraction.link_animation(name, *addr, *fps);
+    }
+    if let Some(addr) = args.joint_limits {
+        extraction = extraction.joint_limits(addr);
+    }
+    for addr in &args.extra_skeletons {
+        extraction = extraction.skeleton(*addr);
+    }
+    let extraction = extraction
+        .mesh_options(mesh_options.clone())
+        .animation_options(animation_options.clone());
+    if args.animation_files {
+        let (root, animations) = extraction.to_gltf_per_animation()?;
+        let output = output_path(args);
+        for (name, animation_root) in animations {
+            let extension = if args.glb { "glb" } else { "gltf" };
+            let path = output.with_file_name(format!("{}.{}", name, extension));
+            log::info!("Writing animation {} to {}", name, path.display());
+            write_gltf(&path, args.glb, animation_root)?;
+        }
+        return write_gltf(&output, args.glb, root);
+    }
+
+    let (root, report) =
+        extraction.to_gltf_with_progress(&mut |progress| log::debug!("{:?}", progress))?;
+
+    log::info!(
+        "Extracted {} limbs, {} meshes and {} animations with {} warnings and {} errors",
+        report.limbs,
+        report.meshes,
+        report.animations,
+        report.warnings.len(),
+        report.errors.len()
+    );
+    for (opcode, count) in &report.skipped_opcodes {
+        log::debug!("Skipped {} {:?} instructions", count, opcode);
+    }
+
+    for dlist in skeleton::find_adjacent_display_lists(&reader, RawVirtAddr::new(skeleton).into())?
+    {
+        match reader.phys_addr_of(dlist) {
+            Some(phys) => log::info!(
+                "Display list found after skeleton header at {} (ROM {})",
+                dlist,
+                phys
+            ),
+            None => log::info!("Display list found after skeleton header at {}", dlist),
+        }
+    }
+
+    write_gltf(&output_path(args), args.glb, root)
+}
+
+fn extract_room(
+    args: &Args,
+    room: Range<u32>,
+    scene: Option<Range<u32>>,
+    mesh_options: &api::MeshOptions,
+) -> Result<()> {
+    let rom_path = get_rom_path(args.rom.as_deref())?;
+
+    let mut reader = api::Reader::from_rom(&rom_path)?;
+    check_version(&reader, args.allow_unknown_version, args.strict)?;
+    reader.read_compressed_segment(api::Segment::Room, &mut fs::File::open(&rom_path)?, room)?;
+    if let Some(scene) = scene {
+        reader.read_compressed_segment(
+            api::Segment::Scene,
+            &mut fs::File::open(&rom_path)?,
+            scene,
+        )?;
+    }
+
+    load_segments(&mut reader, &args.segments)?;
+    if let Some(dir) = &args.dump_segments {
+        dump_segments(&reader, dir)?;
+    }
+
+    let (root, report) = Extraction::new(&reader)
+        .room()
+        .mesh_options(mesh_options.clone())
+        .to_gltf_with_report()?;
+    log::info!(
+        "Extracted {} meshes with {} warnings and {} errors",
+        report.meshes,
+        report.warnings.len(),
+        report.errors.len()
+    );
+
+    write_gltf(&output_path(args), args.glb, root)
+}
+
+/// Loads the files given with --segment into their segments
+fn load_segments(
+    reader: &mut api::Reader,
+    segments: &[(u8, PathBuf, Option<Range<u32>>)],
+) -> Result<()> {
+    for (number, path, range) in segments {
+        let mut file =
+            fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let range = match range {
+            Some(range) => range.clone(),
+            None => 0..file.metadata()?.len() as u32,
+        };
+        reader
+            .read_compressed_segment_raw(*number, &mut file, range)
+            .with_context(|| {
+                format!("Failed to load {} into segment {}", path.display(), number)
+            })?;
+    }
+    Ok(())
+}
+
+/// Writes the data of every loaded segment to DIR/segment_NN.bin
+fn dump_segments(reader: &api::Reader, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for (number, data) in reader.segments() {
+        let path = dir.join(format!("segment_{:02}.bin", number));
+        log::info!("Writing segment {} to {}", number, path.display());
+        fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The file given by --output, or its default
+fn output_path(args: &Args) -> PathBuf {
+    args.output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(if args.glb { "out.glb" } else { "out.gltf" }))
+}
+
+/// Writes the glTF to `output`, as binary glTF with `glb`
+fn write_gltf(output: &Path, glb: bool, mut root: gltf::json::Root) -> Result<()> {
+    let writer = fs::File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    if glb {
+        let buffers = buffer::take_buffers(&mut root)?;
+        buffer::write_glb(&root, &buffers, writer)?;
+    } else {
+        buffer::write_gltf(&root, &[], writer)?;
+    }
+
+    Ok(())
+}
+
+/// Refuses ROMs that aren't a known release, as the addresses read from them
+/// would be wrong, and with `strict` those that fail their checksum or whose
+/// header CRCs aren't those of their release
+fn check_version(reader: &api::Reader, allow_unknown_version: bool, strict: bool) -> Result<()> {
+    match version::verify_checksum(reader) {
+        Ok(()) => log::debug!("ROM checksum matches its header"),
+        Err(e) if strict => return Err(e.context("Refusing to extract with --strict")),
+        Err(e) => log::warn!("{:#}", e),
+    }
+
+    match version::detect_version(reader) {
+        Ok(version) => {
+            log::info!("Detected ROM version {}", version);
+            match version::verify_header_crcs(reader, version) {
+                Ok(()) => log::debug!("ROM header CRCs match those of {}", version),
+                Err(e) if strict => return Err(e.context("Refusing to extract with --strict")),
+                Err(e) => log::warn!("{:#}", e),
+            }
+        }
+        Err(e) if allow_unknown_version => log::warn!("{:#}", e),
+        Err(e) => {
+            return Err(e.context(
+                "Refusing to extract from an unknown ROM, pass --allow-unknown-version to do so anyway",
+            ));
+        }
+    }
     Ok(())
 }
 
-fn get_rom_path() -> Result<PathBuf> {
-    Ok(glob::glob("*.z64")
-        .expect("Failed to read glob pattern")
-        .next()
-        .with_context(|| "No ROM found")?
-        .expect("Glob error"))
+/// The given ROM, or else the only ROM found in the current directory or one
+/// level of subdirectories
+fn get_rom_path(rom: Option<&Path>) -> Result<PathBuf> {
+    if let Some(rom) = rom {
+        return Ok(rom.to_owned());
+    }
+
+    let mut candidates = Vec::new();
+    for pattern in ["*.z64", "*.v64", "*.n64", "*/*.z64", "*/*.v64", "*/*.n64"] {
+        for path in glob::glob(pattern).expect("Failed to read glob pattern") {
+            candidates.push(path.context("Failed to search for ROMs")?);
+        }
+    }
+
+    match candidates.len() {
+        0 => bail!("No ROM found (*.z64, *.v64 or *.n64), pass one with --rom"),
+        1 => Ok(candidates.remove(0)),
+        _ => bail!(
+            "Found several ROMs, pass one with --rom: {}",
+            candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Parses a START:END pair of hexadecimal ROM offsets
+fn parse_range(s: &str) -> Result<Range<u32>> {
+    let (start, end) = s.split_once(':').context("Expected START:END")?;
+    Ok(parse_hex(start)?..parse_hex(end)?)
+}
+
+fn parse_object_id(s: &str) -> Result<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .with_context(|| format!("Invalid object ID {}", s))
+}
+
+/// The log level of --verbose and --quiet. Warnings are logged by default,
+/// and each -v adds the next levels down to trace.
+fn log_level(quiet: bool, verbose: u8) -> log::LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Off,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    }
+}
+
+/// Sets the log level from --verbose and --quiet, with RUST_LOG overriding
+/// them when set
+fn init_logger(args: &Args) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.filter_level(log_level(args.quiet, args.verbose));
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
+        .with_context(|| format!("Invalid hexadecimal number {}", s))
+}
+
+/// Parses a SEGMENT=START:END pair of a segment number and a ROM range
+fn parse_object(s: &str) -> Result<(u8, Range<u32>)> {
+    let (segment, range) = s.split_once('=').context("Expected SEGMENT=START:END")?;
+    let segment = u8::from_str_radix(segment.trim_start_matches("0x"), 16)
+        .with_context(|| format!("Invalid segment number {}", segment))?;
+    Ok((segment, parse_range(range)?))
+}
+
+fn parse_interpolation(s: &str) -> Result<gltf::json::animation::Interpolation> {
+    match s {
+        "linear" => Ok(gltf::json::animation::Interpolation::Linear),
+        "step" => Ok(gltf::json::animation::Interpolation::Step),
+        _ => anyhow::bail!("Unknown interpolation {}, expected linear or step", s),
+    }
+}
+
+/// Parses a SEGMENT=FILE[@START:END] pair of a segment number and a file, or
+/// a range of it
+fn parse_segment(s: &str) -> Result<(u8, PathBuf, Option<Range<u32>>)> {
+    let (segment, file) = s
+        .split_once('=')
+        .context("Expected SEGMENT=FILE[@START:END]")?;
+    let segment = u8::from_str_radix(segment.trim_start_matches("0x"), 16)
+        .with_context(|| format!("Invalid segment number {}", segment))?;
+    let (path, range) = match file.rsplit_once('@') {
+        Some((path, range)) => (path, Some(parse_range(range)?)),
+        None => (file, None),
+    };
+    Ok((segment, PathBuf::from(path), range))
+}
+
+/// Parses a NAME=ADDR pair with a hexadecimal address and an optional @FPS
+fn parse_animation(s: &str) -> Result<(String, u32, Option<f32>)> {
+    let (name, addr) = s.split_once('=').context("Expected NAME=ADDR[@FPS]")?;
+    let (addr, fps) = match addr.split_once('@') {
+        Some((addr, fps)) => (
+            addr,
+            Some(
+                fps.parse()
+                    .with_context(|| format!("Invalid frame rate {}", fps))?,
+            ),
+        ),
+        None => (addr, None),
+    };
+    Ok((name.to_owned(), parse_hex(addr)?, fps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_object_and_its_addresses() {
+        let args = Args::try_parse_from([
+            "armos",
+            "--object-range",
+            "10DB000:0x10E8F10",
+            "--skeleton",
+            "06009D74",
+            "--animation",
+            "walk=0x06002470",
+            "--animation",
+            "gallop=06001e2c@30",
+        ])
+        .unwrap();
+
+        assert_eq!(args.object_range, Some(0x010DB000..0x010E8F10));
+        assert_eq!(args.skeleton, Some(0x06009D74));
+        assert_eq!(
+            args.animations,
+            [
+                (String::from("walk"), 0x06002470, None),
+                (String::from("gallop"), 0x06001E2C, Some(30.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_addresses() {
+        for args in [
+            ["armos", "--object-range", "10DB000"],
+            ["armos", "--object-range", "10DB000:end"],
+            ["armos", "--skeleton", "skeleton"],
+            ["armos", "--animation", "06002470"],
+        ] {
+            assert!(Args::try_parse_from(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn refuses_segments_when_extracting_every_object() {
+        for extra in [
+            ["--segment", "4=gameplay_keep.bin"],
+            ["--dump-segments", "dump"],
+        ] {
+            let args = Args::try_parse_from(
+                ["armos", "extract-all", "--out", "objects"]
+                    .into_iter()
+                    .chain(extra),
+            )
+            .unwrap();
+            let Some(Command::ExtractAll { out }) = &args.command else {
+                panic!("extract-all wasn't parsed as such");
+            };
+
+            let error = extract_all(&args, out, &Default::default()).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "--segment and --dump-segments can't be used with extract-all"
+            );
+        }
+    }
+
+    #[test]
+    fn logs_warnings_unless_quiet_and_more_when_verbose() {
+        assert_eq!(log_level(false, 0), log::LevelFilter::Warn);
+        assert_eq!(log_level(false, 1), log::LevelFilter::Info);
+        assert_eq!(log_level(false, 2), log::LevelFilter::Debug);
+        assert_eq!(log_level(false, 3), log::LevelFilter::Trace);
+        assert_eq!(log_level(false, 5), log::LevelFilter::Trace);
+        assert_eq!(log_level(true, 0), log::LevelFilter::Off);
+    }
 }
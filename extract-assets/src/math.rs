@@ -1,6 +1,8 @@
 // This file emulates the same math functions as can be found in the OOT project
 
-use glam::Mat4;
+use glam::{Mat4, Quat};
+
+use crate::rom;
 
 const SHT_MAX: f32 = 32767.0f32;
 const SHT_MINV: f32 = 1.0f32 / SHT_MAX;
@@ -94,20 +96,18 @@ const SINTABLE: [i16; 0x400] = [
 ];
 
 fn sins(x: u16) -> i16 {
-    let value: i16;
-
     let x = x >> 4;
 
-    if (x & 0x400) != 0 {
-        value = SINTABLE[(0x3FF - (x & 0x3FF)) as usize];
+    let value = if (x & 0x400) != 0 {
+        SINTABLE[(0x3FF - (x & 0x3FF)) as usize]
     } else {
-        value = SINTABLE[(x & 0x3FF) as usize];
-    }
+        SINTABLE[(x & 0x3FF) as usize]
+    };
 
     if (x & 0x800) != 0 {
-        return -value;
+        -value
     } else {
-        return value;
+        value
     }
 }
 
@@ -184,27 +184,64 @@ pub fn rotate_zyx(x: i16, y: i16, z: i16) -> Mat4 {
         mf_yz = (yz * cos) - (yy * sin);
 
         mf_zy = mf_zz * sin;
-        mf_zz = mf_zz * cos;
+        mf_zz *= cos;
     } else {
         mf_zy = 0.0;
     }
 
     Mat4::from_cols_array(&[
-        mf_xx as f32,
-        mf_yx as f32,
-        mf_zx as f32,
-        mf_wx as f32,
-        mf_xy as f32,
-        mf_yy as f32,
-        mf_zy as f32,
-        mf_wy as f32,
-        mf_xz as f32,
-        mf_yz as f32,
-        mf_zz as f32,
-        mf_wz as f32,
-        mf_xw as f32,
-        mf_yw as f32,
-        mf_zw as f32,
-        mf_ww as f32,
+        mf_xx, mf_yx, mf_zx, mf_wx, mf_xy, mf_yy, mf_zy, mf_wy, mf_xz, mf_yz, mf_zz, mf_wz, mf_xw,
+        mf_yw, mf_zw, mf_ww,
     ])
 }
+
+/// The rotation of `rotate_zyx` as a unit quaternion. The game's space is
+/// right-handed with Y up like glTF's, so the axes are kept as they are. The
+/// sine table makes the matrix slightly off from orthonormal, which would
+/// leave the quaternion off from unit length without normalizing it.
+pub fn rotate_zyx_quat(x: i16, y: i16, z: i16) -> Quat {
+    Quat::from_mat4(&rotate_zyx(x, y, z)).normalize()
+}
+
+/// Converts a fixed point matrix. N64 matrices transform row vectors, so
+/// their row-major elements are glam's column-major ones.
+pub fn mtx_to_mat4(mtx: &rom::Mtx) -> Mat4 {
+    let mut elements = [0.0; 16];
+    for (i, element) in elements.iter_mut().enumerate() {
+        let fixed = ((mtx.int_part[i].get() as i32) << 16) | mtx.frac_part[i].get() as i32;
+        *element = fixed as f32 / 65536.0;
+    }
+    Mat4::from_cols_array(&elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn rotates_about_z_then_y_then_x() {
+        use std::f32::consts::FRAC_PI_2;
+
+        // A quarter turn about Z takes X to Y, like glTF's right-handed axes
+        let quat = rotate_zyx_quat(0, 0, 0x4000);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(quat.abs_diff_eq(Quat::from_xyzw(0.0, 0.0, half, half), 1e-4));
+        assert!(quat.mul_vec3(Vec3::X).abs_diff_eq(Vec3::Y, 1e-4));
+
+        // The X rotation applies first, then Y, then Z
+        for (x, y, z) in [(0x4000, 0x4000, 0), (0x1000, -0x2000, 0x3000)] {
+            let angle = |binang: i16| binang as f32 / 0x4000 as f32 * FRAC_PI_2;
+            let expected = Quat::from_euler(glam::EulerRot::ZYX, angle(z), angle(y), angle(x));
+            let quat = rotate_zyx_quat(x, y, z);
+            assert!(quat.is_normalized());
+            assert!(
+                quat.dot(expected).abs() > 1.0 - 1e-4,
+                "{} != {}",
+                quat,
+                expected
+            );
+        }
+    }
+}
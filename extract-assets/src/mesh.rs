@@ -1,141 +1,456 @@
-use std::{collections::HashMap, mem};
+use std::{
+    collections::{HashMap, HashSet},
+    io, iter, mem,
+};
 
-use anyhow::{Context, Result};
-use base64::prelude::*;
-use gltf::json::{self, validation::Checked::Valid};
+use anyhow::{bail, Context, Result};
+use glam::{Mat4, Vec3};
+use gltf::json::{self, material::AlphaMode, validation::Checked::Valid};
+use log::Level;
+use num_traits::FromPrimitive;
 use zerocopy::AsBytes;
 
 use crate::{
-    display_list::{Instruction, Opcode, Tri1, Tri2, Vtx},
+    addr::RawVirtAddr,
+    buffer,
+    builder::{self, GltfBuilder},
+    display_list::{
+        self, GeometryFlags, Instruction, InstructionStream, Opcode, OtherModeL, Tri1, Tri2, Vtx,
+    },
+    math,
+    report::ExtractionReport,
     rom,
+    texture::{self, Texture},
 };
 
-#[derive(AsBytes, Debug)]
+#[derive(AsBytes, Clone, Debug)]
 #[repr(C)]
 pub struct Vertex {
     pub pos: [f32; 3],
+
+    /// The joint the vertex is rigidly bound to, in the first of the four
+    /// JOINTS_0 slots
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+    pub normal: [f32; 3],
+
+    /// Texture coordinates, relative to the size of the tile they were drawn
+    /// with but unscaled until `MeshOptions` are applied
+    pub uv: [f32; 2],
+
+    /// The RGBA vertex color. Lit geometry only keeps the alpha, over white.
+    pub color: [u8; 4],
 }
 impl Vertex {
     fn fold_pos(
         op: impl Fn(f32, f32) -> f32,
     ) -> impl FnMut(Option<[f32; 3]>, &Vertex) -> Option<[f32; 3]> {
         move |acc, v| match acc {
-            None => Some(v.pos.clone()),
+            None => Some(v.pos),
             Some([x, y, z]) => Some([op(x, v.pos[0]), op(y, v.pos[1]), op(z, v.pos[2])]),
         }
     }
 }
 impl From<&'_ rom::Vtx> for Vertex {
-    fn from(rom::Vtx { pos, .. }: &rom::Vtx) -> Self {
+    /// Takes both the normal and the color from `cn`, which holds a signed
+    /// normal when the geometry is lit and a color otherwise
+    fn from(rom::Vtx { pos, tpos, cn, .. }: &rom::Vtx) -> Self {
+        let normal = Vec3::new(cn[0] as i8 as _, cn[1] as i8 as _, cn[2] as i8 as _);
+
         Self {
             pos: [pos[0].get() as _, pos[1].get() as _, pos[2].get() as _],
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            normal: normal.try_normalize().unwrap_or(Vec3::Z).to_array(),
+            uv: [tpos[0].get() as _, tpos[1].get() as _],
+            color: *cn,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshOptions {
+    /// Give every triangle its own vertices carrying the face normal, for a
+    /// faceted look instead of smooth shading
+    pub flat_normals: bool,
+
+    /// Write every draw of a display list as its own node and mesh, so that
+    /// the parts can be shown and hidden separately
+    pub split_draws: bool,
+
+    /// How many display lists deep calls are followed before giving up
+    pub max_dl_depth: usize,
+
+    /// Factor converting the raw S/T of `Vtx.tpos` to texels, 1/32 for the
+    /// 10.5 fixed point format
+    pub texcoord_scale: f32,
+
+    /// Keep triangles whose corners aren't three distinct vertices, which are
+    /// dropped otherwise
+    pub keep_degenerate_triangles: bool,
+
+    /// Write the rest pose transform of every limb node relative to its
+    /// skeleton to the node's extras, as the column major `world_matrix`
+    pub world_matrix_extras: bool,
+
+    /// Merge the limb meshes of skeletons into one mesh deformed by the limb
+    /// nodes through a glTF skin, instead of attaching each to its limb
+    pub skin: bool,
+
+    /// Factor every position is scaled by, vertices as well as limbs and
+    /// their animated translations, e.g. 0.01 to shrink the game's units
+    pub scale: f32,
+
+    /// Reverse the winding of every triangle, for models whose outside faces
+    /// inward. Triangles drawn with only their front faces culled are always
+    /// reversed, as those show their clockwise side.
+    pub flip_winding: bool,
+}
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            flat_normals: false,
+            split_draws: false,
+            max_dl_depth: 32,
+            texcoord_scale: 1.0 / 32.0,
+            keep_degenerate_triangles: false,
+            world_matrix_extras: false,
+            skin: false,
+            scale: 1.0,
+            flip_winding: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub alpha_mode: AlphaMode,
+
+    /// The texture drawn with, when texturing is on and the texture could be
+    /// decoded
+    pub texture: Option<Texture>,
+
+    /// Whether back faces are drawn, as backface culling is off
+    pub double_sided: bool,
+
+    /// The primitive color of untextured geometry, as RGBA
+    pub base_color: Option<[u8; 4]>,
+
+    /// Whether the geometry is lit, in which case its vertices carry normals
+    /// rather than colors
+    pub lighting: bool,
+}
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            alpha_mode: AlphaMode::Opaque,
+            texture: None,
+            double_sided: false,
+            base_color: None,
+            lighting: true,
         }
     }
 }
 
 #[derive(Default, Debug)]
-pub struct Mesh {
+pub struct Primitive {
     pub indices: Vec<u32>,
     pub vertices: Vec<Vertex>,
+    pub material: Material,
+
+    /// Whether the vertex normals have been set and should be exported
+    pub has_normals: bool,
+
+    /// Where in `indices` each draw after the first begins. A draw is a
+    /// vertex load followed by the triangles drawn from it.
+    pub draw_starts: Vec<usize>,
+
+    /// The position displacement of every vertex for each morph target
+    pub targets: Vec<Vec<[f32; 3]>>,
 }
-impl Mesh {
-    pub fn write_into_gltf(&self, root: &mut json::Root) {
-        root.buffers.push(json::Buffer {
-            byte_length: mem::size_of_val(&*self.vertices) as _,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            uri: Some(format!(
-                "data:application/octet-stream;base64,{}",
-                BASE64_STANDARD.encode(self.vertices.as_bytes())
-            )),
-        });
-        root.buffer_views.push(json::buffer::View {
-            buffer: json::Index::new(root.buffers.len() as u32 - 1),
-            byte_length: mem::size_of_val(&*self.vertices) as _,
-            byte_offset: None,
-            byte_stride: Some(mem::size_of::<Vertex>() as _),
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            target: Some(Valid(json::buffer::Target::ArrayBuffer)),
-        });
-        root.accessors.push(json::Accessor {
-            buffer_view: Some(json::Index::new(root.buffer_views.len() as u32 - 1)),
-            byte_offset: 0,
-            count: self.vertices.len() as u32,
-            component_type: Valid(json::accessor::GenericComponentType(
+impl Primitive {
+    /// Replaces the indexed vertices with three vertices per triangle that
+    /// all carry the triangle's face normal
+    pub fn flatten_normals(&mut self) {
+        let source = &self.vertices;
+        let vertices = self
+            .indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(source[triangle[i] as usize].pos));
+                let normal = (b - a)
+                    .cross(c - a)
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z)
+                    .to_array();
+
+                triangle.iter().map(move |index| Vertex {
+                    normal,
+                    ..source[*index as usize].clone()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for target in &mut self.targets {
+            *target = self
+                .indices
+                .iter()
+                .map(|index| target[*index as usize])
+                .collect();
+        }
+        self.indices = (0..vertices.len() as u32).collect();
+        self.vertices = vertices;
+        self.has_normals = true;
+    }
+
+    /// Merges vertices that are identical, including in every morph target,
+    /// as the same vertex is often loaded again by later draws
+    pub fn dedup_vertices(&mut self) {
+        // Compared by bit pattern, as floats are neither `Eq` nor `Hash`
+        let key = |index: usize| {
+            let mut key = self.vertices[index].as_bytes().to_vec();
+            for target in &self.targets {
+                key.extend_from_slice(target[index].as_bytes());
+            }
+            key
+        };
+
+        let mut unique = HashMap::new();
+        let mut kept = Vec::new();
+        let remap = (0..self.vertices.len())
+            .map(|index| {
+                *unique.entry(key(index)).or_insert_with(|| {
+                    kept.push(index);
+                    kept.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        if kept.len() == self.vertices.len() {
+            return;
+        }
+
+        log::debug!(
+            "Merged {} duplicate vertices",
+            self.vertices.len() - kept.len()
+        );
+        self.vertices = kept
+            .iter()
+            .map(|index| self.vertices[*index].clone())
+            .collect();
+        for target in &mut self.targets {
+            *target = kept.iter().map(|index| target[*index]).collect();
+        }
+        for index in &mut self.indices {
+            *index = remap[*index as usize];
+        }
+    }
+
+    fn write_into_gltf(
+        &self,
+        root: &mut json::Root,
+        images: &HashMap<Texture, Vec<u8>>,
+        skinned: bool,
+    ) -> json::mesh::Primitive {
+        let vertices = root.add_buffer_view(
+            self.vertices.as_bytes(),
+            Some(mem::size_of::<Vertex>()),
+            Some(json::buffer::Target::ArrayBuffer),
+        );
+
+        let mut attributes = HashMap::new();
+        let mut add_attribute =
+            |semantic,
+             byte_offset: usize,
+             component_type,
+             type_,
+             normalized,
+             bounds: Option<([f32; 3], [f32; 3])>| {
+                let (min, max) = bounds.unzip();
+                let accessor = root.add(json::Accessor {
+                    buffer_view: Some(vertices),
+                    byte_offset: byte_offset as _,
+                    min: min.map(|v| json::Value::from(v.to_vec())),
+                    max: max.map(|v| json::Value::from(v.to_vec())),
+                    normalized,
+                    ..builder::accessor(self.vertices.len(), component_type, type_)
+                });
+                attributes.insert(Valid(semantic), accessor);
+            };
+
+        add_attribute(
+            json::mesh::Semantic::Positions,
+            mem::offset_of!(Vertex, pos),
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec3,
+            false,
+            self.min_vertex_pos().zip(self.max_vertex_pos()),
+        );
+        if self.has_normals {
+            add_attribute(
+                json::mesh::Semantic::Normals,
+                mem::offset_of!(Vertex, normal),
                 json::accessor::ComponentType::F32,
-            )),
-            extensions: Default::default(),
-            extras: Default::default(),
-            type_: Valid(json::accessor::Type::Vec3),
-            min: self.min_vertex_pos().map(|v| json::Value::from(v.to_vec())),
-            max: self.max_vertex_pos().map(|v| json::Value::from(v.to_vec())),
-            name: None,
-            normalized: false,
-            sparse: None,
-        });
+                json::accessor::Type::Vec3,
+                false,
+                None,
+            );
+        }
+        if !self.material.lighting || self.vertices.iter().any(|vertex| vertex.color[3] != 0xFF) {
+            add_attribute(
+                json::mesh::Semantic::Colors(0),
+                mem::offset_of!(Vertex, color),
+                json::accessor::ComponentType::U8,
+                json::accessor::Type::Vec4,
+                true,
+                None,
+            );
+        }
+        add_attribute(
+            json::mesh::Semantic::TexCoords(0),
+            mem::offset_of!(Vertex, uv),
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Vec2,
+            false,
+            None,
+        );
+        if skinned {
+            add_attribute(
+                json::mesh::Semantic::Joints(0),
+                mem::offset_of!(Vertex, joints),
+                json::accessor::ComponentType::U16,
+                json::accessor::Type::Vec4,
+                false,
+                None,
+            );
+            add_attribute(
+                json::mesh::Semantic::Weights(0),
+                mem::offset_of!(Vertex, weights),
+                json::accessor::ComponentType::F32,
+                json::accessor::Type::Vec4,
+                false,
+                None,
+            );
+        }
 
-        root.buffers.push(json::Buffer {
-            byte_length: mem::size_of_val(&*self.indices) as _,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            uri: Some(format!(
-                "data:application/octet-stream;base64,{}",
-                BASE64_STANDARD.encode(self.indices.as_bytes())
-            )),
-        });
-        root.buffer_views.push(json::buffer::View {
-            buffer: json::Index::new(root.buffers.len() as u32 - 1),
-            byte_length: mem::size_of_val(&*self.indices) as u32,
-            byte_offset: None,
-            byte_stride: None,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            target: Some(Valid(json::buffer::Target::ElementArrayBuffer)),
-        });
-        root.accessors.push(json::Accessor {
-            buffer_view: Some(json::Index::new(root.buffer_views.len() as u32 - 1)),
-            byte_offset: 0,
-            count: self.indices.len() as u32,
-            component_type: Valid(json::accessor::GenericComponentType(
+        let indices = root.add_accessor(
+            self.indices.as_bytes(),
+            Some(json::buffer::Target::ElementArrayBuffer),
+            builder::accessor(
+                self.indices.len(),
                 json::accessor::ComponentType::U32,
-            )),
-            extensions: Default::default(),
-            extras: Default::default(),
-            type_: Valid(json::accessor::Type::Scalar),
-            min: None,
-            max: None,
-            name: None,
-            normalized: false,
-            sparse: None,
+                json::accessor::Type::Scalar,
+            ),
+        );
+
+        let base_color_texture = self.material.texture.and_then(|texture| {
+            Some(write_texture_into_gltf(
+                root,
+                texture,
+                images.get(&texture)?,
+            ))
+        });
+        let targets = self
+            .targets
+            .iter()
+            .map(|target| {
+                let bounds = |op: fn(f32, f32) -> f32| {
+                    target
+                        .iter()
+                        .copied()
+                        .reduce(|a, b| [op(a[0], b[0]), op(a[1], b[1]), op(a[2], b[2])])
+                        .map(|v| json::Value::from(v.to_vec()))
+                };
+
+                let positions = root.add_accessor(
+                    target.as_bytes(),
+                    Some(json::buffer::Target::ArrayBuffer),
+                    json::Accessor {
+                        min: bounds(f32::min),
+                        max: bounds(f32::max),
+                        ..builder::accessor(
+                            target.len(),
+                            json::accessor::ComponentType::F32,
+                            json::accessor::Type::Vec3,
+                        )
+                    },
+                );
+                json::mesh::MorphTarget {
+                    positions: Some(positions),
+                    normals: None,
+                    tangents: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let material = root.add(json::Material {
+            alpha_mode: Valid(self.material.alpha_mode),
+            double_sided: self.material.double_sided,
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_texture,
+                base_color_factor: self
+                    .material
+                    .base_color
+                    .map(|color| {
+                        json::material::PbrBaseColorFactor(color.map(|c| c as f32 / 255.0))
+                    })
+                    .unwrap_or_default(),
+                ..Default::default()
+            },
+            ..Default::default()
         });
 
-        root.meshes.push(json::Mesh {
+        json::mesh::Primitive {
+            attributes,
             extensions: Default::default(),
             extras: Default::default(),
-            name: None,
-            primitives: vec![json::mesh::Primitive {
-                attributes: {
-                    let mut map = HashMap::new();
-                    map.insert(
-                        Valid(json::mesh::Semantic::Positions),
-                        json::Index::new(root.accessors.len() as u32 - 2),
-                    );
-                    map
-                },
-                extensions: Default::default(),
-                extras: Default::default(),
-                indices: Some(json::Index::new(root.accessors.len() as u32 - 1)),
-                material: None,
-                mode: Valid(json::mesh::Mode::Triangles),
-                targets: None,
-            }],
-            weights: None,
-        });
+            indices: Some(indices),
+            material: Some(material),
+            mode: Valid(json::mesh::Mode::Triangles),
+            targets: (!targets.is_empty()).then_some(targets),
+        }
+    }
+
+    /// Splits off the triangles of each draw, keeping only the vertices they
+    /// use
+    fn split_draws(&self) -> impl Iterator<Item = Primitive> + '_ {
+        let starts = iter::once(0).chain(self.draw_starts.iter().copied());
+        let ends = self
+            .draw_starts
+            .iter()
+            .copied()
+            .chain(iter::once(self.indices.len()));
+
+        starts.zip(ends).map(|(start, end)| {
+            let mut remap = HashMap::new();
+            let mut sources = Vec::new();
+            let indices = self.indices[start..end]
+                .iter()
+                .map(|index| {
+                    *remap.entry(*index).or_insert_with(|| {
+                        sources.push(*index as usize);
+                        sources.len() as u32 - 1
+                    })
+                })
+                .collect();
+            let vertices = sources
+                .iter()
+                .map(|source| self.vertices[*source].clone())
+                .collect();
+            let targets = self
+                .targets
+                .iter()
+                .map(|target| sources.iter().map(|source| target[*source]).collect())
+                .collect();
+
+            Primitive {
+                indices,
+                vertices,
+                material: self.material,
+                has_normals: self.has_normals,
+                draw_starts: Vec::new(),
+                targets,
+            }
+        })
     }
 
     fn min_vertex_pos(&self) -> Option<[f32; 3]> {
@@ -151,38 +466,1228 @@ impl Mesh {
     }
 }
 
-pub fn fold(reader: &rom::Reader) -> impl FnMut(Mesh, Instruction) -> Result<Mesh> + '_ {
-    let mut vertex_offset = 0;
+#[derive(Default, Debug)]
+pub struct Mesh {
+    pub primitives: Vec<Primitive>,
+
+    /// The textures drawn with, encoded as PNG
+    pub images: HashMap<Texture, Vec<u8>>,
+
+    /// What came up while decoding the display list
+    pub report: ExtractionReport,
+
+    /// Whether the vertices are bound to the joints of a skin, which writes
+    /// their JOINTS_0 and WEIGHTS_0
+    pub skinned: bool,
+}
+impl Mesh {
+    pub fn write_into_gltf(&self, root: &mut json::Root, name: &str) -> json::Index<json::Mesh> {
+        let primitives = self
+            .primitives
+            .iter()
+            .map(|primitive| primitive.write_into_gltf(root, &self.images, self.skinned))
+            .collect();
+
+        let target_count = self
+            .primitives
+            .first()
+            .map_or(0, |primitive| primitive.targets.len());
+        root.add(json::Mesh {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: Some(name.to_owned()),
+            primitives,
+            weights: (target_count > 0).then(|| vec![0.0; target_count]),
+        })
+    }
+
+    /// Writes the mesh as Wavefront OBJ, one group per primitive. Texture
+    /// coordinates are flipped vertically, as OBJ has them start at the
+    /// bottom.
+    pub fn write_obj(&self, mut writer: impl io::Write) -> io::Result<()> {
+        let mut first_index = 1;
+        for (index, primitive) in self.primitives.iter().enumerate() {
+            writeln!(writer, "g primitive_{}", index)?;
+            for vertex in &primitive.vertices {
+                let [x, y, z] = vertex.pos;
+                let [u, v] = vertex.uv;
+                let [nx, ny, nz] = vertex.normal;
+                writeln!(writer, "v {} {} {}", x, y, z)?;
+                writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+                writeln!(writer, "vn {} {} {}", nx, ny, nz)?;
+            }
+
+            for triangle in primitive.indices.chunks_exact(3) {
+                write!(writer, "f")?;
+                for &i in triangle {
+                    let i = first_index + i;
+                    if primitive.has_normals {
+                        write!(writer, " {}/{}/{}", i, i, i)?;
+                    } else {
+                        write!(writer, " {}/{}", i, i)?;
+                    }
+                }
+                writeln!(writer)?;
+            }
+            first_index += primitive.vertices.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a node named `name` drawing the mesh, or with `split_draws` a
+    /// node whose children each draw one of the draws of the mesh. Returns
+    /// the index of the node.
+    pub fn write_node_into_gltf(
+        &self,
+        root: &mut json::Root,
+        name: &str,
+        options: &MeshOptions,
+    ) -> json::Index<json::Node> {
+        if !options.split_draws {
+            let mesh = self.write_into_gltf(root, name);
+            return push_mesh_node(root, mesh, name.to_owned());
+        }
+
+        let children = self
+            .primitives
+            .iter()
+            .flat_map(|primitive| primitive.split_draws())
+            .enumerate()
+            .map(|(index, primitive)| {
+                let name = format!("{}_draw_{}", name, index);
+                let primitive = primitive.write_into_gltf(root, &self.images, self.skinned);
+                let mesh = root.add(json::Mesh {
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                    name: Some(name.clone()),
+                    primitives: vec![primitive],
+                    weights: None,
+                });
+                push_mesh_node(root, mesh, name)
+            })
+            .collect();
+
+        root.add(json::Node {
+            children: Some(children),
+            name: Some(name.to_owned()),
+            ..empty_node()
+        })
+    }
+
+    /// Applies the options that post-process the decoded geometry
+    pub fn apply_options(&mut self, options: &MeshOptions) {
+        for vertex in self
+            .primitives
+            .iter_mut()
+            .flat_map(|primitive| primitive.vertices.iter_mut())
+        {
+            vertex.uv = vertex.uv.map(|n| n * options.texcoord_scale);
+            vertex.pos = vertex.pos.map(|n| n * options.scale);
+        }
+        for target in self
+            .primitives
+            .iter_mut()
+            .flat_map(|primitive| primitive.targets.iter_mut())
+        {
+            for displacement in target {
+                *displacement = displacement.map(|n| n * options.scale);
+            }
+        }
+
+        if options.flat_normals {
+            for primitive in &mut self.primitives {
+                primitive.flatten_normals();
+            }
+        }
+
+        for primitive in &mut self.primitives {
+            primitive.dedup_vertices();
+        }
+    }
+
+    /// Adds a morph target moving every vertex to where it is in `other`,
+    /// which must be drawn by the same display list
+    pub fn push_morph_target(&mut self, other: &Mesh) -> Result<()> {
+        if self.primitives.len() != other.primitives.len() {
+            bail!("Morph target has a different number of primitives");
+        }
+
+        for (primitive, other) in self.primitives.iter_mut().zip(&other.primitives) {
+            if primitive.vertices.len() != other.vertices.len() {
+                bail!("Morph target has a different number of vertices");
+            }
+
+            let target = primitive
+                .vertices
+                .iter()
+                .zip(&other.vertices)
+                .map(|(vertex, other)| (Vec3::from(other.pos) - Vec3::from(vertex.pos)).to_array())
+                .collect();
+            primitive.targets.push(target);
+        }
+
+        Ok(())
+    }
+
+    /// Transforms the positions and normals of every vertex by `matrix`
+    pub fn transform(&mut self, matrix: Mat4) {
+        for vertex in self
+            .primitives
+            .iter_mut()
+            .flat_map(|primitive| primitive.vertices.iter_mut())
+        {
+            vertex.pos = matrix.transform_point3(vertex.pos.into()).into();
+            vertex.normal = matrix
+                .transform_vector3(vertex.normal.into())
+                .try_normalize()
+                .unwrap_or(Vec3::Z)
+                .into();
+        }
+    }
+
+    /// Binds every vertex of the mesh fully to the given joint
+    pub fn assign_joint(&mut self, joint: u16) {
+        for vertex in self
+            .primitives
+            .iter_mut()
+            .flat_map(|primitive| primitive.vertices.iter_mut())
+        {
+            vertex.joints = [joint, 0, 0, 0];
+            vertex.weights = [1.0, 0.0, 0.0, 0.0];
+        }
+    }
+
+    /// Appends the primitives of `other`, drawn with the given alpha mode.
+    /// Opaque primitives keep the alpha mode of the render mode they were
+    /// drawn with.
+    pub fn merge(&mut self, other: Mesh, alpha_mode: AlphaMode) {
+        self.primitives
+            .extend(other.primitives.into_iter().map(|mut primitive| {
+                if alpha_mode != AlphaMode::Opaque {
+                    primitive.material.alpha_mode = alpha_mode;
+                }
+                primitive
+            }));
+        self.images.extend(other.images);
+        self.report.merge(other.report);
+    }
+
+    fn current_primitive(&mut self) -> &mut Primitive {
+        if self.primitives.is_empty() {
+            self.primitives.push(Primitive::default());
+        }
+        self.primitives.last_mut().unwrap()
+    }
+
+    /// The primitive to draw with the given material, starting a new one
+    /// when the current one has triangles drawn with another material
+    fn primitive_with(&mut self, material: Material) -> &mut Primitive {
+        let primitive = self.current_primitive();
+        if primitive.material != material {
+            if primitive.indices.is_empty() {
+                primitive.material = material;
+            } else {
+                self.primitives.push(Primitive {
+                    material,
+                    ..Default::default()
+                });
+            }
+        }
+        self.primitives.last_mut().unwrap()
+    }
+}
+
+/// Writes a node drawing the given mesh, returning the index of the node
+pub fn push_mesh_node(
+    root: &mut json::Root,
+    mesh: json::Index<json::Mesh>,
+    name: String,
+) -> json::Index<json::Node> {
+    root.add(json::Node {
+        mesh: Some(mesh),
+        name: Some(name),
+        ..empty_node()
+    })
+}
+
+/// Writes the image and texture of `texture` unless already written, returning
+/// the texture info to draw it with
+fn write_texture_into_gltf(
+    root: &mut json::Root,
+    texture: Texture,
+    png: &[u8],
+) -> json::texture::Info {
+    let name = format!("{}", texture);
+    let image = match root
+        .images
+        .iter()
+        .position(|image| image.name.as_ref() == Some(&name))
+    {
+        Some(image) => json::Index::new(image as u32),
+        None => root.add(json::Image {
+            buffer_view: None,
+            mime_type: Some(json::image::MimeType(String::from("image/png"))),
+            name: Some(name),
+            uri: Some(buffer::png_data_uri(png)),
+            extensions: Default::default(),
+            extras: Default::default(),
+        }),
+    };
+
+    let index = match root
+        .textures
+        .iter()
+        .position(|texture| texture.source == image)
+    {
+        Some(index) => json::Index::new(index as u32),
+        None => root.add(json::Texture {
+            name: None,
+            sampler: None,
+            source: image,
+            extensions: Default::default(),
+            extras: Default::default(),
+        }),
+    };
+
+    json::texture::Info {
+        index,
+        tex_coord: 0,
+        extensions: Default::default(),
+        extras: Default::default(),
+    }
+}
+
+pub fn empty_node() -> json::Node {
+    json::Node {
+        camera: None,
+        children: None,
+        extensions: Default::default(),
+        extras: Default::default(),
+        matrix: None,
+        mesh: None,
+        name: None,
+        rotation: None,
+        scale: None,
+        translation: None,
+        skin: None,
+        weights: None,
+    }
+}
+
+pub fn read_display_list(
+    reader: &rom::Reader,
+    dlist: RawVirtAddr,
+    options: &MeshOptions,
+) -> Result<Mesh> {
+    let mut instruction_stream = InstructionStream::new(reader, dlist, options.max_dl_depth)?;
+
+    if log::log_enabled!(Level::Trace) {
+        log::trace!("Display list instructions:");
+        instruction_stream
+            .clone()
+            .flatten()
+            .for_each(display_list::dump());
+    }
+
+    let mut fold = fold(
+        reader,
+        options.keep_degenerate_triangles,
+        options.flip_winding,
+    );
+    let mut mesh = instruction_stream
+        .try_fold(Mesh::default(), |mesh, instruction| {
+            fold(mesh, instruction?)
+        })
+        .with_context(|| {
+            format!(
+                "Failed at {} of display list {} (offset {:#X})",
+                instruction_stream.last_addr().unwrap_or(dlist),
+                dlist,
+                instruction_stream.offset()
+            )
+        })?;
+    // Vertices may have been loaded without drawing anything with them
+    mesh.primitives
+        .retain(|primitive| !primitive.indices.is_empty());
+
+    if mesh.report.degenerate_triangles > 0 {
+        log::debug!(
+            "Dropped {} degenerate triangles of {}",
+            mesh.report.degenerate_triangles,
+            dlist
+        );
+    }
+    Ok(mesh)
+}
+
+/// The 32 vertex slots of the RSP that `VTX` loads into and triangles index
+#[derive(Default)]
+struct VertexCache {
+    slots: [Option<Vertex>; 32],
+
+    /// The index in the current primitive each slot was written to, once a
+    /// triangle has used it
+    written: [Option<u32>; 32],
+
+    /// The number of primitives of the mesh when `written` was filled in, as
+    /// the written vertices belong to the last of them
+    primitive_count: usize,
+
+    /// The width and height of the tile drawn with when `written` was filled
+    /// in, which the texture coordinates of the written vertices are divided by
+    tile_size: [f32; 2],
+}
+impl VertexCache {
+    const SIZE: usize = 32;
+
+    /// Sets the tile size texture coordinates are normalized by as vertices
+    /// are written, which have to be written again if it changed
+    fn set_tile_size(&mut self, tile_size: [f32; 2]) {
+        if self.tile_size != tile_size {
+            self.tile_size = tile_size;
+            self.written = Default::default();
+        }
+    }
+
+    fn load(&mut self, dest: usize, vertices: impl ExactSizeIterator<Item = Vertex>) -> Result<()> {
+        if dest + vertices.len() > Self::SIZE {
+            bail!(
+                "Loading {} vertices at slot {} overflows the vertex cache",
+                vertices.len(),
+                dest
+            );
+        }
+
+        for (slot, vertex) in (dest..).zip(vertices) {
+            self.slots[slot] = Some(vertex);
+            self.written[slot] = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the vertex in the given slot within the current
+    /// primitive of `mesh`, writing the vertex to the primitive first unless
+    /// it has been already
+    fn index(&mut self, mesh: &mut Mesh, slot: u32) -> Result<u32> {
+        // Creates the first primitive if there is none yet
+        mesh.current_primitive();
+        if self.primitive_count != mesh.primitives.len() {
+            self.primitive_count = mesh.primitives.len();
+            self.written = Default::default();
+        }
+
+        let slot = slot as usize;
+        if let Some(index) = self.written.get(slot).copied().flatten() {
+            return Ok(index);
+        }
+        let mut vertex =
+            self.slots.get(slot).cloned().flatten().with_context(|| {
+                format!("Triangle uses vertex slot {} before it is loaded", slot)
+            })?;
+        let [width, height] = self.tile_size;
+        vertex.uv = [vertex.uv[0] / width, vertex.uv[1] / height];
+
+        let primitive = mesh.current_primitive();
+        primitive.vertices.push(vertex);
+        let index = primitive.vertices.len() as u32 - 1;
+        self.written[slot] = Some(index);
+        Ok(index)
+    }
+}
+
+/// Adds a triangle of the vertices in the given slots of the vertex cache,
+/// unless it is degenerate and those are dropped
+fn push_triangle(
+    mesh: &mut Mesh,
+    vertex_cache: &mut VertexCache,
+    slots: [u32; 3],
+    keep_degenerate: bool,
+) -> Result<()> {
+    let [a, b, c] = slots;
+    if !keep_degenerate && (a == b || b == c || a == c) {
+        mesh.report.degenerate_triangles += 1;
+        return Ok(());
+    }
+
+    for slot in slots {
+        let index = vertex_cache.index(mesh, slot)?;
+        mesh.current_primitive().indices.push(index);
+    }
+    Ok(())
+}
+
+/// What SETTILE and SETTILESIZE set of one of the 8 tiles
+#[derive(Clone, Copy, Default)]
+struct TileDescriptor {
+    /// The format and texel size, unless either is one not known
+    format: Option<(texture::Format, texture::Size)>,
+
+    /// Where in TMEM the tile starts, in 64 bit words
+    tmem: u32,
+
+    /// The palette of 4 bit color indexed tiles
+    palette: u32,
+
+    /// The width and height in texels, once set by SETTILESIZE
+    size: Option<[f32; 2]>,
+}
+
+/// The state set by the display list that decides the material triangles
+/// are drawn with
+#[derive(Default)]
+struct RenderState {
+    /// The 8 tiles, and the tile drawn with
+    tiles: [TileDescriptor; 8],
+    active_tile: usize,
+
+    /// The image last loaded as texels, and the last loaded palette with the
+    /// TMEM address and count of its entries
+    texels: Option<RawVirtAddr>,
+    tlut: Option<(RawVirtAddr, u32, u32)>,
+
+    texturing: bool,
+    geometry_mode: GeometryFlags,
+    other_mode_l: OtherModeL,
+    prim_color: Option<[u8; 4]>,
+}
+impl RenderState {
+    fn lighting(&self) -> bool {
+        self.geometry_mode.contains(GeometryFlags::LIGHTING)
+    }
+
+    /// The texture of the tile drawn with, when texturing is on and the tile
+    /// and its texels are fully set
+    fn texture(&self) -> Option<Texture> {
+        if !self.texturing {
+            return None;
+        }
+        let tile = self.tiles[self.active_tile];
+        let (format, size) = tile.format?;
+        let [width, height] = tile.size?;
+
+        // Palettes are looked up from TMEM word 256 on, where 4 bit tiles
+        // pick one of 16 palettes of 16 colors
+        let first_entry = match size {
+            texture::Size::Bits4 => 256 + tile.palette * 16,
+            _ => 256,
+        };
+        let (palette, palette_len) = self
+            .tlut
+            .filter(|_| format == texture::Format::Ci)
+            .and_then(|(addr, tmem, count)| {
+                let skipped = first_entry.checked_sub(tmem)?;
+                Some((Some(addr + skipped as i32 * 2), count.checked_sub(skipped)?))
+            })
+            .unwrap_or((None, 0));
+
+        Some(Texture {
+            addr: self.texels?,
+            format,
+            size,
+            width: width as _,
+            height: height as _,
+            palette,
+            palette_len,
+        })
+    }
+
+    /// Orders the corners of a triangle counterclockwise, as glTF expects of
+    /// the front faces. With only their front faces culled, triangles show
+    /// their clockwise side instead. `flip_winding` reverses every triangle on
+    /// top of that.
+    fn wind(&self, [a, b, c]: [u32; 3], flip_winding: bool) -> [u32; 3] {
+        let front_culled = self.geometry_mode.contains(GeometryFlags::CULL_FRONT)
+            && !self.geometry_mode.contains(GeometryFlags::CULL_BACK);
+        if front_culled != flip_winding {
+            [a, c, b]
+        } else {
+            [a, b, c]
+        }
+    }
+
+    fn material(&self, texture: Option<Texture>) -> Material {
+        let alpha_mode = if self.other_mode_l.is_translucent() {
+            AlphaMode::Blend
+        } else if self.other_mode_l.is_alpha_tested() {
+            AlphaMode::Mask
+        } else {
+            AlphaMode::Opaque
+        };
+        Material {
+            alpha_mode,
+            texture,
+            double_sided: !self.geometry_mode.contains(GeometryFlags::CULL_BACK)
+                && !self.geometry_mode.contains(GeometryFlags::CULL_FRONT),
+            base_color: self.prim_color.filter(|_| texture.is_none()),
+            lighting: self.lighting(),
+        }
+    }
+}
+
+/// Switches to the primitive drawn with the current material before drawing
+/// triangles, decoding its texture on first use. Textures and tile sizes may
+/// change between loading vertices and drawing them, so the material and
+/// the texture coordinates are only known here.
+fn begin_triangles(
+    mesh: &mut Mesh,
+    reader: &rom::Reader,
+    render_state: &RenderState,
+    vertex_cache: &mut VertexCache,
+    undecodable: &mut HashSet<Texture>,
+    new_draw: &mut bool,
+) {
+    vertex_cache.set_tile_size(
+        render_state.tiles[render_state.active_tile]
+            .size
+            .unwrap_or([1.0, 1.0]),
+    );
+
+    let texture = render_state.texture().filter(|texture| {
+        if !mesh.images.contains_key(texture) && !undecodable.contains(texture) {
+            match texture.to_png(reader) {
+                Ok(png) => {
+                    mesh.images.insert(*texture, png);
+                }
+                Err(e) => {
+                    mesh.report
+                        .error(format!("Not drawing with texture {}: {:#}", texture, e));
+                    undecodable.insert(*texture);
+                }
+            }
+        }
+        mesh.images.contains_key(texture)
+    });
+
+    let primitive = mesh.primitive_with(render_state.material(texture));
+    if mem::take(new_draw)
+        && primitive.indices.len() > primitive.draw_starts.last().copied().unwrap_or(0)
+    {
+        primitive.draw_starts.push(primitive.indices.len());
+    }
+    primitive.has_normals = render_state.lighting();
+}
+
+pub fn fold(
+    reader: &rom::Reader,
+    keep_degenerate_triangles: bool,
+    flip_winding: bool,
+) -> impl FnMut(Mesh, Instruction) -> Result<Mesh> + '_ {
+    let mut vertex_cache = VertexCache::default();
+    // The modelview matrix stack, whose top transforms loaded vertices
+    let mut matrices = vec![Mat4::IDENTITY];
+    let mut render_state = RenderState::default();
+    // The image of the last SETTIMG
+    let mut timg = None::<RawVirtAddr>;
+    let mut undecodable = HashSet::new();
+    let mut new_draw = false;
     move |mut mesh, instruction| {
         match instruction.opcode() {
             Opcode::VTX => {
                 let data = Vtx::new(&instruction);
-                vertex_offset = mesh.vertices.len();
+
+                let lighting = render_state.lighting();
+                // Triangles drawn from these vertices start a new draw
+                new_draw = true;
                 let vtxs = reader
-                    .read_slice(data.addr(), data.nn() as _)
+                    .read_slice(data.addr(), data.count() as _)
                     .context("Could not read vertices")?;
 
-                for vtx in vtxs {
-                    mesh.vertices.push(Vertex::from(vtx));
+                let matrix = *matrices.last().unwrap();
+                vertex_cache.load(
+                    data.dest_index() as _,
+                    vtxs.iter().map(|vtx| {
+                        let mut vertex = Vertex::from(vtx);
+                        if lighting {
+                            vertex.color = [0xFF, 0xFF, 0xFF, vertex.color[3]];
+                        }
+                        if matrix != Mat4::IDENTITY {
+                            vertex.pos = matrix.transform_point3(vertex.pos.into()).into();
+                            vertex.normal = matrix
+                                .transform_vector3(vertex.normal.into())
+                                .try_normalize()
+                                .unwrap_or(Vec3::Z)
+                                .into();
+                        }
+                        vertex
+                    }),
+                )?;
+            }
+            Opcode::MTX => {
+                let data = display_list::Mtx::new(&instruction);
+                if data.projection() {
+                    return Ok(mesh);
                 }
+
+                // Matrices outside of the object, like the ones the game
+                // computes for limbs at runtime, leave the vertices as they are
+                let matrix = match reader.read(data.addr()) {
+                    Ok(mtx) => math::mtx_to_mat4(&mtx),
+                    Err(e) => {
+                        mesh.report
+                            .warn(format!("Ignoring matrix at {}: {:#}", data.addr(), e));
+                        Mat4::IDENTITY
+                    }
+                };
+
+                let top = *matrices.last().unwrap();
+                if data.push() {
+                    matrices.push(top);
+                }
+                *matrices.last_mut().unwrap() = if data.load() { matrix } else { top * matrix };
+            }
+            Opcode::TEXTURE => {
+                let data = display_list::Texture::new(&instruction);
+                render_state.active_tile = data.tile() as usize;
+                render_state.texturing = data.on();
+            }
+            Opcode::SETOTHERMODE_L => {
+                render_state.other_mode_l =
+                    display_list::SetOtherModeL::new(&instruction).apply(render_state.other_mode_l);
+            }
+            Opcode::GEOMETRYMODE => {
+                render_state.geometry_mode =
+                    display_list::GeometryMode::new(&instruction).apply(render_state.geometry_mode);
+            }
+            Opcode::SETPRIMCOLOR => {
+                render_state.prim_color =
+                    Some(display_list::SetPrimColor::new(&instruction).color());
+            }
+            Opcode::SETTIMG => timg = Some(display_list::SetTimg::new(&instruction).addr()),
+            Opcode::LOADBLOCK => render_state.texels = timg,
+            Opcode::LOADTLUT => {
+                let data = display_list::LoadTlut::new(&instruction);
+                let tmem = render_state.tiles[data.tile() as usize].tmem;
+                render_state.tlut = timg.map(|addr| (addr, tmem, data.count()));
+            }
+            Opcode::SETTILE => {
+                let data = display_list::SetTile::new(&instruction);
+                let tile = &mut render_state.tiles[data.tile() as usize];
+                *tile = TileDescriptor {
+                    format: texture::Format::from_u32(data.format())
+                        .zip(texture::Size::from_u32(data.siz())),
+                    tmem: data.tmem(),
+                    palette: data.palette(),
+                    ..*tile
+                };
+            }
+            Opcode::SETTILESIZE => {
+                let data = display_list::SetTileSize::new(&instruction);
+                render_state.tiles[data.tile() as usize].size = Some(data.size());
+            }
+            Opcode::POPMTX => {
+                let count = display_list::PopMtx::new(&instruction).count() as usize;
+                matrices.truncate(matrices.len().saturating_sub(count).max(1));
             }
             Opcode::TRI1 => {
                 let data = Tri1::new(&instruction);
-                mesh.indices.push(vertex_offset as u32 + data.aa());
-                mesh.indices.push(vertex_offset as u32 + data.bb());
-                mesh.indices.push(vertex_offset as u32 + data.cc());
+                begin_triangles(
+                    &mut mesh,
+                    reader,
+                    &render_state,
+                    &mut vertex_cache,
+                    &mut undecodable,
+                    &mut new_draw,
+                );
+                push_triangle(
+                    &mut mesh,
+                    &mut vertex_cache,
+                    render_state.wind([data.aa(), data.bb(), data.cc()], flip_winding),
+                    keep_degenerate_triangles,
+                )?;
             }
             Opcode::TRI2 => {
                 let data = Tri2::new(&instruction);
-                mesh.indices.push(vertex_offset as u32 + data.aa());
-                mesh.indices.push(vertex_offset as u32 + data.bb());
-                mesh.indices.push(vertex_offset as u32 + data.cc());
-                mesh.indices.push(vertex_offset as u32 + data.dd());
-                mesh.indices.push(vertex_offset as u32 + data.ee());
-                mesh.indices.push(vertex_offset as u32 + data.ff());
+                begin_triangles(
+                    &mut mesh,
+                    reader,
+                    &render_state,
+                    &mut vertex_cache,
+                    &mut undecodable,
+                    &mut new_draw,
+                );
+                for slots in [
+                    [data.aa(), data.bb(), data.cc()],
+                    [data.dd(), data.ee(), data.ff()],
+                ] {
+                    push_triangle(
+                        &mut mesh,
+                        &mut vertex_cache,
+                        render_state.wind(slots, flip_winding),
+                        keep_degenerate_triangles,
+                    )?;
+                }
             }
-            _ => (),
+            // Calls and ends of lists are followed by the instruction stream
+            Opcode::DL | Opcode::ENDDL | Opcode::SPNOOP => (),
+            opcode => *mesh.report.skipped_opcodes.entry(opcode).or_default() += 1,
         }
         Ok(mesh)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{self, object_addr, SegmentBuilder};
+
+    fn read(builder: SegmentBuilder, dlist: usize) -> Mesh {
+        read_display_list(
+            &builder.into_object_reader(),
+            RawVirtAddr::new(object_addr(dlist)),
+            &Default::default(),
+        )
+        .unwrap()
+    }
+
+    fn positions(primitive: &Primitive) -> Vec<[f32; 3]> {
+        primitive.vertices.iter().map(|vertex| vertex.pos).collect()
+    }
+
+    #[test]
+    fn skips_no_op_padding() {
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    0,
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    0,
+                    0,
+                    test_rom::g_tri1(0, 1, 2),
+                    0,
+                    test_rom::g_enddl(),
+                ],
+            );
+        let padded = read(builder, 0);
+        let unpadded = read(test_rom::triangle_list(SegmentBuilder::new(), 0, 0x100), 0);
+
+        assert!(padded.report.skipped_opcodes.is_empty());
+        assert_eq!(padded.primitives.len(), 1);
+        assert_eq!(padded.primitives[0].indices, unpadded.primitives[0].indices);
+        assert_eq!(
+            positions(&padded.primitives[0]),
+            positions(&unpadded.primitives[0])
+        );
+    }
+
+    #[test]
+    fn skips_scissor_and_primitive_depth() {
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    0xED000000_005003C0,
+                    0xEE000000_00100000,
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mesh = read(builder, 0);
+
+        assert_eq!(mesh.primitives.len(), 1);
+        assert_eq!(mesh.primitives[0].indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn gives_every_face_its_own_vertices_with_flat_normals() {
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 0, 100], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    test_rom::g_vtx(object_addr(0x100), 4, 0),
+                    test_rom::g_tri2([0, 1, 2], [0, 3, 1]),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mut mesh = read(builder, 0);
+        assert_eq!(mesh.primitives[0].vertices.len(), 4);
+
+        mesh.apply_options(&MeshOptions {
+            flat_normals: true,
+            ..Default::default()
+        });
+        let primitive = &mesh.primitives[0];
+        assert_eq!(primitive.indices.len(), 6);
+        assert_eq!(primitive.vertices.len(), primitive.indices.len());
+
+        let normals = primitive
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let normals = [0, 1, 2].map(|i| primitive.vertices[triangle[i] as usize].normal);
+                assert!(normals.iter().all(|normal| *normal == normals[0]));
+                normals[0]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(normals, [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0]]);
+    }
+
+    #[test]
+    fn decodes_normals_of_lit_vertices() {
+        let vertices = [
+            ([0, 0, 0], [0, 0], [0x7F, 0, 0, 0xFF]),
+            ([100, 0, 0], [0, 0], [0, 0x81, 0, 0xFF]),
+            ([0, 100, 0], [0, 0], [0, 0, 0x40, 0xFF]),
+        ];
+        let draw = [
+            test_rom::g_vtx(object_addr(0x100), 3, 0),
+            test_rom::g_tri1(0, 1, 2),
+            test_rom::g_enddl(),
+        ];
+
+        let lit = read(
+            SegmentBuilder::new()
+                .vertices(0x100, &vertices)
+                .commands(0, &draw),
+            0,
+        );
+        let primitive = &lit.primitives[0];
+        assert!(primitive.has_normals);
+        let normals = primitive
+            .vertices
+            .iter()
+            .map(|vertex| vertex.normal)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            normals,
+            [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]]
+        );
+
+        // gsSPClearGeometryMode(G_LIGHTING) makes `cn` a color
+        let unlit = read(
+            SegmentBuilder::new()
+                .vertices(0x100, &vertices)
+                .commands(0, &[0xD9FDFFFF_00000000])
+                .commands(8, &draw),
+            0,
+        );
+        let primitive = &unlit.primitives[0];
+        assert!(!primitive.has_normals);
+        assert_eq!(primitive.vertices[1].color, [0, 0x81, 0, 0xFF]);
+    }
+
+    #[test]
+    fn draws_past_culling_commands() {
+        // gsSPCullDisplayList(0, 2)
+        let cull = 0x03000000_00000004;
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    cull,
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let reader = builder.clone().into_object_reader();
+        let dlist = reader.slice_from(RawVirtAddr::new(object_addr(0))).unwrap();
+        assert_eq!(display_list::display_list_len(dlist), Some(0x20));
+
+        let mesh = read(builder, 0);
+        assert_eq!(mesh.primitives.len(), 1);
+        assert_eq!(mesh.primitives[0].indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn scales_texture_coordinates_by_the_configured_factor() {
+        let builder = SegmentBuilder::new().vertices(
+            0x100,
+            &[
+                ([0, 0, 0], [64, -32], [0, 0, 127, 0xFF]),
+                ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+            ],
+        );
+        let builder = builder.commands(
+            0,
+            &[
+                test_rom::g_vtx(object_addr(0x100), 3, 0),
+                test_rom::g_tri1(0, 1, 2),
+                test_rom::g_enddl(),
+            ],
+        );
+
+        let mut mesh = read(builder.clone(), 0);
+        mesh.apply_options(&Default::default());
+        assert_eq!(mesh.primitives[0].vertices[0].uv, [2.0, -1.0]);
+
+        let mut mesh = read(builder, 0);
+        mesh.apply_options(&MeshOptions {
+            texcoord_scale: 1.0 / 1024.0,
+            ..Default::default()
+        });
+        assert_eq!(mesh.primitives[0].vertices[0].uv, [0.0625, -0.03125]);
+
+        let mut root = json::Root::default();
+        mesh.write_into_gltf(&mut root, "mesh");
+        let attributes = &root.meshes[0].primitives[0].attributes;
+        let uvs = test_rom::accessor_elements(
+            &root,
+            attributes[&Valid(json::mesh::Semantic::TexCoords(0))],
+        );
+        assert_eq!(uvs[0], [0.0625f32, -0.03125].as_bytes());
+    }
+
+    #[test]
+    fn writes_every_draw_as_its_own_node() {
+        let triangle = [
+            ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+            ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+            ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+        ];
+        let builder = SegmentBuilder::new()
+            .vertices(0x100, &triangle)
+            .vertices(0x130, &triangle)
+            .commands(
+                0,
+                &[
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_vtx(object_addr(0x130), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mesh = read(builder, 0);
+        let options = MeshOptions {
+            split_draws: true,
+            ..Default::default()
+        };
+
+        let mut root = json::Root::default();
+        let node = mesh.write_node_into_gltf(&mut root, "dlist", &options);
+        let children = root.nodes[node.value()].children.clone().unwrap();
+        let names = children
+            .iter()
+            .map(|child| root.nodes[child.value()].name.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["dlist_draw_0", "dlist_draw_1"]);
+        assert_eq!(root.meshes.len(), 2);
+        for child in &children {
+            assert!(root.nodes[child.value()].mesh.is_some());
+        }
+    }
+
+    #[test]
+    fn transforms_vertices_by_the_loaded_matrix() {
+        // A translation by (10, 20, 0), in the fixed point halves of `Mtx`
+        let mut int_part = [0; 16];
+        for (i, n) in [(0, 1), (5, 1), (10, 1), (12, 10), (13, 20), (15, 1)] {
+            int_part[i] = n;
+        }
+        let builder = SegmentBuilder::new()
+            .i16s(0x200, &int_part)
+            .u16s(0x220, &[0; 16]);
+        let builder = test_rom::triangle_list(builder, 0x08, 0x100).commands(
+            0,
+            // gsSPMatrix(0x06000200, G_MTX_MODELVIEW | G_MTX_LOAD | G_MTX_PUSH)
+            &[0xDA380002_00000000 | object_addr(0x200) as u64],
+        );
+        let mesh = read(builder, 0);
+
+        assert_eq!(
+            positions(&mesh.primitives[0]),
+            [[10.0, 20.0, 0.0], [110.0, 20.0, 0.0], [10.0, 120.0, 0.0]]
+        );
+    }
+
+    #[test]
+    fn normalizes_texture_coordinates_by_the_tile_size() {
+        let uv = |width, height| {
+            let builder = SegmentBuilder::new()
+                .vertices(
+                    0x100,
+                    &[
+                        ([0, 0, 0], [1024, 512], [0, 0, 127, 0xFF]),
+                        ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                        ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ],
+                )
+                .commands(
+                    0,
+                    &[
+                        test_rom::g_settilesize(0, width, height),
+                        test_rom::g_vtx(object_addr(0x100), 3, 0),
+                        test_rom::g_tri1(0, 1, 2),
+                        test_rom::g_enddl(),
+                    ],
+                );
+            let mut mesh = read(builder, 0);
+            mesh.apply_options(&Default::default());
+            mesh.primitives[0].vertices[0].uv
+        };
+
+        assert_eq!(uv(32, 32), [1.0, 0.5]);
+        assert_eq!(uv(64, 16), [0.5, 1.0]);
+    }
+
+    #[test]
+    fn normalizes_texture_coordinates_by_the_tile_size_set_after_loading() {
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [1024, 512], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    test_rom::g_settilesize(0, 32, 16),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_settilesize(0, 64, 64),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mut mesh = read(builder, 0);
+        mesh.apply_options(&Default::default());
+
+        let primitive = &mesh.primitives[0];
+        let uvs = primitive
+            .indices
+            .iter()
+            .step_by(3)
+            .map(|index| primitive.vertices[*index as usize].uv)
+            .collect::<Vec<_>>();
+        assert_eq!(uvs, [[1.0, 1.0], [0.5, 0.25]]);
+    }
+
+    #[test]
+    fn draws_with_the_texture_of_the_tile() {
+        let builder = SegmentBuilder::new()
+            .u16s(0x200, &[0xF801, 0x07C1])
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [64, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 32], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    // gsSPTexture(0xFFFF, 0xFFFF, 0, G_TX_RENDERTILE, G_ON)
+                    0xD7000002_FFFFFFFF,
+                    // An RGBA16 image of 2x1 texels
+                    0xFD100000_00000000 | object_addr(0x200) as u64,
+                    0xF3000000_07000000,
+                    0xF5100000_00000000,
+                    test_rom::g_settilesize(0, 2, 1),
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mesh = read(builder, 0);
+
+        let texture = mesh.primitives[0].material.texture.unwrap();
+        assert_eq!(texture.addr, RawVirtAddr::new(object_addr(0x200)));
+        assert_eq!(
+            (texture.format, texture.size),
+            (texture::Format::Rgba, texture::Size::Bits16)
+        );
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert!(mesh.images[&texture].starts_with(b"\x89PNG"));
+
+        let mut root = json::Root::default();
+        mesh.write_into_gltf(&mut root, "mesh");
+        assert_eq!((root.images.len(), root.textures.len()), (1, 1));
+        let material = &root.materials[root.meshes[0].primitives[0].material.unwrap().value()];
+        let texture = material.pbr_metallic_roughness.base_color_texture.as_ref();
+        assert_eq!(texture.unwrap().index.value(), 0);
+    }
+
+    #[test]
+    fn resolves_triangles_through_the_vertex_cache() {
+        let builder = SegmentBuilder::new()
+            .vertices(
+                0x100,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0,
+                &[
+                    test_rom::g_vtx(object_addr(0x100), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    // Replaces only slot 1, keeping slots 0 and 2
+                    test_rom::g_vtx(object_addr(0x130), 1, 1),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            );
+        let mesh = read(builder, 0);
+
+        let primitive = &mesh.primitives[0];
+        let triangles = primitive
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| primitive.vertices[triangle[i] as usize].pos))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triangles,
+            [
+                [[0.0, 0.0, 0.0], [100.0, 0.0, 0.0], [0.0, 100.0, 0.0]],
+                [[0.0, 0.0, 0.0], [100.0, 100.0, 0.0], [0.0, 100.0, 0.0]],
+            ]
+        );
+        assert_eq!(primitive.vertices.len(), 4);
+    }
+
+    #[test]
+    fn folds_vertices_and_triangles_into_a_primitive() {
+        let mesh = read(test_rom::triangle_list(SegmentBuilder::new(), 0, 0x100), 0);
+
+        assert_eq!(mesh.primitives.len(), 1);
+        assert_eq!(mesh.primitives[0].indices, [0, 1, 2]);
+        assert_eq!(
+            positions(&mesh.primitives[0]),
+            [[0.0, 0.0, 0.0], [100.0, 0.0, 0.0], [0.0, 100.0, 0.0]]
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use crate::display_list::Opcode;
+
+/// What an extraction produced and what it had to leave out, for callers
+/// that need more than the log
+#[derive(Default, Debug, Clone)]
+pub struct ExtractionReport {
+    pub limbs: usize,
+    pub meshes: usize,
+    pub animations: usize,
+
+    /// How often each opcode was passed over without being interpreted
+    pub skipped_opcodes: BTreeMap<Opcode, usize>,
+
+    /// Triangles dropped for not having three distinct vertices
+    pub degenerate_triangles: usize,
+
+    /// Unexpected data that was worked around
+    pub warnings: Vec<String>,
+
+    /// Data that could not be read and was left out
+    pub errors: Vec<String>,
+}
+impl ExtractionReport {
+    /// Logs and records a warning
+    pub fn warn(&mut self, message: String) {
+        log::warn!("{}", message);
+        self.warnings.push(message);
+    }
+
+    /// Logs and records an error. Errors that abort the extraction are
+    /// returned instead.
+    pub fn error(&mut self, message: String) {
+        log::warn!("{}", message);
+        self.errors.push(message);
+    }
+
+    pub fn merge(&mut self, other: ExtractionReport) {
+        self.limbs += other.limbs;
+        self.meshes += other.meshes;
+        self.animations += other.animations;
+        for (opcode, count) in other.skipped_opcodes {
+            *self.skipped_opcodes.entry(opcode).or_default() += count;
+        }
+        self.degenerate_triangles += other.degenerate_triangles;
+        self.warnings.extend(other.warnings);
+        self.errors.extend(other.errors);
+    }
+}
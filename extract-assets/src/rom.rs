@@ -1,22 +1,32 @@
 use std::{
-    fmt::Debug,
-    io,
+    any,
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Display},
+    fs,
+    io::{self, Read as _},
+    iter, mem,
     ops::{Deref, Range},
+    path::Path,
+    rc::Rc,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use num_derive::FromPrimitive;
 use zerocopy::{AsBytes, BigEndian, FromBytes, LayoutVerified};
 
-use crate::addr::{RawVirtAddr, VirtAddr};
+use crate::{
+    addr::{PhysAddr, RawVirtAddr, VirtAddr},
+    dmadata, version, yaz0,
+};
 
+#[derive(Clone, Copy)]
 pub enum Segment {
-    _Scene = 2,
-    _Room = 3,
+    Scene = 2,
+    Room = 3,
     _Keep = 4,
     _FieldDungeonKeep = 5,
     Object = 6,
-    _LinkAnimation = 7,
+    LinkAnimation = 7,
     IconItemStatic = 8,
 }
 
@@ -26,89 +36,598 @@ pub enum SkinLimbType {
     Normal = 11,
 }
 
+/// A whole ROM whose files are loaded on demand through dmadata
+#[derive(Clone)]
+struct Rom {
+    data: Rc<Vec<u8>>,
+    files: Vec<DmaEntry>,
+
+    /// The data of each loaded file, along with its ROM offset unless it was
+    /// decompressed
+    cache: HashMap<usize, (Vec<u8>, Option<u32>)>,
+
+    /// The object table, once it has been read
+    object_table: Option<Vec<Range<u32>>>,
+}
+impl Rom {
+    /// Reads and decompresses a dmadata file, along with its ROM offset
+    /// unless it was decompressed. The file is not cached.
+    fn read_file(&self, index: usize) -> Result<(Vec<u8>, Option<u32>)> {
+        let file = self
+            .files
+            .get(index)
+            .with_context(|| format!("File {} is not in dmadata", index))?;
+
+        log::debug!("Loading file {} from {:?}", index, file.rom_range());
+        let range = file.rom_range();
+        let mut data = read_range(&mut io::Cursor::new(&self.data[..]), range.clone())
+            .with_context(|| format!("Failed to load file {}", index))?;
+        let compressed = data.starts_with(yaz0::MAGIC);
+        if compressed {
+            data = yaz0::decompress(&data)
+                .with_context(|| format!("Failed to decompress file {}", index))?;
+        }
+
+        log::debug!(
+            "Loaded file {} with checksum {:08X}",
+            index,
+            crc32fast::hash(&data)
+        );
+        Ok((data, (!compressed).then_some(range.start)))
+    }
+
+    /// The virtual ROM range of every file in dmadata
+    fn file_ranges(&self) -> HashSet<(u32, u32)> {
+        self.files
+            .iter()
+            .map(|file| (file.vrom_start.get(), file.vrom_end.get()))
+            .collect()
+    }
+
+    /// Reads the object table starting at a virtual ROM address
+    fn object_table_at(&self, vrom: u32) -> Result<Vec<Range<u32>>> {
+        let index = self
+            .files
+            .iter()
+            .position(|file| (file.vrom_start.get()..file.vrom_end.get()).contains(&vrom))
+            .context("The address is in no file")?;
+        let data = match self.cache.get(&index) {
+            Some((data, _)) => data.clone(),
+            None => self.read_file(index)?.0,
+        };
+
+        let offset = (vrom - self.files[index].vrom_start.get()) as usize;
+        let entries = word_pairs(data.get(offset..).unwrap_or_default());
+        match find_object_table(&entries, &self.file_ranges()) {
+            Some((0, table)) => Ok(table),
+            _ => bail!("The entries there are not the files of dmadata"),
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct Reader {
     segments: [Option<Vec<u8>>; 16],
+
+    /// The ROM offset each segment was read from, unless it was decompressed
+    /// or set directly
+    segment_offsets: [Option<u32>; 16],
+    rom: Option<Rom>,
 }
 impl Reader {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Reads a whole ROM and its dmadata, so that files can later be loaded
+    /// into segments by index with `load_file`
+    pub fn from_rom(path: impl AsRef<Path>) -> Result<Self> {
+        let mut data = fs::read(path.as_ref())
+            .with_context(|| format!("Failed to read ROM {}", path.as_ref().display()))?;
+        if let Some(byte_order) = data
+            .first_chunk()
+            .and_then(|magic| ByteOrder::detect(*magic))
+            .filter(|byte_order| *byte_order != ByteOrder::BigEndian)
+        {
+            log::info!("Converting {:?} ROM to big-endian", byte_order);
+            byte_order.normalize(&mut data);
+        }
+        let files = dmadata::read(&data)?;
+
+        let mut reader = Self::new();
+        reader.rom = Some(Rom {
+            data: Rc::new(data),
+            files,
+            cache: HashMap::new(),
+            object_table: None,
+        });
+        Ok(reader)
+    }
+
+    /// The whole ROM in big-endian order, if a ROM was loaded
+    pub fn rom_data(&self) -> Option<&[u8]> {
+        self.rom.as_ref().map(|rom| &rom.data[..])
+    }
+
+    /// The header of the ROM, if a ROM was loaded
+    pub fn rom_header(&self) -> Option<RomHeader> {
+        let rom = self.rom.as_ref()?;
+        LayoutVerified::<_, RomHeader>::new_from_prefix(&rom.data[..]).map(|(lv, _)| lv.read())
+    }
+
+    /// The number of files in dmadata, or 0 if no ROM was loaded
+    pub fn file_count(&self) -> usize {
+        self.rom.as_ref().map_or(0, |rom| rom.files.len())
+    }
+
+    /// Returns the index of the dmadata file starting at the given virtual
+    /// ROM address
+    pub fn find_file(&self, vrom_start: u32) -> Option<usize> {
+        self.rom.as_ref().and_then(|rom| {
+            rom.files
+                .iter()
+                .position(|file| file.vrom_start.get() == vrom_start)
+        })
+    }
+
+    /// Loads a dmadata file into a segment. Files are read from the ROM on
+    /// first use and cached for subsequent loads.
+    pub fn load_file(&mut self, segment: Segment, index: usize) -> Result<()> {
+        let rom = self
+            .rom
+            .as_mut()
+            .context("Files can only be loaded from a reader created with from_rom")?;
+
+        let (data, offset) = match rom.cache.get(&index) {
+            Some(file) => {
+                log::debug!("Using cached file {}", index);
+                file.clone()
+            }
+            None => {
+                let file = rom.read_file(index)?;
+                rom.cache.insert(index, file.clone());
+                file
+            }
+        };
+
+        self.set_segment(segment, Some(data));
+        self.segment_offsets[segment as usize] = offset;
+        Ok(())
+    }
+
+    /// Loads the object with the given ID into the object segment, looking
+    /// up its file in the object table
+    pub fn load_object(&mut self, object_id: u16) -> Result<()> {
+        let table = self.object_table()?;
+        let range = table
+            .get(object_id as usize)
+            .with_context(|| format!("Object {:#06X} is not in the object table", object_id))?;
+        if range.is_empty() {
+            bail!("Object {:#06X} is unused", object_id);
+        }
+        let index = self
+            .find_file(range.start)
+            .with_context(|| format!("File of object {:#06X} not found in dmadata", object_id))?;
+
+        log::info!("Loading object {:#06X} from file {}", object_id, index);
+        self.load_file(Segment::Object, index)
+    }
+
+    /// The virtual ROM range of every object by ID, empty for unused IDs.
+    ///
+    /// The table is read at its known address in the detected version, so it
+    /// can't be read from versions whose address isn't known. It is read once
+    /// and kept for later calls.
+    pub fn object_table(&mut self) -> Result<Vec<Range<u32>>> {
+        let rom = self
+            .rom
+            .as_ref()
+            .context("The object table can only be read from a reader created with from_rom")?;
+        if let Some(table) = &rom.object_table {
+            return Ok(table.clone());
+        }
+
+        let version = version::detect_version(self)?;
+        let vrom = version.object_table_vrom().with_context(|| {
+            format!(
+                "The object table of {} is not known, load objects by their ROM range instead",
+                version
+            )
+        })?;
+        let table = rom.object_table_at(vrom).with_context(|| {
+            format!(
+                "No object table at {:#010X}, where {} has it",
+                vrom, version
+            )
+        })?;
+
+        if let Some(rom) = self.rom.as_mut() {
+            rom.object_table = Some(table.clone());
+        }
+        Ok(table)
+    }
+
     pub fn read_segment<R: io::Read + io::Seek>(
         &mut self,
         segment: Segment,
         r: &mut R,
         range: Range<u32>,
     ) -> Result<()> {
-        let mut buf = vec![0u8; range.len()];
-        r.seek(io::SeekFrom::Start(range.start as u64))?;
-        r.read_exact(&mut buf)?;
+        let start = range.start;
+        let buf = read_range(r, range)?;
 
         self.set_segment(segment, Some(buf));
+        self.segment_offsets[segment as usize] = Some(start);
+
+        Ok(())
+    }
+
+    /// Like `read_segment`, but decompresses the range when it starts with
+    /// the Yaz0 magic
+    pub fn read_compressed_segment<R: io::Read + io::Seek>(
+        &mut self,
+        segment: Segment,
+        r: &mut R,
+        range: Range<u32>,
+    ) -> Result<()> {
+        self.read_compressed_segment_raw(segment as u8, r, range)
+    }
+
+    /// Like `read_compressed_segment`, for any of the 16 segments
+    pub fn read_compressed_segment_raw<R: io::Read + io::Seek>(
+        &mut self,
+        number: u8,
+        r: &mut R,
+        range: Range<u32>,
+    ) -> Result<()> {
+        let start = range.start;
+        let mut buf = read_range(r, range)?;
+        let compressed = buf.starts_with(yaz0::MAGIC);
+        if compressed {
+            buf = yaz0::decompress(&buf)?;
+        }
 
+        self.set_segment_raw(number, Some(buf))?;
+        self.segment_offsets[number as usize] = (!compressed).then_some(start);
+
+        Ok(())
+    }
+
+    /// Loads several objects at once, each into its own segment, for models
+    /// that refer to data in more than one object
+    pub fn read_objects<R: io::Read + io::Seek>(
+        &mut self,
+        r: &mut R,
+        objects: &[(u8, Range<u32>)],
+    ) -> Result<()> {
+        for (number, range) in objects {
+            self.read_compressed_segment_raw(*number, r, range.clone())
+                .with_context(|| format!("Failed to load object into segment {}", number))?;
+        }
         Ok(())
     }
 
+    /// CRC32 of the data loaded into a segment, to tell whether two loads
+    /// produced the same data
+    pub fn segment_checksum(&self, number: u32) -> Option<u32> {
+        self.segments
+            .get(number as usize)?
+            .as_ref()
+            .map(|data| crc32fast::hash(data))
+    }
+
     pub fn set_segment(&mut self, segment: Segment, data: Option<Vec<u8>>) {
         self.segments[segment as usize] = data;
+        self.segment_offsets[segment as usize] = None;
+    }
+
+    /// The number and data of every segment holding data
+    pub fn segments(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(number, data)| Some((number as u8, data.as_deref()?)))
+    }
+
+    /// Like `set_segment`, for any of the 16 segments, including those
+    /// without a `Segment` variant
+    pub fn set_segment_raw(&mut self, number: u8, data: Option<Vec<u8>>) -> Result<()> {
+        if number as usize >= self.segments.len() {
+            bail!("Segment {} is out of range, there are 16 segments", number);
+        }
+        self.segments[number as usize] = data;
+        self.segment_offsets[number as usize] = None;
+
+        Ok(())
+    }
+
+    /// The data of segment `number`, if it has been set
+    pub fn segment_raw(&self, number: u8) -> Option<&[u8]> {
+        self.segments.get(number as usize)?.as_deref()
     }
 
-    pub fn read<T>(&self, addr: VirtAddr<T>) -> Result<T>
+    /// The ROM offset of the data at `addr`, to find it in a hex editor. Not
+    /// known for segments that were decompressed or set directly.
+    pub fn phys_addr_of(&self, addr: RawVirtAddr) -> Option<PhysAddr> {
+        let data = self.segments[addr.segment_number() as usize].as_ref()?;
+        if addr.segment_offset() as usize >= data.len() {
+            return None;
+        }
+        addr.to_phys(&self.segment_offsets)
+    }
+
+    pub fn read<T>(&self, addr: VirtAddr<T>) -> Result<T, RomError>
     where
         T: FromBytes,
     {
         log::trace!("Reading struct at {}", addr);
         let (lv, _) = LayoutVerified::<_, T>::new_from_prefix(self.slice_from(addr.into())?)
-            .with_context(|| format!("Failed to read item from address {}", addr))?;
+            .ok_or_else(|| RomError::LayoutMismatch {
+                addr: addr.into(),
+                type_name: any::type_name::<T>(),
+            })?;
 
         Ok(lv.read())
     }
 
-    pub fn read_slice<T>(&self, addr: VirtAddr<T>, count: usize) -> Result<&[T]>
+    pub fn read_slice<T>(&self, addr: VirtAddr<T>, count: usize) -> Result<&[T], RomError>
     where
         T: FromBytes,
     {
         log::trace!("Reading slice of count {} at {}", count, addr);
         let (lv, _) =
             LayoutVerified::<_, [T]>::new_slice_from_prefix(self.slice_from(addr.into())?, count)
-                .with_context(|| format!("Failed to read slice at {}", addr))?;
+                .ok_or_else(|| RomError::LayoutMismatch {
+                addr: addr.into(),
+                type_name: any::type_name::<[T]>(),
+            })?;
 
         Ok(lv.into_slice())
     }
 
+    /// Reads the items pointed to by an array of pointers. Items whose
+    /// pointer is NULL or cannot be read are yielded as `None`, so that the
+    /// remaining items keep their index in the array.
     pub fn ptr_slice_iter<'a, T>(
         &'a self,
         addr: VirtAddr<VirtAddr<T>>,
         count: usize,
-    ) -> Result<impl Iterator<Item = T> + 'a>
+    ) -> Result<impl Iterator<Item = Option<T>> + 'a, RomError>
     where
         T: FromBytes + 'a,
     {
         self.read_slice(addr, count).map(|addrs| {
-            addrs
-                .into_iter()
-                .flat_map(|addr| self.read::<T>(*addr).into_iter())
+            addrs.iter().map(|addr| {
+                if RawVirtAddr::from(*addr).is_null() {
+                    return None;
+                }
+                self.read::<T>(*addr)
+                    .map_err(|e| log::warn!("Failed to read item at {}: {:#}", addr, e))
+                    .ok()
+            })
         })
     }
 
-    pub fn slice_from(&self, addr: RawVirtAddr) -> Result<&[u8]> {
-        let number = addr.segment_number();
-        let offset = addr.segment_offset();
+    /// Iterates over consecutive items from `addr` up to the end of its
+    /// segment, for data ended by a sentinel rather than preceded by a count
+    pub fn iter_from<'a, T>(
+        &'a self,
+        addr: VirtAddr<T>,
+    ) -> impl Iterator<Item = Result<T, RomError>> + 'a
+    where
+        T: FromBytes + 'a,
+    {
+        self.segment_iter_addr(addr)
+            .map(|item| item.map(|(_, item)| item))
+    }
 
-        self.segments[number as usize]
+    /// Iterates over consecutive items from `addr` up to the end of its
+    /// segment, yielding each item along with the address it was read from
+    pub fn segment_iter_addr<'a, T>(
+        &'a self,
+        addr: VirtAddr<T>,
+    ) -> impl Iterator<Item = Result<(RawVirtAddr, T), RomError>> + 'a
+    where
+        T: FromBytes + 'a,
+    {
+        let mut next = Some(addr);
+        iter::from_fn(move || {
+            let addr = next.take()?;
+            match self.slice_from(addr.into()) {
+                Err(e) => Some(Err(e)),
+                Ok(data) if data.len() < mem::size_of::<T>() => None,
+                Ok(_) => {
+                    next = Some(addr + 1);
+                    Some(self.read(addr).map(|item| (addr.into(), item)))
+                }
+            }
+        })
+    }
+
+    /// Like `slice_from`, but warns when the display list does not start on
+    /// an 8-byte instruction boundary within its segment
+    pub fn display_list_from(&self, addr: RawVirtAddr) -> Result<&[u8], RomError> {
+        if !addr.segment_offset().is_multiple_of(8) {
+            log::warn!(
+                "Display list at {:?} is not aligned to 8 bytes, instructions will be misread",
+                addr
+            );
+        }
+
+        self.slice_from(addr)
+    }
+
+    pub fn slice_from(&self, addr: RawVirtAddr) -> Result<&[u8], RomError> {
+        let segment = addr.segment_number();
+        let data = self.segments[segment as usize]
             .as_ref()
-            .map(|data| &data[offset as usize..])
-            .with_context(|| format!("Segment {} has not been set", number))
+            .ok_or(RomError::SegmentNotSet { segment })?;
+
+        data.get(addr.segment_offset() as usize..)
+            .ok_or(RomError::OutOfBounds {
+                addr,
+                len: data.len(),
+            })
+    }
+}
+
+/// Why data could not be read from a segmented address
+#[derive(Debug)]
+pub enum RomError {
+    SegmentNotSet {
+        segment: u32,
+    },
+    /// The address lies past the end of its segment, which is `len` bytes
+    OutOfBounds {
+        addr: RawVirtAddr,
+        len: usize,
+    },
+    /// Too few bytes are left in the segment for the item to read
+    LayoutMismatch {
+        addr: RawVirtAddr,
+        type_name: &'static str,
+    },
+}
+impl Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::SegmentNotSet { segment } => {
+                write!(f, "Segment {} has not been set", segment)
+            }
+            RomError::OutOfBounds { addr, len } => write!(
+                f,
+                "Offset {:#X} is out of bounds of segment {} (length {:#X})",
+                addr.segment_offset(),
+                addr.segment_number(),
+                len
+            ),
+            RomError::LayoutMismatch { addr, type_name } => {
+                write!(f, "Failed to read {} from address {}", type_name, addr)
+            }
+        }
+    }
+}
+impl std::error::Error for RomError {}
+
+/// Finds the object table among pairs of words, as the empty entry of object
+/// 0 followed by entries that are dmadata files, up to the first entry that
+/// is neither. Unused objects have empty entries. Returns the index of the
+/// entry the table starts at, along with the table.
+fn find_object_table(
+    entries: &[(u32, u32)],
+    files: &HashSet<(u32, u32)>,
+) -> Option<(usize, Vec<Range<u32>>)> {
+    // Enough entries to rule out a chance match
+    const MIN_ENTRIES: usize = 16;
+
+    let start = (0..entries.len().saturating_sub(MIN_ENTRIES)).find(|start| {
+        entries[*start] == (0, 0)
+            && entries[start + 1..=start + MIN_ENTRIES]
+                .iter()
+                .all(|entry| files.contains(entry))
+    })?;
+
+    let mut table = entries[start..]
+        .iter()
+        .take_while(|entry| **entry == (0, 0) || files.contains(entry))
+        .map(|(vrom_start, vrom_end)| *vrom_start..*vrom_end)
+        .collect::<Vec<_>>();
+    while table.last().is_some_and(|range| range.is_empty()) {
+        table.pop();
+    }
+    Some((start, table))
+}
+
+/// The big-endian words of `data` in pairs
+fn word_pairs(data: &[u8]) -> Vec<(u32, u32)> {
+    data.chunks_exact(8)
+        .map(|entry| {
+            let word = |i: usize| u32::from_be_bytes(entry[i..i + 4].try_into().unwrap());
+            (word(0), word(4))
+        })
+        .collect()
+}
+
+/// Reads a range of a ROM in big-endian order, whatever order the ROM is in
+fn read_range<R: io::Read + io::Seek>(r: &mut R, range: Range<u32>) -> Result<Vec<u8>> {
+    ensure!(
+        range.start <= range.end,
+        "File range 0x{:X}..0x{:X} ends before it starts",
+        range.start,
+        range.end
+    );
+
+    let mut magic = [0; 4];
+    r.seek(io::SeekFrom::Start(0))?;
+    r.read_exact(&mut magic)?;
+    let byte_order = ByteOrder::detect(magic).unwrap_or(ByteOrder::BigEndian);
+
+    // Words are swapped as a whole, so the range is widened to whole words
+    let word_size = byte_order.word_size() as u32;
+    let start = range.start - range.start % word_size;
+    let end = range.end.next_multiple_of(word_size);
+
+    let mut buf = vec![0u8; (range.end - start) as usize];
+    r.seek(io::SeekFrom::Start(start as u64))?;
+    r.read_exact(&mut buf)?;
+
+    // The last word of a file may be cut short, so it's padded with zeros
+    r.by_ref()
+        .take((end - range.end) as u64)
+        .read_to_end(&mut buf)?;
+    buf.resize((end - start) as usize, 0);
+    byte_order.normalize(&mut buf);
+
+    let skipped = (range.start - start) as usize;
+    Ok(buf[skipped..skipped + range.len()].to_vec())
+}
+
+/// The byte orders ROMs are dumped in, told apart by their first word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// `.z64`, the order the console reads in
+    BigEndian,
+    /// `.v64`, with the bytes of every 16 bit word swapped
+    ByteSwapped,
+    /// `.n64`, with every 32 bit word little-endian
+    LittleEndian,
+}
+impl ByteOrder {
+    pub fn detect(magic: [u8; 4]) -> Option<Self> {
+        match u32::from_be_bytes(magic) {
+            0x80371240 => Some(Self::BigEndian),
+            0x37804012 => Some(Self::ByteSwapped),
+            0x40123780 => Some(Self::LittleEndian),
+            _ => None,
+        }
+    }
+
+    fn word_size(&self) -> usize {
+        match self {
+            Self::BigEndian => 1,
+            Self::ByteSwapped => 2,
+            Self::LittleEndian => 4,
+        }
+    }
+
+    /// Reorders data that starts on a word boundary to big-endian
+    pub fn normalize(&self, data: &mut [u8]) {
+        match self {
+            Self::BigEndian => (),
+            Self::ByteSwapped => data.chunks_exact_mut(2).for_each(|word| word.swap(0, 1)),
+            Self::LittleEndian => data.chunks_exact_mut(4).for_each(|word| word.reverse()),
+        }
     }
 }
 
-type U16 = zerocopy::U16<BigEndian>;
+pub type U16 = zerocopy::U16<BigEndian>;
 const _: () = assert!(std::mem::size_of::<U16>() == 0x02);
-type I16 = zerocopy::I16<BigEndian>;
+pub type I16 = zerocopy::I16<BigEndian>;
 const _: () = assert!(std::mem::size_of::<I16>() == 0x02);
-type I32 = zerocopy::I32<BigEndian>;
+pub type I32 = zerocopy::I32<BigEndian>;
 const _: () = assert!(std::mem::size_of::<I32>() == 0x04);
+pub type U32 = zerocopy::U32<BigEndian>;
+const _: () = assert!(std::mem::size_of::<U32>() == 0x04);
 
 type Gfx = RawVirtAddr;
 
@@ -157,6 +676,15 @@ pub struct SkeletonHeader {
     pub limb_count: u8,
 }
 
+/// Rotation limits of a joint in binary angles, as kept in a table holding
+/// one entry per limb
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+pub struct JointLimits {
+    pub min: [I16; 3],
+    pub max: [I16; 3],
+}
+
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 pub struct SkinLimb {
@@ -172,6 +700,19 @@ pub struct SkinLimb {
 }
 const _: () = assert!(std::mem::size_of::<SkinLimb>() == 0x10);
 
+/// Limb of a curve skeleton (see SkelCurveLimb)
+#[derive(Debug, FromBytes)]
+#[repr(C)]
+pub struct CurveLimb {
+    pub child: u8,
+    pub sibling: u8,
+
+    /// The opaque display list followed by the translucent (XLU) display list,
+    /// either of which may be NULL
+    pub dlists: Aligned4<[Gfx; 2]>,
+}
+const _: () = assert!(std::mem::size_of::<CurveLimb>() == 0xC);
+
 #[derive(Debug, FromBytes)]
 #[repr(C)]
 pub struct SkinAnimatedLimbData {
@@ -191,6 +732,16 @@ pub struct Vtx {
     pub cn: [u8; 4],
 }
 
+/// A matrix in s15.16 fixed point, with the integer parts of all elements
+/// followed by the fractional parts
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct Mtx {
+    pub int_part: [I16; 16],
+    pub frac_part: [U16; 16],
+}
+const _: () = assert!(std::mem::size_of::<Mtx>() == 0x40);
+
 #[derive(FromBytes, Debug)]
 #[repr(C)]
 pub struct SkinLimbModif {
@@ -242,6 +793,16 @@ pub struct AnimationHeader {
 }
 const _: () = assert!(std::mem::size_of::<AnimationHeader>() == 0x10);
 
+/// The header of one of Link's animations, whose frames are stored whole in
+/// the link animation segment rather than the object
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct LinkAnimationHeader {
+    pub common: AnimationHeaderCommon,
+    pub segment: VirtAddr<I16>,
+}
+const _: () = assert!(std::mem::size_of::<LinkAnimationHeader>() == 0x8);
+
 #[derive(FromBytes)]
 #[repr(C)]
 pub struct JointIndex {
@@ -250,3 +811,366 @@ pub struct JointIndex {
     pub z: U16,
 }
 const _: () = assert!(std::mem::size_of::<JointIndex>() == 0x06);
+
+/// A command of the header a scene or room file starts with
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct SceneCommand {
+    pub code: u8,
+    pub data1: u8,
+    _pad: [u8; 2],
+    pub data2: U32,
+}
+const _: () = assert!(std::mem::size_of::<SceneCommand>() == 0x8);
+
+/// The start of every room shape (mesh header). Type 0 and 2 shapes list
+/// `count` entries from `start` to `end`, type 1 shapes (prerendered
+/// backgrounds) point to a single entry with `start`.
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct RoomShapeHeader {
+    pub kind: u8,
+    pub count: u8,
+    _pad: [u8; 2],
+    pub start: RawVirtAddr,
+    pub end: RawVirtAddr,
+}
+const _: () = assert!(std::mem::size_of::<RoomShapeHeader>() == 0xC);
+
+/// The opaque and translucent (XLU) display lists of a part of a room,
+/// either of which may be NULL
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct RoomShapeDListsEntry {
+    pub dlists: [Gfx; 2],
+}
+const _: () = assert!(std::mem::size_of::<RoomShapeDListsEntry>() == 0x8);
+
+/// Entry of a type 2 room shape, culled by its bounding sphere
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct RoomShapeCullableEntry {
+    pub center: [I16; 3],
+    pub radius: I16,
+    pub dlists: [Gfx; 2],
+}
+const _: () = assert!(std::mem::size_of::<RoomShapeCullableEntry>() == 0x10);
+
+/// An entry of the room list of a scene header, where a room file is in the
+/// ROM
+#[derive(FromBytes, Debug)]
+#[repr(C)]
+pub struct RoomListEntry {
+    pub vrom_start: U32,
+    pub vrom_end: U32,
+}
+const _: () = assert!(std::mem::size_of::<RoomListEntry>() == 0x8);
+
+#[derive(FromBytes, Debug, Clone)]
+#[repr(C)]
+pub struct RomHeader {
+    pub pi_settings: U32,
+    pub clock_rate: U32,
+    pub entry_point: U32,
+    pub release: U32,
+    pub crc1: U32,
+    pub crc2: U32,
+    pub _reserved: [u8; 8],
+    pub name: [u8; 20],
+    pub _reserved2: [u8; 7],
+
+    /// The media format, cartridge id and region, e.g. `NZLE`
+    pub game_code: [u8; 4],
+    pub revision: u8,
+}
+const _: () = assert!(std::mem::size_of::<RomHeader>() == 0x40);
+
+#[derive(FromBytes, Debug, Clone)]
+#[repr(C)]
+pub struct DmaEntry {
+    pub vrom_start: U32,
+    pub vrom_end: U32,
+    pub rom_start: U32,
+
+    /// Zero when the file is stored uncompressed
+    pub rom_end: U32,
+}
+const _: () = assert!(std::mem::size_of::<DmaEntry>() == 0x10);
+impl DmaEntry {
+    pub fn is_compressed(&self) -> bool {
+        self.rom_end.get() != 0
+    }
+
+    /// The range the file occupies in the ROM
+    pub fn rom_range(&self) -> Range<u32> {
+        let start = self.rom_start.get();
+        if self.is_compressed() {
+            start..self.rom_end.get()
+        } else {
+            start..start + (self.vrom_end.get() - self.vrom_start.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{self, object_addr, SegmentBuilder, TempRom};
+
+    #[test]
+    fn warns_of_misaligned_display_lists() {
+        let reader = SegmentBuilder::new()
+            .bytes(0, &[0; 0x10])
+            .into_object_reader();
+
+        let warnings = test_rom::logged_warnings(|| {
+            reader
+                .display_list_from(RawVirtAddr::new(object_addr(4)))
+                .unwrap();
+        });
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("not aligned to 8 bytes"));
+
+        let warnings = test_rom::logged_warnings(|| {
+            reader
+                .display_list_from(RawVirtAddr::new(object_addr(8)))
+                .unwrap();
+        });
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn loads_files_once_and_reuses_them() {
+        let rom = TempRom::new("cache", &test_rom::rom(b"NZLE", &[&[1; 0x20], &[2; 0x10]]));
+        let mut reader = Reader::from_rom(rom.path()).unwrap();
+        assert_eq!(reader.find_file(0x10D0), Some(3));
+
+        let object = RawVirtAddr::new(object_addr(0));
+        reader.load_file(Segment::Object, 3).unwrap();
+        assert_eq!(reader.slice_from(object).unwrap(), &[2; 0x10]);
+
+        // A second load takes the cached data rather than the ROM's
+        let cache = &mut reader.rom.as_mut().unwrap().cache;
+        assert_eq!(cache.len(), 1);
+        cache.get_mut(&3).unwrap().0[0] = 3;
+        reader.set_segment(Segment::Object, None);
+        reader.load_file(Segment::Object, 3).unwrap();
+        assert_eq!(reader.slice_from(object).unwrap()[0], 3);
+    }
+
+    #[test]
+    fn yields_the_address_of_every_item() {
+        let reader = SegmentBuilder::new()
+            .u16s(0x10, &[1, 2, 3, 4, 5, 6, 7, 8, 9])
+            .into_object_reader();
+
+        let items = reader
+            .segment_iter_addr(VirtAddr::<JointIndex>::from(RawVirtAddr::new(object_addr(
+                0x10,
+            ))))
+            .map(|item| {
+                let (addr, index) = item.unwrap();
+                (addr.segment_offset(), index.x.get())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(items, [(0x10, 1), (0x16, 4), (0x1C, 7)]);
+        assert_eq!(std::mem::size_of::<JointIndex>(), 6);
+    }
+
+    #[test]
+    fn refuses_slices_past_the_end_of_a_segment() {
+        let reader = SegmentBuilder::new()
+            .bytes(0, &[1; 0x10])
+            .into_object_reader();
+
+        assert_eq!(
+            reader
+                .slice_from(RawVirtAddr::new(object_addr(0x8)))
+                .unwrap()
+                .len(),
+            8
+        );
+        assert!(reader
+            .slice_from(RawVirtAddr::new(object_addr(0x10)))
+            .unwrap()
+            .is_empty());
+
+        let error = reader
+            .slice_from(RawVirtAddr::new(object_addr(0x18)))
+            .unwrap_err();
+        assert!(matches!(error, RomError::OutOfBounds { len: 0x10, .. }));
+        assert_eq!(
+            error.to_string(),
+            "Offset 0x18 is out of bounds of segment 6 (length 0x10)"
+        );
+
+        assert!(matches!(
+            reader.slice_from(RawVirtAddr::new(0x05000000)),
+            Err(RomError::SegmentNotSet { segment: 5 })
+        ));
+    }
+
+    #[test]
+    fn decompresses_segments_starting_with_the_yaz0_magic() {
+        let object = b"armos armos armos armos".repeat(4);
+        let compressed = test_rom::yaz0(&object);
+        let rom = SegmentBuilder::new()
+            .bytes(0x100, &compressed)
+            .bytes(0x200, &object)
+            .into_bytes();
+        let mut r = io::Cursor::new(&rom);
+
+        for (start, len, offset) in [
+            (0x100, compressed.len(), None),
+            (0x200, object.len(), Some(0x200)),
+        ] {
+            let mut reader = Reader::new();
+            reader
+                .read_compressed_segment(Segment::Object, &mut r, start..start + len as u32)
+                .unwrap();
+            assert_eq!(
+                reader.segments[Segment::Object as usize].as_deref(),
+                Some(&object[..])
+            );
+            assert_eq!(reader.segment_offsets[Segment::Object as usize], offset);
+        }
+    }
+
+    #[test]
+    fn checksums_tell_segments_apart() {
+        let mut reader = SegmentBuilder::new()
+            .bytes(0, b"armos")
+            .into_object_reader();
+        let other = Segment::IconItemStatic;
+        reader.set_segment(other, Some(b"armos".to_vec()));
+        let object = reader.segment_checksum(Segment::Object as u32).unwrap();
+        assert_eq!(reader.segment_checksum(other as u32), Some(object));
+
+        reader.set_segment(other, Some(b"armor".to_vec()));
+        assert_ne!(reader.segment_checksum(other as u32), Some(object));
+
+        assert_eq!(reader.segment_checksum(4), None);
+        assert_eq!(reader.segment_checksum(16), None);
+    }
+
+    #[test]
+    fn maps_addresses_back_to_their_file_offset() {
+        let mut file = std::io::Cursor::new(vec![0; 0x200]);
+        let mut reader = Reader::new();
+        reader
+            .read_segment(Segment::Object, &mut file, 0x100..0x180)
+            .unwrap();
+
+        assert_eq!(
+            reader.phys_addr_of(RawVirtAddr::new(object_addr(0x24))),
+            Some(PhysAddr(0x124))
+        );
+        assert_eq!(
+            reader.phys_addr_of(RawVirtAddr::new(object_addr(0x80))),
+            None
+        );
+
+        // Data set directly was not read from the file
+        reader.set_segment(Segment::Object, Some(vec![0; 0x80]));
+        assert_eq!(
+            reader.phys_addr_of(RawVirtAddr::new(object_addr(0x24))),
+            None
+        );
+    }
+
+    #[test]
+    fn pads_the_last_word_of_files_cut_short() {
+        // A byte-swapped file whose last 16 bit word lacks its second byte
+        let mut file = std::io::Cursor::new(vec![0x37, 0x80, 0x40, 0x12, 0x22, 0x11, 0x33]);
+
+        assert_eq!(read_range(&mut file, 4..6).unwrap(), [0x11, 0x22]);
+        assert_eq!(read_range(&mut file, 4..7).unwrap(), [0x11, 0x22, 0x00]);
+        assert!(read_range(&mut file, 4..9).is_err());
+    }
+
+    #[test]
+    fn refuses_inverted_ranges() {
+        let mut file = std::io::Cursor::new(vec![0x80, 0x37, 0x12, 0x40, 0x11, 0x22]);
+
+        let error = read_range(&mut file, Range { start: 6, end: 4 }).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "File range 0x6..0x4 ends before it starts"
+        );
+        assert_eq!(read_range(&mut file, 4..4).unwrap(), Vec::<u8>::new());
+    }
+
+    /// A ROM with an object table in file 2, 4 bytes into it, whose objects
+    /// 1 to 16 and 18 are file 3 at 0x1160 and object 17 is unused
+    fn object_table_rom(game_code: &[u8; 4]) -> Vec<u8> {
+        let object = [0x1160, 0x1170];
+        let mut entries = vec![[0, 0]];
+        entries.extend([object; 16]);
+        entries.extend([[0, 0], object, [1, 2]]);
+        let table = SegmentBuilder::new()
+            .u32s(0, &[0xFFFFFFFF])
+            .u32s(4, &entries.concat())
+            .into_bytes();
+        test_rom::rom(game_code, &[&table, &[7; 0x10]])
+    }
+
+    #[test]
+    fn loads_objects_of_the_object_table_without_loading_segments() {
+        let rom = TempRom::new("objects", &object_table_rom(b"NZLX"));
+        let mut reader = Reader::from_rom(rom.path()).unwrap();
+        let rom_data = reader.rom.as_mut().unwrap();
+        rom_data.object_table = Some(rom_data.object_table_at(0x10B4).unwrap());
+
+        let table = reader.object_table().unwrap();
+        assert_eq!(table.len(), 19);
+        assert_eq!(table[1], 0x1160..0x1170);
+        assert!(table[17].is_empty());
+        assert_eq!(reader.segments.iter().flatten().count(), 0);
+        assert!(reader.rom.as_ref().unwrap().cache.is_empty());
+
+        reader.load_object(18).unwrap();
+        assert_eq!(
+            reader.segment_raw(Segment::Object as u8).unwrap(),
+            &[7; 0x10]
+        );
+        assert!(reader.load_object(17).is_err());
+        assert!(reader.load_object(19).is_err());
+    }
+
+    #[test]
+    fn refuses_object_ids_of_versions_without_a_known_table() {
+        let rom = TempRom::new("objects-unknown", &object_table_rom(b"NZLE"));
+        let mut reader = Reader::from_rom(rom.path()).unwrap();
+
+        let error = reader.load_object(1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No object table at 0x00B6EF58, where NTSC-U 1.0 has it"
+        );
+
+        // Revision 1 of NTSC-U has no known table address
+        let mut rom_data = object_table_rom(b"NZLE");
+        rom_data[0x3F] = 1;
+        let rom = TempRom::new("objects-unknown-rev", &rom_data);
+        let mut reader = Reader::from_rom(rom.path()).unwrap();
+
+        let error = reader.load_object(1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The object table of NTSC-U 1.1 is not known, load objects by their ROM range instead"
+        );
+        assert!(reader.rom.as_ref().unwrap().object_table.is_none());
+    }
+
+    #[test]
+    fn reads_the_object_table_at_a_known_address() {
+        let rom = TempRom::new("objects-at", &object_table_rom(b"NZLX"));
+        let reader = Reader::from_rom(rom.path()).unwrap();
+        let rom_data = reader.rom.as_ref().unwrap();
+
+        assert_eq!(rom_data.object_table_at(0x10B4).unwrap().len(), 19);
+        assert!(rom_data.object_table_at(0x10B0).is_err());
+        assert!(rom_data.object_table_at(0x10BC).is_err());
+        assert!(rom_data.object_table_at(0x2000).is_err());
+    }
+}
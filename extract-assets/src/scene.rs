@@ -0,0 +1,235 @@
+use std::{io, mem, ops::Range};
+
+use anyhow::{bail, Context, Result};
+use gltf::json::{self, material::AlphaMode};
+
+use crate::{
+    addr::{RawVirtAddr, VirtAddr},
+    builder::GltfBuilder,
+    mesh::{self, MeshOptions},
+    report::ExtractionReport,
+    rom,
+};
+
+/// Where the header of the room loaded into the room segment starts
+const ROOM_HEADER: u32 = 0x03000000;
+/// Where the header of the scene loaded into the scene segment starts
+const SCENE_HEADER: u32 = 0x02000000;
+
+/// `SCENE_CMD_ID_ROOM_LIST`, pointing to where each room file is in the ROM
+const CMD_ROOM_LIST: u8 = 0x04;
+/// `SCENE_CMD_ID_ROOM_SHAPE`, pointing to the room shape
+const CMD_ROOM_SHAPE: u8 = 0x0A;
+/// `SCENE_CMD_ID_END`, ending the header
+const CMD_END: u8 = 0x14;
+
+/// Finds the room shape of the room loaded into the room segment by walking
+/// its header commands
+pub fn find_room_shape(reader: &rom::Reader) -> Result<VirtAddr<rom::RoomShapeHeader>> {
+    let header = VirtAddr::<rom::SceneCommand>::from(RawVirtAddr::new(ROOM_HEADER));
+    for command in reader.iter_from(header) {
+        let command = command.context("Failed to read room header")?;
+        match command.code {
+            CMD_ROOM_SHAPE => return Ok(RawVirtAddr::new(command.data2.get()).into()),
+            CMD_END => break,
+            _ => {}
+        }
+    }
+
+    bail!("The room header has no room shape command")
+}
+
+/// Reads where each room of the scene loaded into the scene segment is in
+/// the ROM, from the room list command of its header
+pub fn find_room_list(reader: &rom::Reader) -> Result<Vec<Range<u32>>> {
+    let header = VirtAddr::<rom::SceneCommand>::from(RawVirtAddr::new(SCENE_HEADER));
+    for command in reader.iter_from(header) {
+        let command = command.context("Failed to read scene header")?;
+        match command.code {
+            CMD_ROOM_LIST => {
+                let rooms = reader
+                    .read_slice::<rom::RoomListEntry>(
+                        RawVirtAddr::new(command.data2.get()).into(),
+                        command.data1 as usize,
+                    )
+                    .context("Failed to read room list")?;
+                return Ok(rooms
+                    .iter()
+                    .map(|room| room.vrom_start.get()..room.vrom_end.get())
+                    .collect());
+            }
+            CMD_END => break,
+            _ => {}
+        }
+    }
+
+    bail!("The scene header has no room list command")
+}
+
+/// Loads a scene file followed in the ROM by its rooms, as one contiguous
+/// range, into the scene segment, and the room with the given index into the
+/// room segment. Where the rooms start is taken from the room list of the
+/// scene header, whose addresses are ROM offsets in a decompressed ROM.
+pub fn load_scene_with_rooms<R: io::Read + io::Seek>(
+    reader: &mut rom::Reader,
+    r: &mut R,
+    range: Range<u32>,
+    room: usize,
+) -> Result<()> {
+    reader
+        .read_segment(rom::Segment::Scene, r, range.clone())
+        .context("Failed to read scene")?;
+    let rooms = find_room_list(reader)?;
+
+    if let Some(outside) = rooms
+        .iter()
+        .find(|part| part.start < range.start || part.end > range.end || part.is_empty())
+    {
+        bail!(
+            "Room {:#X}..{:#X} is not within {:#X}..{:#X}",
+            outside.start,
+            outside.end,
+            range.start,
+            range.end
+        );
+    }
+    let scene_end = rooms
+        .iter()
+        .map(|part| part.start)
+        .min()
+        .unwrap_or(range.end);
+    let room_range = rooms
+        .get(room)
+        .with_context(|| format!("The scene has {} rooms, not {}", rooms.len(), room + 1))?
+        .clone();
+
+    log::debug!(
+        "Loading scene {:#X}..{:#X} and room {:#X}..{:#X}",
+        range.start,
+        scene_end,
+        room_range.start,
+        room_range.end
+    );
+    reader.read_segment(rom::Segment::Scene, r, range.start..scene_end)?;
+    reader.read_segment(rom::Segment::Room, r, room_range)?;
+
+    Ok(())
+}
+
+/// Reads the opaque and translucent display lists of every entry of a room
+/// shape
+pub fn read_room_dlists(
+    reader: &rom::Reader,
+    shape: VirtAddr<rom::RoomShapeHeader>,
+) -> Result<Vec<[RawVirtAddr; 2]>> {
+    let header = reader.read(shape).context("Failed to read room shape")?;
+    log::info!(
+        "Room shape of type {} with {} entries at {}",
+        header.kind,
+        header.count,
+        header.start
+    );
+
+    Ok(match header.kind {
+        0 => reader
+            .read_slice::<rom::RoomShapeDListsEntry>(header.start.into(), header.count as usize)?
+            .iter()
+            .map(|entry| entry.dlists)
+            .collect(),
+        1 => vec![
+            reader
+                .read::<rom::RoomShapeDListsEntry>(header.start.into())?
+                .dlists,
+        ],
+        2 => reader
+            .read_slice::<rom::RoomShapeCullableEntry>(header.start.into(), header.count as usize)?
+            .iter()
+            .map(|entry| entry.dlists)
+            .collect(),
+        kind => bail!("Unknown room shape type {}", kind),
+    })
+}
+
+/// Reads the geometry of the room loaded into the room segment, with the
+/// scene it belongs to loaded into the scene segment. Every entry of the room
+/// shape becomes a child of the returned room node.
+pub fn read_room_into_gltf(
+    root: &mut json::Root,
+    reader: &rom::Reader,
+    options: &MeshOptions,
+) -> Result<(json::Index<json::Node>, ExtractionReport)> {
+    let shape = find_room_shape(reader)?;
+
+    let mut report = ExtractionReport::default();
+    let mut children = Vec::new();
+    for (index, [opa, xlu]) in read_room_dlists(reader, shape)?.into_iter().enumerate() {
+        log::info!("  Room entry {}, opa:{} xlu:{}", index, opa, xlu);
+
+        let mut mesh = mesh::Mesh::default();
+        for (dlist, alpha_mode) in [(opa, AlphaMode::Opaque), (xlu, AlphaMode::Blend)] {
+            if !dlist.is_null() {
+                mesh.merge(mesh::read_display_list(reader, dlist, options)?, alpha_mode);
+            }
+        }
+        if mesh.primitives.is_empty() {
+            continue;
+        }
+
+        mesh.apply_options(options);
+        let meshes = root.meshes.len();
+        let node = mesh.write_node_into_gltf(root, &format!("room_entry_{:02}", index), options);
+        report.merge(mem::take(&mut mesh.report));
+        report.meshes += root.meshes.len() - meshes;
+        children.push(node);
+    }
+
+    let node = root.add(json::Node {
+        children: Some(children),
+        name: Some(String::from("room")),
+        ..mesh::empty_node()
+    });
+    Ok((node, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::SegmentBuilder;
+
+    #[test]
+    fn splits_a_scene_and_its_rooms_by_the_room_list() {
+        // The scene at 0x100 lists two rooms following it in the ROM
+        let data = SegmentBuilder::new()
+            .bytes(0x100, &[CMD_ROOM_LIST, 2, 0, 0])
+            .u32s(0x104, &[0x02000010])
+            .bytes(0x108, &[CMD_END])
+            .u32s(0x110, &[0x140, 0x160, 0x160, 0x180])
+            .bytes(0x140, &[1; 0x20])
+            .bytes(0x160, &[2; 0x20])
+            .into_bytes();
+
+        let mut reader = rom::Reader::new();
+        let mut r = io::Cursor::new(&data);
+        load_scene_with_rooms(&mut reader, &mut r, 0x100..0x180, 1).unwrap();
+        assert_eq!(
+            reader.segment_raw(rom::Segment::Scene as u8).unwrap(),
+            &data[0x100..0x140]
+        );
+        assert_eq!(
+            reader.segment_raw(rom::Segment::Room as u8).unwrap(),
+            &[2; 0x20]
+        );
+        assert_eq!(
+            find_room_list(&reader).unwrap(),
+            [0x140..0x160, 0x160..0x180]
+        );
+
+        load_scene_with_rooms(&mut reader, &mut r, 0x100..0x180, 0).unwrap();
+        assert_eq!(
+            reader.segment_raw(rom::Segment::Room as u8).unwrap(),
+            &[1; 0x20]
+        );
+
+        assert!(load_scene_with_rooms(&mut reader, &mut r, 0x100..0x170, 0).is_err());
+    }
+}
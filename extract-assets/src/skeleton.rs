@@ -1,72 +1,277 @@
-use anyhow::{Context, Result};
-use gltf::json::{self, Index};
+use std::{collections::HashSet, mem};
+
+use anyhow::{bail, Context, Result};
+use glam::{Mat4, Quat, Vec3};
+use gltf::json::{self, material::AlphaMode, Index};
 use log::Level;
 use num_traits::FromPrimitive;
 use zerocopy::AsBytes;
 
 use crate::{
-    addr::VirtAddr,
-    display_list::{self, InstructionStream},
-    mesh, rom, skeleton_animation,
+    addr::{RawVirtAddr, VirtAddr},
+    builder::{self, GltfBuilder},
+    display_list, extras, mesh,
+    report::ExtractionReport,
+    rom, skeleton_animation,
 };
 
-pub fn read_into_gltf(
-    root: &mut json::Root,
+/// A limb read from either of the supported limb layouts
+struct Limb {
+    child: u8,
+    sibling: u8,
+    joint_pos: Option<[f32; 3]>,
+    mesh: Option<mesh::Mesh>,
+
+    /// What kind of limb it is, named after `SkinLimbType` for skin limbs
+    kind: &'static str,
+
+    /// The `SkinAnimatedLimbData` of animated skin limbs
+    skin_data: Option<RawVirtAddr>,
+}
+impl Limb {
+    /// Stands in for a limb whose pointer is NULL or unreadable, keeping
+    /// the following limbs at the index their child and sibling refer to
+    fn placeholder(limb_index: usize, report: &mut ExtractionReport) -> Self {
+        report.error(format!(
+            "Limb {} is missing, adding an empty node",
+            limb_index
+        ));
+        Self {
+            child: 0xFF,
+            sibling: 0xFF,
+            joint_pos: None,
+            mesh: None,
+            kind: "missing",
+            skin_data: None,
+        }
+    }
+
+    /// The name of the limb's node and mesh, e.g. `limb_03_animated`
+    fn name(&self, limb_index: usize) -> String {
+        format!("limb_{:02}_{}", limb_index, self.kind)
+    }
+}
+
+/// How far `read_into_gltf` has come, reported before reading each limb and
+/// each animation
+#[derive(Debug)]
+pub enum Progress<'a> {
+    Limb {
+        index: usize,
+        total: usize,
+    },
+    Animation {
+        name: &'a str,
+        index: usize,
+        total: usize,
+    },
+}
+
+/// The most limbs of any skeleton of the game, beyond which a skeleton
+/// header is taken to be misidentified data
+const MAX_LIMB_COUNT: u8 = 64;
+
+/// Reads a skeleton header, refusing headers that can't be one, as happens
+/// when given the wrong address
+fn read_skeleton_header(
     reader: &rom::Reader,
     addr: VirtAddr<rom::SkeletonHeader>,
-    animation_addrs: &[VirtAddr<rom::AnimationHeader>],
-) -> Result<()> {
+) -> Result<rom::SkeletonHeader> {
     let skeleton_header = reader
         .read(addr)
         .context("Failed to read skeleton header")?;
-    let limbs = reader
-        .ptr_slice_iter(skeleton_header.limbs, skeleton_header.limb_count as usize)
+
+    if RawVirtAddr::from(skeleton_header.limbs).is_null() {
+        bail!("Skeleton header at {} has no limbs (NULL pointer)", addr);
+    }
+    if !(1..=MAX_LIMB_COUNT).contains(&skeleton_header.limb_count) {
+        bail!(
+            "Skeleton header at {} has {} limbs, expected 1 to {}. Is it a skeleton?",
+            addr,
+            skeleton_header.limb_count,
+            MAX_LIMB_COUNT
+        );
+    }
+
+    Ok(skeleton_header)
+}
+
+/// Writes the limbs of the skeleton as nodes, after any nodes already in
+/// `root` so that several skeletons can share a root, along with their
+/// meshes and the animations. The first of the nodes is the root limb. With
+/// `skin`, the meshes are instead drawn by a node of their own following the
+/// limbs, which is not part of the limb hierarchy. Without animations, only
+/// the limbs and their meshes in the rest pose are read.
+pub fn read_into_gltf(
+    root: &mut json::Root,
+    reader: &rom::Reader,
+    addr: VirtAddr<rom::SkeletonHeader>,
+    animations: &[skeleton_animation::NamedAnimation],
+    mesh_options: &mesh::MeshOptions,
+    animation_options: &skeleton_animation::AnimationOptions,
+    mut progress: Option<&mut dyn FnMut(Progress)>,
+) -> Result<ExtractionReport> {
+    let mut progress = |p: Progress| {
+        if let Some(progress) = progress.as_mut() {
+            progress(p);
+        }
+    };
+
+    let skeleton_header = read_skeleton_header(reader, addr)?;
+    let mut report = ExtractionReport::default();
+    let first_node = root.nodes.len() as u32;
+    let first_animation = root.animations.len();
+
+    if log::log_enabled!(Level::Trace) {
+        for item in reader
+            .segment_iter_addr(skeleton_header.limbs)
+            .take(skeleton_header.limb_count as usize)
+        {
+            let (addr, limb_addr) = item?;
+            log::trace!("  Limb pointer at {} -> {}", addr, limb_addr);
+        }
+    }
+
+    let curve_limbs = reader
+        .ptr_slice_iter::<rom::CurveLimb>(
+            RawVirtAddr::from(skeleton_header.limbs).into(),
+            skeleton_header.limb_count as usize,
+        )
         .context("Failed to read limbs")?
         .collect::<Vec<_>>();
 
+    // Every limb has to agree on the layout, so that a skin limb whose type
+    // happens to resolve as an address doesn't turn the skeleton into curves
+    let layouts = curve_limbs
+        .iter()
+        .flatten()
+        .filter_map(|limb| has_xlu_dlist(reader, limb))
+        .collect::<Vec<_>>();
+    let xlu_limbs = layouts.iter().filter(|&&xlu| xlu).count();
+    if xlu_limbs != 0 && xlu_limbs != layouts.len() {
+        report.warn(format!(
+            "Only {} of {} limbs have a translucent display list, reading them as skin limbs",
+            xlu_limbs,
+            layouts.len()
+        ));
+    }
+
     log::info!("Creating skeleton skin nodes");
-    for limb in &limbs {
-        let mesh = match FromPrimitive::from_i32(limb.segment_type.get()) {
-            Some(rom::SkinLimbType::Normal) => {
-                log::info!("  Normal skin limb, segment:{}", limb.segment);
-                Some(read_normal_skin_limb(reader, &limb)?)
-            }
-            Some(rom::SkinLimbType::Animated) => {
-                log::info!("  Animated skin limb, segment:{}", limb.segment);
-                Some(read_animated_skin_limb(reader, &limb)?)
-            }
-            _ => None,
-        };
+    let mut limbs = if xlu_limbs != 0 && xlu_limbs == layouts.len() {
+        log::info!("  Limbs carry opaque and translucent display lists");
+        read_curve_limbs(
+            reader,
+            &curve_limbs,
+            mesh_options,
+            &mut report,
+            &mut progress,
+        )?
+    } else {
+        read_skin_limbs(
+            reader,
+            &skeleton_header,
+            mesh_options,
+            &mut report,
+            &mut progress,
+        )?
+    };
+
+    let frame_counts = if animation_options.skin_morph_targets && !animations.is_empty() {
+        log::info!("Sampling animated skin limbs into morph targets");
+        push_skin_morph_targets(
+            reader,
+            &skeleton_header,
+            &mut limbs,
+            animations,
+            mesh_options,
+        )?
+    } else {
+        Vec::new()
+    };
+    if mesh_options.skin && !frame_counts.is_empty() {
+        bail!("Skinned meshes can't be combined with skin morph targets");
+    }
+
+    // Skin limbs are sampled in the game's units, so the limbs are only
+    // scaled afterwards, like the meshes are by `apply_options`
+    for joint_pos in limbs.iter_mut().filter_map(|limb| limb.joint_pos.as_mut()) {
+        *joint_pos = joint_pos.map(|n| n * mesh_options.scale);
+    }
+    let rest_matrices = limb_matrices(&limbs, &rest_pose(&limbs));
+    let mut skinned_mesh = mesh_options.skin.then(|| mesh::Mesh {
+        skinned: true,
+        ..Default::default()
+    });
+    let morphed_nodes = limbs
+        .iter()
+        .enumerate()
+        .filter(|(_, limb)| limb.skin_data.is_some() && limb.mesh.is_some())
+        .map(|(limb_index, _)| limb_index as u32)
+        .collect::<Vec<_>>();
 
-        if let Some(mesh) = mesh.as_ref() {
-            mesh.write_into_gltf(root);
+    for (limb_index, limb) in limbs.iter_mut().enumerate() {
+        let name = limb.name(limb_index);
+        let mut mesh_index = None;
+        if let Some(mut mesh) = limb.mesh.take() {
+            report.merge(mem::take(&mut mesh.report));
+            mesh.apply_options(mesh_options);
+            match skinned_mesh.as_mut() {
+                // Skinned vertices are in the space of the skeleton, placed
+                // by the inverse bind matrices relative to their joint
+                Some(skinned_mesh) => {
+                    mesh.assign_joint(limb_index as _);
+                    mesh.transform(rest_matrices[limb_index]);
+                    skinned_mesh.merge(mesh, AlphaMode::Opaque);
+                }
+                None => {
+                    mesh_index = Some(mesh.write_into_gltf(root, &name));
+                    report.meshes += 1;
+                }
+            }
         }
 
-        root.nodes.push(json::Node {
+        root.add(json::Node {
             camera: None,
             children: None,
             extensions: Default::default(),
             extras: Default::default(),
             matrix: None,
-            mesh: mesh.map(|_| Index::new(root.meshes.len() as u32 - 1)),
-            name: None,
+            mesh: mesh_index,
+            name: Some(name),
             rotation: None,
             scale: None,
-            translation: Some([
-                limb.joint_pos[0].get() as _,
-                limb.joint_pos[1].get() as _,
-                limb.joint_pos[2].get() as _,
-            ]),
+            translation: limb.joint_pos,
             skin: None,
             weights: None,
         });
     }
 
     log::info!("Building skeleton node hierarchy");
-    build_node_hierarchy(root, &limbs);
+    build_node_hierarchy(root, &limbs, first_node);
+    check_rest_pose(root, &limbs, first_node, &mut report);
+    if let Some(skinned_mesh) = skinned_mesh.filter(|mesh| !mesh.primitives.is_empty()) {
+        let mesh = skinned_mesh.write_into_gltf(root, "skinned_mesh");
+        report.meshes += 1;
+        let skin = write_skin_into_gltf(root, &rest_matrices, first_node);
+        root.add(json::Node {
+            mesh: Some(mesh),
+            name: Some("skinned_mesh".to_owned()),
+            skin: Some(skin),
+            ..mesh::empty_node()
+        });
+    }
+    if mesh_options.world_matrix_extras {
+        for (node, matrix) in node_world_matrices(root, first_node) {
+            extras::insert(
+                &mut root.nodes[node as usize].extras,
+                "world_matrix",
+                json::Value::from(matrix.to_cols_array().to_vec()),
+            )?;
+        }
+    }
 
     if log::log_enabled!(Level::Trace) {
-        for (index, node) in root.nodes.iter().enumerate() {
+        for (index, node) in root.nodes.iter().enumerate().skip(first_node as usize) {
             log::trace!(
                 "  Node {} as {} children",
                 index,
@@ -78,36 +283,304 @@ pub fn read_into_gltf(
         }
     }
 
-    for animation_addr in animation_addrs {
-        skeleton_animation::read_into_gltf(root, reader, &skeleton_header, *animation_addr)?;
+    let target_count = frame_counts.iter().sum();
+    let mut first_target = 0;
+    for (animation_index, animation) in animations.iter().enumerate() {
+        progress(Progress::Animation {
+            name: &animation.name,
+            index: animation_index,
+            total: animations.len(),
+        });
+
+        // Samplers without keyframes are invalid glTF, so empty animations
+        // are left out
+        let frame_count = skeleton_animation::frame_count(reader, animation.addr)
+            .with_context(|| format!("Failed to read animation {}", animation.name))?;
+        if frame_count == 0 {
+            report.warn(format!(
+                "Skipping animation {}, which has no frames",
+                animation.name
+            ));
+            continue;
+        }
+
+        let morph_weights = frame_counts
+            .get(animation_index)
+            .filter(|_| !morphed_nodes.is_empty())
+            .map(|frame_count| skeleton_animation::MorphWeights {
+                nodes: &morphed_nodes,
+                targets: first_target..first_target + frame_count,
+                target_count,
+            });
+        first_target += frame_counts.get(animation_index).copied().unwrap_or(0);
+
+        let options = skeleton_animation::AnimationOptions {
+            frame_duration: animation
+                .fps
+                .map_or(animation_options.frame_duration, |fps| 1.0 / fps),
+            scale: mesh_options.scale,
+            ..animation_options.clone()
+        };
+        skeleton_animation::read_into_gltf(
+            root,
+            reader,
+            &skeleton_header,
+            &animation.name,
+            animation.addr,
+            morph_weights.as_ref(),
+            &options,
+        )?;
+
+        // Channels target limbs by index, which are offset by the nodes
+        // written before the skeleton
+        for channel in &mut root.animations.last_mut().unwrap().channels {
+            channel.target.node = Index::new(first_node + channel.target.node.value() as u32);
+        }
+    }
+
+    report.limbs = limbs.len();
+    report.animations = root.animations.len() - first_animation;
+
+    Ok(report)
+}
+
+/// Attaches the rotation limits of every limb, in degrees, to the extras of
+/// the limb nodes written by `read_into_gltf` from `first_node` on
+pub fn read_joint_limits_into_gltf(
+    root: &mut json::Root,
+    reader: &rom::Reader,
+    addr: VirtAddr<rom::SkeletonHeader>,
+    limits_addr: VirtAddr<rom::JointLimits>,
+    first_node: u32,
+) -> Result<()> {
+    let skeleton_header = read_skeleton_header(reader, addr)?;
+    let limits = reader
+        .read_slice(limits_addr, skeleton_header.limb_count as usize)
+        .context("Failed to read joint limits")?;
+
+    let degrees = |angles: &[rom::I16; 3]| {
+        angles
+            .iter()
+            .map(|angle| angle.get() as f32 * 360.0 / 65536.0)
+            .collect::<Vec<_>>()
+    };
+    for (node, limits) in root.nodes.iter_mut().skip(first_node as usize).zip(limits) {
+        extras::insert(
+            &mut node.extras,
+            "rotation_limits",
+            json::Value::from_iter([("min", degrees(&limits.min)), ("max", degrees(&limits.max))]),
+        )?;
     }
 
     Ok(())
 }
 
-fn read_normal_skin_limb(reader: &rom::Reader, limb: &rom::SkinLimb) -> Result<mesh::Mesh> {
-    let mut instruction_stream = InstructionStream::new(
-        reader
-            .slice_from(limb.segment)
-            .with_context(|| format!("Could not read data for at address {}", limb.segment))?,
-    );
+/// Looks for display lists stored back to back directly after the skeleton
+/// header. This is only a discovery aid, reporting addresses worth extracting.
+pub fn find_adjacent_display_lists(
+    reader: &rom::Reader,
+    addr: VirtAddr<rom::SkeletonHeader>,
+) -> Result<Vec<RawVirtAddr>> {
+    let header_size = (mem::size_of::<rom::SkeletonHeader>() + 7) & !7;
+    let start = RawVirtAddr::from(addr.offset_bytes(header_size as i32));
+    let data = reader
+        .slice_from(start)
+        .context("Failed to read data after skeleton header")?;
 
-    if log::log_enabled!(Level::Trace) {
-        log::trace!("Display list instructions:");
-        instruction_stream.clone().for_each(display_list::dump());
+    let mut dlists = Vec::new();
+    let mut offset = 0;
+    while let Some(len) = display_list::display_list_len(&data[offset..]) {
+        dlists.push(start + offset as i32);
+        offset += len;
+    }
+
+    Ok(dlists)
+}
+
+/// Scans a segment for data that looks like a skeleton header: a limb count
+/// that fits an object, followed by padding, and a limb pointer array whose
+/// entries all point into the same segment
+pub fn find_skeletons(
+    reader: &rom::Reader,
+    segment: rom::Segment,
+) -> Result<Vec<VirtAddr<rom::SkeletonHeader>>> {
+    let segment_start = RawVirtAddr::new((segment as u32) << 24);
+    let data = reader.slice_from(segment_start)?;
+
+    Ok((0..data.len().saturating_sub(8))
+        .step_by(4)
+        .filter(|offset| data[offset + 5..offset + 8] == [0, 0, 0])
+        .map(|offset| VirtAddr::from(segment_start + offset as i32))
+        .filter(|addr| is_plausible_skeleton(reader, *addr))
+        .collect())
+}
+
+fn is_plausible_skeleton(reader: &rom::Reader, addr: VirtAddr<rom::SkeletonHeader>) -> bool {
+    let Ok(skeleton_header) = reader.read(addr) else {
+        return false;
+    };
+    let limbs = RawVirtAddr::from(skeleton_header.limbs);
+    if !(1..=MAX_LIMB_COUNT).contains(&skeleton_header.limb_count)
+        || limbs.segment_number() != RawVirtAddr::from(addr).segment_number()
+        || !limbs.segment_offset().is_multiple_of(4)
+    {
+        return false;
+    }
+
+    reader
+        .read_slice(skeleton_header.limbs, skeleton_header.limb_count as usize)
+        .map(|limb_addrs| {
+            limb_addrs.iter().all(|limb_addr| {
+                let limb_addr = RawVirtAddr::from(*limb_addr);
+                limb_addr.segment_number() == limbs.segment_number()
+                    && limb_addr.segment_offset().is_multiple_of(4)
+                    && reader.read::<rom::SkinLimb>(limb_addr.into()).is_ok()
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn read_skin_limbs(
+    reader: &rom::Reader,
+    skeleton_header: &rom::SkeletonHeader,
+    options: &mesh::MeshOptions,
+    report: &mut ExtractionReport,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<Limb>> {
+    let limb_count = skeleton_header.limb_count as usize;
+    let skin_limbs = reader
+        .ptr_slice_iter(skeleton_header.limbs, limb_count)
+        .context("Failed to read limbs")?;
+
+    skin_limbs
+        .enumerate()
+        .map(|(limb_index, limb)| {
+            progress(Progress::Limb {
+                index: limb_index,
+                total: limb_count,
+            });
+            let Some(limb) = limb else {
+                return Ok(Limb::placeholder(limb_index, report));
+            };
+            let mut skin_data = None;
+            let (mesh, kind) = match FromPrimitive::from_i32(limb.segment_type.get()) {
+                Some(rom::SkinLimbType::Normal) => {
+                    log::info!("  Normal skin limb, segment:{}", limb.segment);
+                    (
+                        Some(read_normal_skin_limb(reader, &limb, options)?),
+                        "normal",
+                    )
+                }
+                Some(rom::SkinLimbType::Animated) => {
+                    log::info!("  Animated skin limb, segment:{}", limb.segment);
+                    skin_data = Some(limb.segment);
+                    (
+                        Some(read_animated_skin_limb(
+                            reader,
+                            limb.segment,
+                            options,
+                            apply_limb_transformations,
+                        )?),
+                        "animated",
+                    )
+                }
+                _ => (None, "empty"),
+            };
+
+            Ok(Limb {
+                child: limb.child,
+                sibling: limb.sibling,
+                joint_pos: Some([
+                    limb.joint_pos[0].get() as _,
+                    limb.joint_pos[1].get() as _,
+                    limb.joint_pos[2].get() as _,
+                ]),
+                mesh,
+                kind,
+                skin_data,
+            })
+        })
+        .collect()
+}
+
+/// Curve limbs store their translucent display list where skin limbs store
+/// their limb type, so a resolvable pointer there identifies the layout.
+/// `None` if it is NULL, which both layouts allow
+fn has_xlu_dlist(reader: &rom::Reader, limb: &rom::CurveLimb) -> Option<bool> {
+    let [opa, xlu] = *limb.dlists;
+    if xlu.is_null() {
+        return None;
     }
+    Some(reader.slice_from(xlu).is_ok() && (opa.is_null() || reader.slice_from(opa).is_ok()))
+}
+
+fn read_curve_limbs(
+    reader: &rom::Reader,
+    curve_limbs: &[Option<rom::CurveLimb>],
+    options: &mesh::MeshOptions,
+    report: &mut ExtractionReport,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<Limb>> {
+    curve_limbs
+        .iter()
+        .enumerate()
+        .map(|(limb_index, limb)| {
+            progress(Progress::Limb {
+                index: limb_index,
+                total: curve_limbs.len(),
+            });
+            let Some(limb) = limb else {
+                return Ok(Limb::placeholder(limb_index, report));
+            };
+            let [opa, xlu] = *limb.dlists;
+            log::info!("  Curve limb, opa:{} xlu:{}", opa, xlu);
+
+            let mut mesh = mesh::Mesh::default();
+            for (dlist, alpha_mode) in [(opa, AlphaMode::Opaque), (xlu, AlphaMode::Blend)] {
+                if !dlist.is_null() {
+                    mesh.merge(mesh::read_display_list(reader, dlist, options)?, alpha_mode);
+                }
+            }
 
-    instruction_stream.try_fold(mesh::Mesh::default(), mesh::fold(reader))
+            Ok(Limb {
+                child: limb.child,
+                sibling: limb.sibling,
+                joint_pos: None,
+                kind: if mesh.primitives.is_empty() {
+                    "empty"
+                } else {
+                    "curve"
+                },
+                mesh: (!mesh.primitives.is_empty()).then_some(mesh),
+                skin_data: None,
+            })
+        })
+        .collect()
 }
 
-fn read_animated_skin_limb(reader: &rom::Reader, limb: &rom::SkinLimb) -> Result<mesh::Mesh> {
+fn read_normal_skin_limb(
+    reader: &rom::Reader,
+    limb: &rom::SkinLimb,
+    options: &mesh::MeshOptions,
+) -> Result<mesh::Mesh> {
+    mesh::read_display_list(reader, limb.segment, options)
+}
+
+/// Reads the mesh of an animated skin limb, placing each of its vertices at
+/// the position `vertex_pos` computes from the vertex's limb transformations
+fn read_animated_skin_limb(
+    reader: &rom::Reader,
+    skin_data: RawVirtAddr,
+    options: &mesh::MeshOptions,
+    vertex_pos: impl Fn(&[rom::SkinTransformation]) -> [f32; 3],
+) -> Result<mesh::Mesh> {
     let rom::SkinAnimatedLimbData {
         limb_modifications,
         limb_modif_count,
         total_vtx_count,
         dlist,
     } = reader
-        .read::<rom::SkinAnimatedLimbData>(limb.segment.into())
+        .read::<rom::SkinAnimatedLimbData>(skin_data.into())
         .context("Failed to read skin animated limb data")?;
 
     let limb_modifs = reader
@@ -123,14 +596,27 @@ fn read_animated_skin_limb(reader: &rom::Reader, limb: &rom::SkinLimb) -> Result
             .read_slice(modif.skin_vertices, modif.vtx_count.get() as _)
             .context("Failed to read skin vertices")?;
 
-        let vtx_point = apply_limb_transformations(&limb_transformations);
+        let vtx_point = vertex_pos(limb_transformations);
 
+        // Skin vertices carry their own texture coordinates, normal and
+        // alpha, which the game copies into the vertex buffer along with the
+        // position
         for skin_vertex in skin_vertices {
-            vtx_buffer[skin_vertex.index.get() as usize].pos = [
-                (vtx_point[0] as i16).into(),
-                (vtx_point[1] as i16).into(),
-                (vtx_point[2] as i16).into(),
-            ];
+            vtx_buffer[skin_vertex.index.get() as usize] = rom::Vtx {
+                pos: [
+                    (vtx_point[0] as i16).into(),
+                    (vtx_point[1] as i16).into(),
+                    (vtx_point[2] as i16).into(),
+                ],
+                tpos: [skin_vertex.s, skin_vertex.t],
+                cn: [
+                    skin_vertex.norm_x as u8,
+                    skin_vertex.norm_y as u8,
+                    skin_vertex.norm_z as u8,
+                    skin_vertex.alpha,
+                ],
+                ..Default::default()
+            };
         }
     }
 
@@ -140,18 +626,8 @@ fn read_animated_skin_limb(reader: &rom::Reader, limb: &rom::SkinLimb) -> Result
         Some(vtx_buffer.as_bytes().to_vec()),
     );
 
-    let mut instruction_stream = display_list::InstructionStream::new(
-        reader
-            .slice_from(dlist)
-            .context("Could not read animated skin limb display list")?,
-    );
-
-    if log::log_enabled!(Level::Trace) {
-        log::trace!("Animated skin limb display list");
-        instruction_stream.clone().for_each(display_list::dump());
-    }
-
-    instruction_stream.try_fold(mesh::Mesh::default(), mesh::fold(&reader))
+    mesh::read_display_list(&reader, dlist, options)
+        .context("Could not read animated skin limb display list")
 }
 
 fn apply_limb_transformations(limb_transformations: &[rom::SkinTransformation]) -> [f32; 3] {
@@ -169,20 +645,806 @@ fn apply_limb_transformations(limb_transformations: &[rom::SkinTransformation])
     )
 }
 
-fn build_node_hierarchy(root: &mut json::Root, limbs: &[rom::SkinLimb]) {
-    for (index, rom::SkinLimb { child, .. }) in limbs
+/// Samples the vertices of the animated skin limbs in every frame of the
+/// animations into morph targets of their meshes. The vertices are moved into
+/// the space of their limb, which the animation moves along. Returns the
+/// number of frames of each animation.
+fn push_skin_morph_targets(
+    reader: &rom::Reader,
+    skeleton_header: &rom::SkeletonHeader,
+    limbs: &mut [Limb],
+    animations: &[skeleton_animation::NamedAnimation],
+    options: &mesh::MeshOptions,
+) -> Result<Vec<usize>> {
+    let mut frame_counts = Vec::new();
+    for animation in animations {
+        let poses = skeleton_animation::read_poses(reader, skeleton_header, animation.addr)
+            .with_context(|| format!("Failed to read poses of animation {}", animation.name))?;
+
+        for pose in &poses {
+            let matrices = limb_matrices(limbs, pose);
+            for (limb_index, limb) in limbs.iter_mut().enumerate() {
+                let (Some(skin_data), Some(mesh)) = (limb.skin_data, limb.mesh.as_mut()) else {
+                    continue;
+                };
+
+                let to_limb = matrices[limb_index].inverse();
+                let frame =
+                    read_animated_skin_limb(reader, skin_data, options, |transformations| {
+                        let pos = transformations
+                            .iter()
+                            .fold(Vec3::ZERO, |pos, transformation| {
+                                let offset = Vec3::new(
+                                    transformation.x.get() as _,
+                                    transformation.y.get() as _,
+                                    transformation.z.get() as _,
+                                );
+                                let matrix = matrices
+                                    .get(*transformation.limb_index as usize)
+                                    .unwrap_or(&Mat4::IDENTITY);
+                                pos + matrix.transform_point3(offset)
+                                    * transformation.scale as f32
+                                    * 0.01
+                            });
+                        to_limb.transform_point3(pos).to_array()
+                    })?;
+                mesh.push_morph_target(&frame)?;
+            }
+        }
+
+        frame_counts.push(poses.len());
+    }
+
+    Ok(frame_counts)
+}
+
+/// The model space transform of every limb in the given pose
+fn limb_matrices(limbs: &[Limb], pose: &skeleton_animation::Pose) -> Vec<Mat4> {
+    let mut matrices = vec![Mat4::IDENTITY; limbs.len()];
+    let mut visited = vec![false; limbs.len()];
+
+    // Walks the hierarchy from the root, each limb being placed relative to
+    // its parent. Limbs reached twice are skipped, so a malformed hierarchy
+    // can't loop forever.
+    let mut pending = vec![(0, Mat4::IDENTITY)];
+    while let Some((limb_index, parent)) = pending.pop() {
+        let Some(limb) = limbs.get(limb_index).filter(|_| !visited[limb_index]) else {
+            continue;
+        };
+        visited[limb_index] = true;
+        let translation = match limb_index {
+            0 => pose.root_translation,
+            _ => Vec3::from(limb.joint_pos.unwrap_or_default()),
+        };
+        let rotation = pose
+            .rotations
+            .get(limb_index)
+            .copied()
+            .unwrap_or(Mat4::IDENTITY);
+        matrices[limb_index] = parent * Mat4::from_translation(translation) * rotation;
+
+        if limb.child != 0xFF {
+            pending.push((limb.child as usize, matrices[limb_index]));
+        }
+        if limb.sibling != 0xFF {
+            pending.push((limb.sibling as usize, parent));
+        }
+    }
+
+    matrices
+}
+
+/// The transform of the node `node` and each of its descendants relative to
+/// the parent of `node`, composed from the local transforms of the nodes
+pub fn node_world_matrices(root: &json::Root, node: u32) -> Vec<(u32, Mat4)> {
+    let local = |node: &json::Node| match node.matrix {
+        Some(matrix) => Mat4::from_cols_array(&matrix),
+        None => Mat4::from_scale_rotation_translation(
+            Vec3::from(node.scale.unwrap_or([1.0; 3])),
+            node.rotation
+                .map_or(Quat::IDENTITY, |r| Quat::from_array(r.0)),
+            Vec3::from(node.translation.unwrap_or_default()),
+        ),
+    };
+
+    let mut matrices = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![(node, Mat4::IDENTITY)];
+    while let Some((index, parent)) = pending.pop() {
+        let Some(node) = root
+            .nodes
+            .get(index as usize)
+            .filter(|_| visited.insert(index))
+        else {
+            continue;
+        };
+        let matrix = parent * local(node);
+        matrices.push((index, matrix));
+        for child in node.children.iter().flatten() {
+            pending.push((child.value() as u32, matrix));
+        }
+    }
+
+    matrices
+}
+
+/// The pose of the limbs without an animation, with the root limb at its
+/// joint position
+fn rest_pose(limbs: &[Limb]) -> skeleton_animation::Pose {
+    skeleton_animation::Pose {
+        root_translation: Vec3::from(
+            limbs
+                .first()
+                .and_then(|limb| limb.joint_pos)
+                .unwrap_or_default(),
+        ),
+        rotations: Vec::new(),
+    }
+}
+
+/// Writes a skin whose joints are the limb nodes from `first_node` on, in
+/// limb order, bound at the given rest pose matrices of the limbs
+fn write_skin_into_gltf(
+    root: &mut json::Root,
+    rest_matrices: &[Mat4],
+    first_node: u32,
+) -> Index<json::Skin> {
+    let matrices = rest_matrices
         .iter()
-        .enumerate()
-        .filter(|(_, limb)| limb.child != 0xFF)
-    {
-        let children = root.nodes[index].children.get_or_insert_with(Vec::new);
+        .map(|matrix| matrix.inverse().to_cols_array())
+        .collect::<Vec<_>>();
+    let inverse_bind_matrices = root.add_accessor(
+        matrices.as_bytes(),
+        None,
+        builder::accessor(
+            matrices.len(),
+            json::accessor::ComponentType::F32,
+            json::accessor::Type::Mat4,
+        ),
+    );
+
+    root.add(json::Skin {
+        extensions: Default::default(),
+        extras: Default::default(),
+        inverse_bind_matrices: Some(inverse_bind_matrices),
+        joints: (0..rest_matrices.len() as u32)
+            .map(|limb_index| Index::new(first_node + limb_index))
+            .collect(),
+        name: None,
+        skeleton: Some(Index::new(first_node)),
+    })
+}
+
+/// Checks that the node hierarchy places every limb in its rest pose where
+/// the limbs place each other, reporting the limbs that end up elsewhere
+fn check_rest_pose(
+    root: &json::Root,
+    limbs: &[Limb],
+    first_node: u32,
+    report: &mut ExtractionReport,
+) {
+    let expected = limb_matrices(limbs, &rest_pose(limbs));
+
+    for (node, matrix) in node_world_matrices(root, first_node) {
+        let limb_index = (node - first_node) as usize;
+        let Some(expected) = expected.get(limb_index) else {
+            continue;
+        };
+        if !matrix.abs_diff_eq(*expected, 1e-3) {
+            report.warn(format!(
+                "Limb {} is at {} in the node hierarchy instead of {}",
+                limb_index,
+                matrix.w_axis.truncate(),
+                expected.w_axis.truncate()
+            ));
+        }
+    }
+}
+
+/// Makes every limb node a child of its parent's node. A limb's children are
+/// its `child` and the siblings following that child.
+fn build_node_hierarchy(root: &mut json::Root, limbs: &[Limb], first_node: u32) {
+    let mut visited = vec![false; limbs.len()];
+
+    // Walks the hierarchy from the root like `limb_matrices`, so that every
+    // limb gets at most one parent and a malformed hierarchy can't loop
+    let mut pending = vec![(0, None::<usize>)];
+    while let Some((limb_index, parent)) = pending.pop() {
+        let Some(limb) = limbs.get(limb_index).filter(|_| !visited[limb_index]) else {
+            log::warn!("Skipping limb {} reached twice or out of range", limb_index);
+            continue;
+        };
+        visited[limb_index] = true;
+
+        if let Some(parent) = parent {
+            root.nodes[first_node as usize + parent]
+                .children
+                .get_or_insert_with(Vec::new)
+                .push(Index::new(first_node + limb_index as u32));
+        }
+
+        if limb.child != 0xFF {
+            pending.push((limb.child as usize, Some(limb_index)));
+        }
+        if limb.sibling != 0xFF {
+            pending.push((limb.sibling as usize, parent));
+        }
+    }
+}
 
-        children.push(Index::new(*child as _));
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use gltf::json::validation::Checked::Valid;
+
+    use super::*;
+    use crate::{
+        display_list::Opcode,
+        test_rom::{self, object_addr, SegmentBuilder},
+    };
+
+    /// A skeleton header at 0 with two limbs, the first drawing a triangle
+    /// and the parent of the second
+    fn two_limb_skeleton() -> SegmentBuilder {
+        let builder = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 2)
+            .u32s(0x10, &[object_addr(0x20), object_addr(0x30)])
+            .skin_limb(0x20, [0, 0, 0], [1, 0xFF], 11, object_addr(0x100))
+            .skin_limb(0x30, [10, 20, 30], [0xFF, 0xFF], 0, 0);
+        test_rom::triangle_list(builder, 0x100, 0x200)
+    }
+
+    fn read_skeleton(reader: &rom::Reader) -> json::Root {
+        read_skeleton_with(reader, &Default::default())
+    }
+
+    fn read_skeleton_with(reader: &rom::Reader, mesh_options: &mesh::MeshOptions) -> json::Root {
+        let mut root = json::Root::default();
+        read_into_gltf(
+            &mut root,
+            reader,
+            RawVirtAddr::new(object_addr(0)).into(),
+            &[],
+            mesh_options,
+            &Default::default(),
+            None,
+        )
+        .unwrap();
+        root
+    }
 
-        let mut child_sibling = limbs[*child as usize].sibling;
-        while child_sibling != 0xFF {
-            children.push(Index::new(child_sibling as _));
-            child_sibling = limbs[child_sibling as usize].sibling;
+    /// An animation of the skeleton whose header is at `offset`
+    fn animation(name: &str, offset: usize) -> skeleton_animation::NamedAnimation {
+        skeleton_animation::NamedAnimation {
+            name: name.to_owned(),
+            addr: skeleton_animation::AnimationAddr::Standard(
+                RawVirtAddr::new(object_addr(offset)).into(),
+            ),
+            fps: None,
         }
     }
+
+    fn children(root: &json::Root, node: usize) -> Vec<usize> {
+        root.nodes[node]
+            .children
+            .iter()
+            .flatten()
+            .map(|child| child.value())
+            .collect()
+    }
+
+    /// The alpha modes of the primitives of a mesh
+    fn alpha_modes(root: &json::Root, mesh: usize) -> Vec<AlphaMode> {
+        root.meshes[mesh]
+            .primitives
+            .iter()
+            .map(
+                |primitive| match root.materials[primitive.material.unwrap().value()].alpha_mode {
+                    Valid(alpha_mode) => alpha_mode,
+                    _ => panic!("Invalid alpha mode"),
+                },
+            )
+            .collect()
+    }
+
+    #[test]
+    fn reads_both_display_lists_of_curve_limbs() {
+        let builder = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 1)
+            .u32s(0x10, &[object_addr(0x20)])
+            .curve_limb(0x20, [0xFF, 0xFF], [object_addr(0x100), object_addr(0x180)]);
+        let builder = test_rom::triangle_list(builder, 0x100, 0x200);
+        let builder = test_rom::triangle_list(builder, 0x180, 0x200);
+        let root = read_skeleton(&builder.into_object_reader());
+
+        assert_eq!(root.meshes.len(), 1);
+        assert_eq!(alpha_modes(&root, 0), [AlphaMode::Opaque, AlphaMode::Blend]);
+    }
+
+    #[test]
+    fn reads_limbs_disagreeing_on_the_layout_as_skin_limbs() {
+        let builder = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 2)
+            .u32s(0x10, &[object_addr(0x20), object_addr(0x30)])
+            .curve_limb(0x20, [1, 0xFF], [object_addr(0x100), object_addr(0x180)])
+            .skin_limb(0x30, [0, 0, 0], [0xFF, 0xFF], 11, object_addr(0x180));
+        let builder = test_rom::triangle_list(builder, 0x100, 0x200);
+        let reader = test_rom::triangle_list(builder, 0x180, 0x280).into_object_reader();
+
+        let mut root = json::Root::default();
+        let report = read_into_gltf(
+            &mut root,
+            &reader,
+            RawVirtAddr::new(object_addr(0)).into(),
+            &[],
+            &Default::default(),
+            &Default::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            report.warnings,
+            ["Only 1 of 2 limbs have a translucent display list, reading them as skin limbs"]
+        );
+        assert_eq!((report.limbs, report.meshes), (2, 1));
+    }
+
+    #[test]
+    fn finds_display_lists_after_the_skeleton_header() {
+        let reader = SegmentBuilder::new()
+            .skeleton_header(0x100, object_addr(0x10), 1)
+            .commands(0x108, &[test_rom::g_enddl()])
+            .commands(
+                0x110,
+                &[
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_tri1(2, 1, 0),
+                    test_rom::g_enddl(),
+                ],
+            )
+            .bytes(0x128, &[0; 0x10])
+            .into_object_reader();
+
+        let dlists =
+            find_adjacent_display_lists(&reader, RawVirtAddr::new(object_addr(0x100)).into())
+                .unwrap();
+        let offsets = dlists
+            .iter()
+            .map(RawVirtAddr::segment_offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [0x108, 0x110]);
+    }
+
+    #[test]
+    fn binds_skinned_vertices_to_their_limb() {
+        let builder = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 3)
+            .u32s(
+                0x10,
+                &[object_addr(0x20), object_addr(0x30), object_addr(0x40)],
+            )
+            .skin_limb(0x20, [0, 0, 0], [1, 0xFF], 11, object_addr(0x100))
+            .skin_limb(0x30, [0, 0, 0], [0xFF, 2], 0, 0)
+            .skin_limb(0x40, [0, 0, 0], [0xFF, 0xFF], 11, object_addr(0x180));
+        let builder = test_rom::triangle_list(builder, 0x100, 0x200);
+        let reader = test_rom::triangle_list(builder, 0x180, 0x280).into_object_reader();
+
+        let mesh_options = mesh::MeshOptions {
+            skin: true,
+            ..Default::default()
+        };
+        let root = read_skeleton_with(&reader, &mesh_options);
+        let attribute = |semantic| {
+            let semantic = Valid(semantic);
+            root.meshes[0]
+                .primitives
+                .iter()
+                .flat_map(|primitive| {
+                    test_rom::accessor_elements(&root, primitive.attributes[&semantic])
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let joints = attribute(json::mesh::Semantic::Joints(0));
+        let weights = attribute(json::mesh::Semantic::Weights(0));
+        assert_eq!(joints.len(), 6);
+        for (vertex, joint) in [(0, 0), (3, 2), (5, 2)] {
+            assert_eq!(joints[vertex], [joint, 0, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(weights[vertex][..4], 1.0f32.to_le_bytes());
+            assert!(weights[vertex][4..].iter().all(|byte| *byte == 0));
+        }
+    }
+
+    #[test]
+    fn keeps_limb_indices_past_a_null_limb_pointer() {
+        let reader = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 3)
+            .u32s(0x10, &[object_addr(0x20), 0, object_addr(0x40)])
+            .skin_limb(0x20, [0, 0, 0], [2, 0xFF], 0, 0)
+            .skin_limb(0x40, [1, 2, 3], [0xFF, 0xFF], 0, 0)
+            .into_object_reader();
+        let mut root = json::Root::default();
+        let warnings = test_rom::logged_warnings(|| root = read_skeleton(&reader));
+
+        assert_eq!(root.nodes.len(), 3);
+        assert_eq!(children(&root, 0), [2]);
+        assert_eq!(root.nodes[1].translation, None);
+        assert_eq!(root.nodes[2].translation, Some([1.0, 2.0, 3.0]));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Limb 1 is missing"));
+    }
+
+    #[test]
+    fn writes_joint_limits_to_the_extras_of_their_limb() {
+        let reader = two_limb_skeleton()
+            .i16s(0x300, &[-0x4000, 0, 0, 0x4000, 0, 0])
+            .i16s(0x30C, &[0, -0x2000, 0, 0, 0x2000, 0x1000])
+            .into_object_reader();
+        let mut root = read_skeleton(&reader);
+        read_joint_limits_into_gltf(
+            &mut root,
+            &reader,
+            RawVirtAddr::new(object_addr(0)).into(),
+            RawVirtAddr::new(object_addr(0x300)).into(),
+            0,
+        )
+        .unwrap();
+
+        let limits = |node: usize| {
+            let extras = root.nodes[node].extras.as_ref().unwrap();
+            json::deserialize::from_str::<json::Value>(extras.get()).unwrap()["rotation_limits"]
+                .clone()
+        };
+        let angles = |angles: [f32; 3]| json::Value::from(angles.to_vec());
+        assert_eq!(limits(0)["min"], angles([-90.0, 0.0, 0.0]));
+        assert_eq!(limits(0)["max"], angles([90.0, 0.0, 0.0]));
+        assert_eq!(limits(1)["min"], angles([0.0, -45.0, 0.0]));
+        assert_eq!(limits(1)["max"], angles([0.0, 45.0, 22.5]));
+    }
+
+    #[test]
+    fn samples_animated_skin_limbs_into_morph_targets() {
+        // The second limb is an animated skin limb with one vertex placed by
+        // the first limb, so rotating the second limb moves that vertex
+        // relative to it
+        let mut builder = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 2)
+            .u32s(0x10, &[object_addr(0x20), object_addr(0x30)])
+            .skin_limb(0x20, [0, 0, 0], [1, 0xFF], 0, 0)
+            .skin_limb(0x30, [100, 0, 0], [0xFF, 0xFF], 4, object_addr(0x40))
+            .u16s(0x40, &[3, 3])
+            .u32s(0x44, &[object_addr(0x50), object_addr(0x100)]);
+        for (i, (limb_index, offset)) in [(1, [0, 0, 0]), (1, [100, 0, 0]), (0, [100, 100, 0])]
+            .into_iter()
+            .enumerate()
+        {
+            let (modif, vertex, transformation) =
+                (0x50 + i * 0x10, 0x80 + i * 0x10, 0xB0 + i * 0x10);
+            builder = builder
+                .u16s(modif, &[1, 1])
+                .u32s(
+                    modif + 8,
+                    &[object_addr(vertex), object_addr(transformation)],
+                )
+                .u16s(vertex, &[i as u16])
+                .bytes(transformation, &[limb_index])
+                .i16s(transformation + 2, &offset)
+                .bytes(transformation + 8, &[100]);
+        }
+        // Two frames, the second rotating the second limb by 90 degrees
+        let frame_data = [[0, 0]; 8]
+            .into_iter()
+            .chain([[0, 0x4000]])
+            .flatten()
+            .collect::<Vec<i16>>();
+        let reader = builder
+            .commands(
+                0x100,
+                &[
+                    test_rom::g_vtx(0x08000000, 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            )
+            .animation_header(0x200, 2, object_addr(0x280), object_addr(0x240), 0)
+            .u16s(0x240, &(0..9).map(|n| n * 2).collect::<Vec<_>>())
+            .i16s(0x280, &frame_data)
+            .into_object_reader();
+
+        let animations = [animation("wave", 0x200)];
+        let animation_options = skeleton_animation::AnimationOptions {
+            skin_morph_targets: true,
+            ..Default::default()
+        };
+        let mut root = json::Root::default();
+        read_into_gltf(
+            &mut root,
+            &reader,
+            RawVirtAddr::new(object_addr(0)).into(),
+            &animations,
+            &Default::default(),
+            &animation_options,
+            None,
+        )
+        .unwrap();
+
+        let mesh = root.nodes[1].mesh.unwrap().value();
+        let targets = root.meshes[mesh].primitives[0].targets.as_ref().unwrap();
+        let positions = targets
+            .iter()
+            .map(|target| test_rom::accessor_elements(&root, target.positions.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(positions.len(), 2);
+        assert_ne!(positions[0], positions[1]);
+
+        let channel = root.animations[0]
+            .channels
+            .iter()
+            .find(|channel| {
+                channel.target.path == Valid(json::animation::Property::MorphTargetWeights)
+            })
+            .unwrap();
+        assert_eq!(channel.target.node.value(), 1);
+        let sampler = &root.animations[0].samplers[channel.sampler.value()];
+        let weights = test_rom::accessor_elements(&root, sampler.output)
+            .iter()
+            .map(|bytes| f32::from_le_bytes(bytes[..].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(weights, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn reports_what_was_extracted_and_skipped() {
+        let reader = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x10), 2)
+            .u32s(0x10, &[object_addr(0x20), object_addr(0x30)])
+            .skin_limb(0x20, [0, 0, 0], [1, 0xFF], 11, object_addr(0x100))
+            .skin_limb(0x30, [10, 20, 30], [0xFF, 0xFF], 11, object_addr(0x100))
+            .vertices(
+                0x200,
+                &[
+                    ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                    ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ],
+            )
+            .commands(
+                0x100,
+                &[
+                    0xE7 << 56,
+                    test_rom::g_vtx(object_addr(0x200), 3, 0),
+                    test_rom::g_tri1(0, 1, 2),
+                    test_rom::g_enddl(),
+                ],
+            )
+            .animation_header(0x300, 1, object_addr(0x380), object_addr(0x340), 0)
+            .u16s(0x340, &[0; 9])
+            .i16s(0x380, &[0])
+            .into_object_reader();
+
+        let animations = [animation("idle", 0x300)];
+        let mut root = json::Root::default();
+        let report = read_into_gltf(
+            &mut root,
+            &reader,
+            RawVirtAddr::new(object_addr(0)).into(),
+            &animations,
+            &Default::default(),
+            &Default::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!((report.limbs, report.meshes, report.animations), (2, 2, 1));
+        assert_eq!(
+            report.skipped_opcodes,
+            BTreeMap::from([(Opcode::RDPPIPESYNC, 2)])
+        );
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn parents_every_sibling_of_a_child_to_the_same_limb() {
+        let links: [(u8, u8); 8] = [
+            (1, 0xFF),
+            (3, 2),
+            (5, 0xFF),
+            (0xFF, 4),
+            (6, 0xFF),
+            (0xFF, 0xFF),
+            (0xFF, 7),
+            (0xFF, 0xFF),
+        ];
+        let limbs = links
+            .iter()
+            .map(|&(child, sibling)| Limb {
+                child,
+                sibling,
+                joint_pos: None,
+                mesh: None,
+                kind: "normal",
+                skin_data: None,
+            })
+            .collect::<Vec<_>>();
+
+        // The limbs follow a node written before the skeleton
+        let mut root = json::Root::default();
+        for _ in 0..=limbs.len() {
+            root.nodes.push(json::deserialize::from_str("{}").unwrap());
+        }
+        build_node_hierarchy(&mut root, &limbs, 1);
+
+        let limb_children = (0..limbs.len())
+            .map(|limb_index| {
+                let mut children = children(&root, 1 + limb_index)
+                    .into_iter()
+                    .map(|node| node - 1)
+                    .collect::<Vec<_>>();
+                children.sort();
+                children
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            limb_children,
+            [
+                vec![1, 2],
+                vec![3, 4],
+                vec![5],
+                vec![],
+                vec![6, 7],
+                vec![],
+                vec![],
+                vec![],
+            ]
+        );
+        assert!(root.nodes[0].children.is_none());
+    }
+
+    #[test]
+    fn rejects_headers_that_are_not_skeletons() {
+        let addr = RawVirtAddr::new(object_addr(0)).into();
+        let error = |builder: SegmentBuilder| {
+            read_skeleton_header(&builder.into_object_reader(), addr)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let zeroed = SegmentBuilder::new().bytes(0, &[0; 8]);
+        assert!(error(zeroed).contains("NULL pointer"));
+
+        let no_limbs = SegmentBuilder::new().skeleton_header(0, object_addr(0x10), 0);
+        assert!(error(no_limbs).contains("has 0 limbs"));
+
+        let too_many = SegmentBuilder::new().skeleton_header(0, object_addr(0x10), 0xFF);
+        assert!(error(too_many).contains("has 255 limbs"));
+
+        assert!(read_skeleton_header(&two_limb_skeleton().into_object_reader(), addr).is_ok());
+    }
+
+    #[test]
+    fn composes_world_matrices_down_the_hierarchy() {
+        // The first node turns its children by 90 degrees around Z
+        let quarter = std::f32::consts::FRAC_1_SQRT_2;
+        let empty: json::Node = json::deserialize::from_str("{}").unwrap();
+        let mut root = json::Root::default();
+        root.nodes.push(json::Node {
+            children: Some(vec![Index::new(1)]),
+            rotation: Some(json::scene::UnitQuaternion([0.0, 0.0, quarter, quarter])),
+            translation: Some([1.0, 0.0, 0.0]),
+            ..empty.clone()
+        });
+        root.nodes.push(json::Node {
+            children: Some(vec![Index::new(2)]),
+            translation: Some([0.0, 2.0, 0.0]),
+            ..empty.clone()
+        });
+        root.nodes.push(json::Node {
+            translation: Some([3.0, 0.0, 0.0]),
+            ..empty
+        });
+
+        let mut matrices = node_world_matrices(&root, 0);
+        matrices.sort_by_key(|(node, _)| *node);
+        let origins = matrices
+            .iter()
+            .map(|(_, matrix)| matrix.transform_point3(Vec3::ZERO))
+            .collect::<Vec<_>>();
+        let expected = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 3.0, 0.0),
+        ];
+        assert_eq!(origins.len(), expected.len());
+        for (origin, expected) in origins.iter().zip(expected) {
+            assert!(
+                origin.abs_diff_eq(expected, 1e-5),
+                "{} != {}",
+                origin,
+                expected
+            );
+        }
+
+        // Limbs write theirs to their extras
+        let mesh_options = mesh::MeshOptions {
+            world_matrix_extras: true,
+            ..Default::default()
+        };
+        let root = read_skeleton_with(&two_limb_skeleton().into_object_reader(), &mesh_options);
+        let extras: json::Value =
+            json::deserialize::from_str(root.nodes[1].extras.as_ref().unwrap().get()).unwrap();
+        assert_eq!(
+            extras["world_matrix"],
+            json::Value::from(
+                Mat4::from_translation(Vec3::new(10.0, 20.0, 30.0))
+                    .to_cols_array()
+                    .to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn binds_no_joints_without_a_skin() {
+        let root = read_skeleton(&two_limb_skeleton().into_object_reader());
+
+        let attributes = &root.meshes[0].primitives[0].attributes;
+        assert!(attributes.contains_key(&Valid(json::mesh::Semantic::Positions)));
+        assert!(!attributes.contains_key(&Valid(json::mesh::Semantic::Joints(0))));
+        assert!(!attributes.contains_key(&Valid(json::mesh::Semantic::Weights(0))));
+    }
+
+    #[test]
+    fn reads_limbs_into_nodes() {
+        let mut root = json::Root::default();
+        let report = read_into_gltf(
+            &mut root,
+            &two_limb_skeleton().into_object_reader(),
+            RawVirtAddr::new(object_addr(0)).into(),
+            &[],
+            &Default::default(),
+            &Default::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(root.nodes.len(), 2);
+        assert_eq!(children(&root, 0), [1]);
+        assert_eq!(root.nodes[1].translation, Some([10.0, 20.0, 30.0]));
+        assert!(root.nodes[0].mesh.is_some());
+        assert!(root.nodes[1].mesh.is_none());
+        assert_eq!((report.limbs, report.meshes), (2, 1));
+    }
+
+    #[test]
+    fn refuses_negative_frame_counts_and_skips_empty_animations() {
+        let reader = two_limb_skeleton()
+            .animation_header(0x300, -1, object_addr(0x380), object_addr(0x340), 0)
+            .animation_header(0x310, 0, object_addr(0x380), object_addr(0x340), 0)
+            .u16s(0x340, &[0; 9])
+            .into_object_reader();
+        let read = |animation| {
+            let mut root = json::Root::default();
+            read_into_gltf(
+                &mut root,
+                &reader,
+                RawVirtAddr::new(object_addr(0)).into(),
+                &[animation],
+                &Default::default(),
+                &Default::default(),
+                None,
+            )
+            .map(|report| (root, report))
+        };
+
+        let error = read(animation("backwards", 0x300)).unwrap_err();
+        assert!(format!("{:#}", error).contains("has a frame count of -1"));
+
+        let (root, report) = read(animation("still", 0x310)).unwrap();
+        assert!(root.animations.is_empty());
+        assert_eq!(report.animations, 0);
+        assert_eq!(
+            report.warnings,
+            ["Skipping animation still, which has no frames"]
+        );
+    }
 }
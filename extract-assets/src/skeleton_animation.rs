@@ -1,35 +1,335 @@
-use std::mem;
+use std::{fmt::Display, mem, ops::Range};
 
-use anyhow::{Context, Result};
-use base64::prelude::*;
-use glam::Quat;
+use anyhow::{bail, Context, Result};
+use glam::{Mat4, Quat, Vec3};
 use gltf::json::{self, validation::Checked::Valid};
 use zerocopy::AsBytes;
 
-use crate::{addr::VirtAddr, math, rom};
+use crate::{
+    addr::VirtAddr,
+    builder::{self, GltfBuilder},
+    extras, math, rom,
+};
+
+#[derive(Clone, Debug)]
+pub struct AnimationOptions {
+    /// Seconds between two frames, 1/20 for the game's 20 frames per second
+    pub frame_duration: f32,
+
+    /// Added to every time sample, so that clips can be laid out end to end
+    /// on a shared timeline
+    pub time_offset: f32,
+
+    /// Leave out the rotation channels of limbs that hold the same rotation
+    /// in every frame, so that their nodes keep their static rotation. The
+    /// channels of the root are always written.
+    pub skip_constant_channels: bool,
+
+    /// Whether the animations loop or hold their last frame, written as a
+    /// `loop` hint to their extras. When unset, only animations with "idle"
+    /// in their name loop.
+    pub looping: Option<bool>,
+
+    /// Sample the vertices of animated skin limbs in every frame into morph
+    /// targets, which the animations then blend between
+    pub skin_morph_targets: bool,
+
+    /// How the samplers interpolate between frames. `Step` holds every
+    /// frame until the next, for animations authored as stepped keyframes.
+    pub interpolation: json::animation::Interpolation,
+
+    /// Factor the root translations are scaled by. Skeletons are extracted
+    /// with the `scale` of their `MeshOptions`, so that these match the limbs.
+    pub scale: f32,
+
+    /// Also write the values of every joint in every frame as read, before
+    /// their conversion to quaternions, to the `frames` of the animation's
+    /// extras. Joint 0 is the root translation, the others the rotations of
+    /// the limbs as the X, Y and Z angles taken by `math::rotate_zyx`.
+    pub euler_extras: bool,
+}
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            frame_duration: 1.0 / 20.0,
+            time_offset: 0.0,
+            skip_constant_channels: false,
+            looping: None,
+            skin_morph_targets: false,
+            interpolation: json::animation::Interpolation::Linear,
+            scale: 1.0,
+            euler_extras: false,
+        }
+    }
+}
+
+/// An animation to extract, under the name given to it in the glTF
+#[derive(Clone)]
+pub struct NamedAnimation {
+    pub name: String,
+    pub addr: AnimationAddr,
+
+    /// The frames per second it plays back at, instead of the rate of the
+    /// `AnimationOptions`
+    pub fps: Option<f32>,
+}
+
+/// The address of an animation header, telling the format of its frames
+#[derive(Clone, Copy, Debug)]
+pub enum AnimationAddr {
+    /// An `AnimationHeader`, as used by the skeletons of objects
+    Standard(VirtAddr<rom::AnimationHeader>),
+
+    /// A `LinkAnimationHeader`, whose frames are read from the link animation
+    /// segment, which has to be loaded separately
+    Link(VirtAddr<rom::LinkAnimationHeader>),
+}
+impl Display for AnimationAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnimationAddr::Standard(addr) => Display::fmt(addr, f),
+            AnimationAddr::Link(addr) => Display::fmt(addr, f),
+        }
+    }
+}
+
+/// An animation header read from an `AnimationAddr`
+enum AnimationHeader {
+    Standard(rom::AnimationHeader),
+    Link(rom::LinkAnimationHeader),
+}
+impl AnimationHeader {
+    fn read(reader: &rom::Reader, addr: AnimationAddr) -> Result<Self> {
+        Ok(match addr {
+            AnimationAddr::Standard(addr) => AnimationHeader::Standard(
+                reader
+                    .read(addr)
+                    .context("Failed to read animation header")?,
+            ),
+            AnimationAddr::Link(addr) => AnimationHeader::Link(
+                reader
+                    .read(addr)
+                    .context("Failed to read Link animation header")?,
+            ),
+        })
+    }
+
+    fn common(&self) -> &rom::AnimationHeaderCommon {
+        match self {
+            AnimationHeader::Standard(header) => &header.common,
+            AnimationHeader::Link(header) => &header.common,
+        }
+    }
+}
+
+/// The meshes of the limbs in `nodes` have `target_count` morph targets, of
+/// which the `targets` hold the frames of the animation being read
+pub struct MorphWeights<'a> {
+    pub nodes: &'a [u32],
+    pub targets: Range<usize>,
+    pub target_count: usize,
+}
+
+/// The pose of a skeleton in one frame
+pub struct Pose {
+    pub root_translation: Vec3,
+
+    /// The rotation of every limb
+    pub rotations: Vec<Mat4>,
+}
+
+/// Largest difference per component between two samples still considered
+/// equal by `skip_constant_channels`
+const CONSTANT_EPSILON: f32 = 1e-5;
+
+/// The number of frames of an animation, refusing negative counts, which
+/// only come from misread headers
+pub fn frame_count(reader: &rom::Reader, addr: AnimationAddr) -> Result<usize> {
+    let animation_header = AnimationHeader::read(reader, addr)?;
+    checked_frame_count(&animation_header, addr)
+}
+
+fn checked_frame_count(animation_header: &AnimationHeader, addr: AnimationAddr) -> Result<usize> {
+    let frame_count = animation_header.common().frame_count.get();
+    if frame_count < 0 {
+        bail!(
+            "Animation at {} has a frame count of {}. Is it an animation?",
+            addr,
+            frame_count
+        );
+    }
+    Ok(frame_count as usize)
+}
 
 pub fn read_into_gltf(
     root: &mut json::Root,
     reader: &crate::rom::Reader,
     skeleton_header: &rom::SkeletonHeader,
-    addr: VirtAddr<rom::AnimationHeader>,
+    name: &str,
+    addr: AnimationAddr,
+    morph_weights: Option<&MorphWeights>,
+    options: &AnimationOptions,
 ) -> Result<()> {
-    log::info!("Reading skeleton animation");
+    log::info!("Reading skeleton animation {}", name);
 
-    let animation_header = reader
-        .read(addr)
-        .context("Failed to read animation header")?;
+    let animation_header = AnimationHeader::read(reader, addr)?;
 
-    log::info!("Adding times buffer");
-    write_times_buffer_to_gltf(root, animation_header.common.frame_count.get() as _);
+    checked_frame_count(&animation_header, addr)?;
+    if let AnimationHeader::Standard(animation_header) = &animation_header {
+        check_static_index_max(reader, animation_header, skeleton_header.limb_count as _)?;
+    }
 
     log::info!("Adding animation frame buffers");
-    write_animation_frames_to_gltf(root, reader, &animation_header, skeleton_header)?;
+    write_animation_frames_to_gltf(
+        root,
+        reader,
+        name,
+        &animation_header,
+        skeleton_header,
+        morph_weights,
+        options,
+    )?;
+
+    Ok(())
+}
+
+/// Reads the pose of the skeleton in every frame of an animation
+pub fn read_poses(
+    reader: &rom::Reader,
+    skeleton_header: &rom::SkeletonHeader,
+    addr: AnimationAddr,
+) -> Result<Vec<Pose>> {
+    let animation_header = AnimationHeader::read(reader, addr)?;
+
+    (0..checked_frame_count(&animation_header, addr)?)
+        .map(|frame_index| {
+            let mut pose = Pose {
+                root_translation: Vec3::ZERO,
+                rotations: Vec::with_capacity(skeleton_header.limb_count as _),
+            };
+            for_each_frame_data(
+                reader,
+                &animation_header,
+                frame_index as _,
+                skeleton_header.limb_count as _,
+                |joint, x, y, z| match joint {
+                    0 => pose.root_translation = Vec3::new(x as _, y as _, z as _),
+                    _ => pose.rotations.push(math::rotate_zyx(x, y, z)),
+                },
+            )?;
+            Ok(pose)
+        })
+        .collect()
+}
+
+/// Joint indices below `static_index_max` refer to values shared by all
+/// frames, the others to values that change per frame. A maximum of 0 makes
+/// every value dynamic and one above every joint index makes every value
+/// static. Both are valid, but worth reporting as they often mean a misread
+/// header.
+fn check_static_index_max(
+    reader: &rom::Reader,
+    animation_header: &rom::AnimationHeader,
+    limb_count: usize,
+) -> Result<()> {
+    let static_index_max = animation_header.static_index_max.get();
+
+    let joint_indicies = reader
+        .read_slice(animation_header.joint_indicies, limb_count + 1)
+        .context("Failed to read joint indicies")?;
+    let max_joint_index = joint_indicies
+        .iter()
+        .flat_map(|index| [index.x.get(), index.y.get(), index.z.get()])
+        .max()
+        .unwrap_or_default();
+
+    if static_index_max == 0 {
+        log::debug!("  static_index_max is 0, all animation values are dynamic");
+    } else if static_index_max > max_joint_index {
+        log::warn!(
+            "  static_index_max {} exceeds every joint index (max {}), the animation is static",
+            static_index_max,
+            max_joint_index
+        );
+    }
 
     Ok(())
 }
 
+/// Calls `f` with the root translation (joint 0) followed by the rotation of
+/// every limb (joint `limb_index + 1`) of the given frame
 fn for_each_frame_data<F>(
+    reader: &rom::Reader,
+    animation_header: &AnimationHeader,
+    frame_index: usize,
+    limb_count: usize,
+    f: F,
+) -> Result<()>
+where
+    F: FnMut(usize, i16, i16, i16),
+{
+    match animation_header {
+        AnimationHeader::Standard(animation_header) => {
+            for_each_standard_frame_data(reader, animation_header, frame_index, limb_count, f)
+        }
+        AnimationHeader::Link(animation_header) => {
+            for_each_link_frame_data(reader, animation_header, frame_index, limb_count, f)
+        }
+    }
+}
+
+/// Link's animations hold every joint of every frame, like the game's
+/// `AnimationContext_SetLoadFrame`. Each frame is the root translation and
+/// the rotation of every limb, followed by a value selecting the textures of
+/// the face, which is skipped.
+fn for_each_link_frame_data<F>(
+    reader: &rom::Reader,
+    animation_header: &rom::LinkAnimationHeader,
+    frame_index: usize,
+    limb_count: usize,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(usize, i16, i16, i16),
+{
+    let joint_count = limb_count + 1;
+    let frame_size = joint_count * mem::size_of::<rom::JointIndex>() + mem::size_of::<i16>();
+
+    let frame = reader
+        .read_slice(
+            animation_header
+                .segment
+                .offset_bytes((frame_index * frame_size) as i32),
+            joint_count * 3,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to read frame {} of Link animation. Is the link animation file loaded \
+                 into segment {}?",
+                frame_index,
+                rom::Segment::LinkAnimation as u8
+            )
+        })?;
+
+    for (joint, values) in frame.chunks_exact(3).enumerate() {
+        let [x, y, z] = [values[0].get(), values[1].get(), values[2].get()];
+
+        log::trace!(
+            "  - Frame [{: >3}]  Joint [{: >3}]  Pos [{: >6}, {: >6}, {: >6}]",
+            frame_index,
+            joint,
+            x,
+            y,
+            z,
+        );
+
+        f(joint, x, y, z);
+    }
+
+    Ok(())
+}
+
+fn for_each_standard_frame_data<F>(
     reader: &rom::Reader,
     animation_header: &rom::AnimationHeader,
     frame_index: usize,
@@ -37,30 +337,23 @@ fn for_each_frame_data<F>(
     mut f: F,
 ) -> Result<()>
 where
-    F: FnMut(usize, i16, i16, i16) -> (),
+    F: FnMut(usize, i16, i16, i16),
 {
     let static_index_max = animation_header.static_index_max.get();
 
     let joint_indicies = reader
-        .read_slice(animation_header.joint_indicies, limb_count as usize + 1)
+        .read_slice(animation_header.joint_indicies, limb_count + 1)
         .context("Failed to read joint indicies")?;
-    let frame_data = |n: i16| {
+    // `n` indexes the i16 values, not bytes
+    let frame_data = |n: i32| {
         reader
-            .read(animation_header.frame_data + n as i32)
+            .read(animation_header.frame_data + n)
             .map(|n| n.get())
+            .map_err(Into::into)
     };
-    let static_data = |n: u16| frame_data(n as i16);
-    let dynamic_data = |n: u16| frame_data(frame_index as i16 + n as i16);
-    let read_data = |n: u16| {
-        if n >= static_index_max {
-            dynamic_data(n)
-        } else {
-            static_data(n)
-        }
-    };
+    let read_data = |n: u16| resolve_frame_value(static_index_max, frame_index, n, frame_data);
 
-    for limb_index in 0..limb_count {
-        let joint_index = &joint_indicies[limb_index as usize + 1];
+    for (joint, joint_index) in joint_indicies.iter().enumerate() {
         let x = read_data(joint_index.x.get())?;
         let y = read_data(joint_index.y.get())?;
         let z = read_data(joint_index.z.get())?;
@@ -76,92 +369,99 @@ where
             z,
         );
 
-        f(limb_index, x, y, z);
+        f(joint, x, y, z);
     }
 
     Ok(())
 }
 
-fn write_times_buffer_to_gltf(root: &mut json::Root, frame_count: usize) {
+/// Reads the value of a joint index in the given frame, like the game's
+/// `SkelAnime_GetFrameData`. Indices below `static_index_max` hold the same
+/// value in every frame, the others start a run of one value per frame. The
+/// comparison is unsigned, as indices may exceed `i16::MAX`, and so is the
+/// offset into `frame_data`.
+fn resolve_frame_value(
+    static_index_max: u16,
+    frame_index: usize,
+    joint_index: u16,
+    frame_data: impl Fn(i32) -> Result<i16>,
+) -> Result<i16> {
+    if joint_index >= static_index_max {
+        frame_data(frame_index as i32 + joint_index as i32)
+    } else {
+        frame_data(joint_index as i32)
+    }
+}
+
+fn write_times_buffer_to_gltf(
+    root: &mut json::Root,
+    frame_count: usize,
+    frame_duration: f32,
+    time_offset: f32,
+) -> json::Index<json::Accessor> {
     let times = (0..frame_count)
-        .enumerate()
-        .map(|(i, _)| i as f32 * 0.1)
+        .map(|i| time_offset + i as f32 * frame_duration)
         .collect::<Vec<_>>();
 
-    root.buffers.push(json::Buffer {
-        byte_length: mem::size_of_val(&*times) as _,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        uri: Some(format!(
-            "data:application/octet-stream;base64,{}",
-            BASE64_STANDARD.encode(times.as_bytes())
-        )),
-    });
-    root.buffer_views.push(json::buffer::View {
-        buffer: json::Index::new(root.buffers.len() as u32 - 1),
-        byte_length: mem::size_of_val(&*times) as _,
-        byte_offset: None,
-        byte_stride: None,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        target: None,
-    });
-    root.accessors.push(json::Accessor {
-        buffer_view: Some(json::Index::new(root.buffer_views.len() as u32 - 1)),
-        byte_offset: 0,
-        count: times.len() as u32,
-        component_type: Valid(json::accessor::GenericComponentType(
-            json::accessor::ComponentType::F32,
-        )),
-        extensions: Default::default(),
-        extras: Default::default(),
-        type_: Valid(json::accessor::Type::Scalar),
-        min: times.first().map(|n| json::Value::from(vec![*n])),
-        max: times.last().map(|n| json::Value::from(vec![*n])),
-        name: None,
-        normalized: false,
-        sparse: None,
-    });
+    root.add_accessor(
+        times.as_bytes(),
+        None,
+        json::Accessor {
+            min: times.first().map(|n| json::Value::from(vec![*n])),
+            max: times.last().map(|n| json::Value::from(vec![*n])),
+            ..builder::accessor(
+                times.len(),
+                json::accessor::ComponentType::F32,
+                json::accessor::Type::Scalar,
+            )
+        },
+    )
 }
 
 fn write_animation_frames_to_gltf(
     root: &mut json::Root,
     reader: &rom::Reader,
-    animation_header: &rom::AnimationHeader,
+    name: &str,
+    animation_header: &AnimationHeader,
     skeleton_header: &rom::SkeletonHeader,
+    morph_weights: Option<&MorphWeights>,
+    options: &AnimationOptions,
 ) -> Result<()> {
-    let times_accessor_index = root.accessors.len() as u32 - 1;
+    let frame_count = animation_header.common().frame_count.get().max(0) as usize;
 
-    let mut frame_table = vec![Vec::<[f32; 4]>::new(); skeleton_header.limb_count as usize + 1];
-    for frame_index in 0..animation_header.common.frame_count.get() {
+    log::info!("Adding times buffer");
+    let times = write_times_buffer_to_gltf(
+        root,
+        frame_count,
+        options.frame_duration,
+        options.time_offset,
+    );
+
+    let mut translations = Vec::<[f32; 3]>::new();
+    let mut frame_table = vec![Vec::<[f32; 4]>::new(); skeleton_header.limb_count as usize];
+    let mut raw_frames = Vec::<Vec<[i16; 3]>>::new();
+    for frame_index in 0..frame_count {
+        let mut raw_frame = Vec::new();
         for_each_frame_data(
             reader,
-            &animation_header,
+            animation_header,
             frame_index as _,
             skeleton_header.limb_count as _,
-            |limb_index, x, y, z| {
-                // let q = Quat::from_euler(EulerRot::ZYX, x as _, y as _, z as _);
-                // dbg!(x, y, z, q);
-
-                //frame_table[limb_index].push(
-                //    Quaternion::from(Euler::new(Rad(x as f32), Rad(y as f32), Rad(z as f32)))
-                //        .into(),
-                //)
-
-                //frame_table[limb_index]
-                //    .push(Quat::from_euler(EulerRot::XYZ, x as _, y as _, z as _).to_array())
-
-                //let eul = EulerAngles::<_, IntraZYX>::from([x as f32, y as f32, z as f32]);
-                // let x = Quaternion::from(eul);
-
-                //dbg!(x, y, z);
-                //dbg!(Quat::from_mat4(&math::rotate_zyx(x, y, z)).to_array());
-
-                frame_table[limb_index].push(Quat::from_mat4(&math::rotate_zyx(x, y, z)).to_array())
+            |joint, x, y, z| {
+                if options.euler_extras {
+                    raw_frame.push([x, y, z]);
+                }
+                match joint {
+                    0 => translations.push([x, y, z].map(|n| n as f32 * options.scale)),
+                    _ => frame_table[joint - 1].push(math::rotate_zyx_quat(x, y, z).to_array()),
+                }
             },
         )?;
+        raw_frames.push(raw_frame);
+    }
+
+    for rotations in &mut frame_table {
+        ensure_shortest_arc(rotations);
     }
 
     let mut animation = json::animation::Animation {
@@ -169,77 +469,578 @@ fn write_animation_frames_to_gltf(
         channels: Default::default(),
         extensions: Default::default(),
         extras: Default::default(),
-        name: Some(String::from("anim")),
+        name: Some(name.to_owned()),
     };
-    for limb_index in 0..skeleton_header.limb_count {
-        let sampler_index = animation.samplers.len() as u32;
-        let buffer_view_index = root.buffer_views.len() as u32;
-        let buffer_index = root.buffers.len() as u32;
-        let accessor_index = root.accessors.len() as u32;
 
-        let bytes = frame_table[limb_index as usize].as_bytes();
+    let animation_name = name;
+    // Every channel gets its own sampler (and output accessor), while all
+    // samplers share the times accessor as input
+    let mut add_channel = |node: u32,
+                           path: json::animation::Property,
+                           type_: json::accessor::Type,
+                           bytes: &[u8],
+                           bounds: Option<(Vec<f32>, Vec<f32>)>| {
+        let name = match path {
+            json::animation::Property::Translation => "translations",
+            json::animation::Property::MorphTargetWeights => "weights",
+            _ => "rotations",
+        };
+        let components = match type_ {
+            json::accessor::Type::Vec3 => 3,
+            json::accessor::Type::Vec4 => 4,
+            _ => 1,
+        };
 
-        root.buffers.push(json::Buffer {
-            byte_length: bytes.len() as u32,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: Some(String::from("rotations")),
-            uri: Some(format!(
-                "data:application/octet-stream;base64,{}",
-                BASE64_STANDARD.encode(bytes)
-            )),
-        });
+        // A sampler's output holds one sample per input time, per target for
+        // morph target weights
+        let count = bytes.len() / (components * 4);
+        let targets = match (path, morph_weights) {
+            (json::animation::Property::MorphTargetWeights, Some(morph_weights)) => {
+                morph_weights.target_count
+            }
+            _ => 1,
+        };
+        assert_eq!(
+            count,
+            frame_count * targets,
+            "The {} of animation {} don't match its frame count",
+            name,
+            animation_name
+        );
 
-        root.buffer_views.push(json::buffer::View {
-            buffer: json::Index::new(buffer_index),
-            byte_length: bytes.len() as u32,
-            byte_offset: None,
-            byte_stride: None,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: Some(String::from("rotations")),
-            target: None,
-        });
+        let (min, max) = bounds.unzip();
+        let output = root.add_accessor(
+            bytes,
+            None,
+            json::Accessor {
+                min: min.map(json::Value::from),
+                max: max.map(json::Value::from),
+                name: Some(String::from(name)),
+                ..builder::accessor(count, json::accessor::ComponentType::F32, type_)
+            },
+        );
 
-        root.accessors.push(json::Accessor {
-            buffer_view: Some(json::Index::new(buffer_view_index)),
-            byte_offset: 0,
-            count: animation_header.common.frame_count.get() as _,
-            component_type: Valid(json::accessor::GenericComponentType(
-                json::accessor::ComponentType::F32,
-            )),
+        animation.samplers.push(json::animation::Sampler {
+            input: times,
+            interpolation: Valid(options.interpolation),
+            output,
             extensions: Default::default(),
             extras: Default::default(),
-            type_: Valid(json::accessor::Type::Vec4),
-            min: None,
-            max: None,
-            name: Some(String::from("rotations")),
-            normalized: false,
-            sparse: None,
         });
 
         animation.channels.push(json::animation::Channel {
-            sampler: json::Index::new(sampler_index),
+            sampler: json::Index::new(animation.samplers.len() as u32 - 1),
             target: json::animation::Target {
-                node: json::Index::new(limb_index as _),
-                path: Valid(json::animation::Property::Rotation),
+                node: json::Index::new(node),
+                path: Valid(path),
                 extensions: Default::default(),
                 extras: Default::default(),
             },
             extensions: Default::default(),
             extras: Default::default(),
         });
+    };
 
-        animation.samplers.push(json::animation::Sampler {
-            input: json::Index::new(times_accessor_index),
-            interpolation: Valid(json::animation::Interpolation::Linear),
-            output: json::Index::new(accessor_index),
-            extensions: Default::default(),
-            extras: Default::default(),
-        });
+    add_channel(
+        0,
+        json::animation::Property::Translation,
+        json::accessor::Type::Vec3,
+        translations.as_bytes(),
+        component_bounds(&translations),
+    );
+
+    for (limb_index, rotations) in frame_table.iter().enumerate() {
+        if options.skip_constant_channels && limb_index != 0 && is_constant(rotations) {
+            log::debug!(
+                "  Skipping constant rotation channel of limb {}",
+                limb_index
+            );
+            continue;
+        }
+
+        add_channel(
+            limb_index as _,
+            json::animation::Property::Rotation,
+            json::accessor::Type::Vec4,
+            rotations.as_bytes(),
+            component_bounds(rotations),
+        );
     }
 
-    root.animations.push(animation);
+    if let Some(morph_weights) = morph_weights {
+        // Each frame fully weights its own target, so that blending between
+        // two frames blends between their sampled vertices
+        let weights = morph_weights
+            .targets
+            .clone()
+            .flat_map(|frame_target| {
+                (0..morph_weights.target_count).map(move |target| {
+                    if target == frame_target {
+                        1.0f32
+                    } else {
+                        0.0
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for node in morph_weights.nodes {
+            add_channel(
+                *node,
+                json::animation::Property::MorphTargetWeights,
+                json::accessor::Type::Scalar,
+                weights.as_bytes(),
+                None,
+            );
+        }
+    }
+
+    let looping = options
+        .looping
+        .unwrap_or_else(|| name.to_lowercase().contains("idle"));
+    animation.extras = extras::to_extras(json::Value::from_iter([("loop", looping)]))?;
+    if options.euler_extras {
+        extras::insert(
+            &mut animation.extras,
+            "frames",
+            json::Value::from_iter(raw_frames.iter().map(|raw_frame| {
+                json::Value::from_iter(
+                    raw_frame
+                        .iter()
+                        .map(|values| json::Value::from(&values[..])),
+                )
+            })),
+        )?;
+    }
+
+    root.add(animation);
 
     Ok(())
 }
+
+/// Flips the sign of each quaternion that lies in the opposite hemisphere of
+/// the previous frame's, so that LINEAR interpolation takes the shortest arc
+fn ensure_shortest_arc(rotations: &mut [[f32; 4]]) {
+    for i in 1..rotations.len() {
+        let previous = Quat::from_array(rotations[i - 1]);
+        let current = Quat::from_array(rotations[i]);
+        if previous.dot(current) < 0.0 {
+            rotations[i] = (-current).to_array();
+        }
+    }
+}
+
+/// Whether every value equals the first one within `CONSTANT_EPSILON`
+fn is_constant<const N: usize>(values: &[[f32; N]]) -> bool {
+    let Some(first) = values.first() else {
+        return true;
+    };
+    values.iter().all(|value| {
+        first
+            .iter()
+            .zip(value)
+            .all(|(a, b)| (a - b).abs() <= CONSTANT_EPSILON)
+    })
+}
+
+/// Component-wise minimum and maximum of the given values
+fn component_bounds<const N: usize>(values: &[[f32; N]]) -> Option<(Vec<f32>, Vec<f32>)> {
+    let first = values.first()?;
+    Some(values.iter().fold(
+        (first.to_vec(), first.to_vec()),
+        |(mut min, mut max), value| {
+            for (i, v) in value.iter().enumerate() {
+                min[i] = min[i].min(*v);
+                max[i] = max[i].max(*v);
+            }
+            (min, max)
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addr::RawVirtAddr,
+        test_rom::{self, object_addr, SegmentBuilder},
+    };
+
+    /// A skeleton of `limb_count` limbs at 0 and its animation at 0x10, whose
+    /// values all change every frame, value `axis` of `joint` in `frame`
+    /// being `value(joint, axis, frame)`
+    fn animated_skeleton(
+        limb_count: usize,
+        frame_count: usize,
+        value: impl Fn(usize, usize, usize) -> i16,
+    ) -> rom::Reader {
+        let joint_count = limb_count + 1;
+        let indices = (0..joint_count * 3)
+            .map(|n| (n * frame_count) as u16)
+            .collect::<Vec<_>>();
+        let frame_data = (0..joint_count * 3)
+            .flat_map(|n| (0..frame_count).map(move |frame| (n, frame)))
+            .map(|(n, frame)| value(n / 3, n % 3, frame))
+            .collect::<Vec<_>>();
+        SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x400), limb_count as _)
+            .animation_header(
+                0x10,
+                frame_count as _,
+                object_addr(0x100),
+                object_addr(0x40),
+                0,
+            )
+            .u16s(0x40, &indices)
+            .i16s(0x100, &frame_data)
+            .into_object_reader()
+    }
+
+    /// Writes the animation of `animated_skeleton` to a new root
+    fn write_animation(reader: &rom::Reader, options: &AnimationOptions) -> json::Root {
+        write_named_animation(reader, "anim", options)
+    }
+
+    fn write_named_animation(
+        reader: &rom::Reader,
+        name: &str,
+        options: &AnimationOptions,
+    ) -> json::Root {
+        let skeleton_header = reader
+            .read(RawVirtAddr::new(object_addr(0)).into())
+            .unwrap();
+        let mut root = json::Root::default();
+        read_into_gltf(
+            &mut root,
+            reader,
+            &skeleton_header,
+            name,
+            AnimationAddr::Standard(RawVirtAddr::new(object_addr(0x10)).into()),
+            None,
+            options,
+        )
+        .unwrap();
+        root
+    }
+
+    /// The joint values of every frame, as `(joint, x, y, z)`
+    fn frames(
+        reader: &rom::Reader,
+        addr: u32,
+        frame_count: usize,
+        limb_count: usize,
+    ) -> Vec<Vec<(usize, i16, i16, i16)>> {
+        let animation_header = AnimationHeader::read(
+            reader,
+            AnimationAddr::Standard(RawVirtAddr::new(addr).into()),
+        )
+        .unwrap();
+        (0..frame_count)
+            .map(|frame_index| {
+                let mut joints = Vec::new();
+                for_each_frame_data(
+                    reader,
+                    &animation_header,
+                    frame_index,
+                    limb_count,
+                    |joint, x, y, z| joints.push((joint, x, y, z)),
+                )
+                .unwrap();
+                joints
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gives_every_channel_a_sampler_of_its_own() {
+        let reader = animated_skeleton(2, 3, |joint, axis, frame| {
+            (joint * 0x1000 + axis * 0x100 + frame * 0x10) as i16
+        });
+        let root = write_animation(&reader, &Default::default());
+
+        let animation = &root.animations[0];
+        assert_eq!(animation.channels.len(), 3);
+        assert_eq!(animation.samplers.len(), 3);
+        let mut samplers = animation
+            .channels
+            .iter()
+            .map(|channel| channel.sampler.value())
+            .collect::<Vec<_>>();
+        samplers.dedup();
+        assert_eq!(samplers, [0, 1, 2]);
+
+        let paths = animation
+            .channels
+            .iter()
+            .map(|channel| (channel.target.node.value(), channel.target.path))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                (0, Valid(json::animation::Property::Translation)),
+                (0, Valid(json::animation::Property::Rotation)),
+                (1, Valid(json::animation::Property::Rotation)),
+            ]
+        );
+
+        // The samplers share their input, but not their output
+        let times = animation.samplers[0].input;
+        let mut outputs = Vec::new();
+        for sampler in &animation.samplers {
+            assert_eq!(sampler.input, times);
+            assert_ne!(sampler.output, times);
+            assert!(!outputs.contains(&sampler.output));
+            outputs.push(sampler.output);
+        }
+    }
+
+    #[test]
+    fn keeps_consecutive_rotations_in_the_same_hemisphere() {
+        let quarter = std::f32::consts::FRAC_1_SQRT_2;
+        let mut rotations = [
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, -quarter, -quarter],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, -quarter, quarter],
+        ];
+        ensure_shortest_arc(&mut rotations);
+
+        for pair in rotations.windows(2) {
+            let dot = Quat::from_array(pair[0]).dot(Quat::from_array(pair[1]));
+            assert!(
+                dot > 0.0,
+                "{:?} and {:?} are a long arc apart",
+                pair[0],
+                pair[1]
+            );
+        }
+        assert_eq!(rotations[1], [0.0, 0.0, quarter, quarter]);
+        assert_eq!(rotations[3], [0.0, 0.0, quarter, -quarter]);
+    }
+
+    #[test]
+    fn reads_every_value_as_dynamic_with_a_static_index_max_of_0() {
+        let reader = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x400), 1)
+            .animation_header(0x10, 2, object_addr(0x20), object_addr(0x40), 0)
+            .i16s(0x20, &[1, 2, 3, 4, 5, 6])
+            .u16s(0x40, &[0, 2, 4, 0, 2, 4])
+            .into_object_reader();
+
+        assert_eq!(
+            frames(&reader, object_addr(0x10), 2, 1),
+            [[(0, 1, 3, 5), (1, 1, 3, 5)], [(0, 2, 4, 6), (1, 2, 4, 6)],]
+        );
+
+        let animation_header = reader
+            .read(RawVirtAddr::new(object_addr(0x10)).into())
+            .unwrap();
+        let warnings = test_rom::logged_warnings(|| {
+            check_static_index_max(&reader, &animation_header, 1).unwrap();
+        });
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn reads_every_value_as_static_with_an_oversized_static_index_max() {
+        let reader = SegmentBuilder::new()
+            .skeleton_header(0x00, object_addr(0x400), 1)
+            .animation_header(0x10, 2, object_addr(0x20), object_addr(0x40), 100)
+            .i16s(0x20, &[1, 2, 3, 4, 5, 6])
+            .u16s(0x40, &[0, 2, 4, 1, 3, 5])
+            .into_object_reader();
+
+        assert_eq!(
+            frames(&reader, object_addr(0x10), 2, 1),
+            [[(0, 1, 3, 5), (1, 2, 4, 6)], [(0, 1, 3, 5), (1, 2, 4, 6)],]
+        );
+
+        let animation_header = reader
+            .read(RawVirtAddr::new(object_addr(0x10)).into())
+            .unwrap();
+        let warnings = test_rom::logged_warnings(|| {
+            check_static_index_max(&reader, &animation_header, 1).unwrap();
+        });
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("exceeds every joint index (max 5)"));
+    }
+
+    /// The time samples of the first sampler, with the accessor's min and max
+    fn times(root: &json::Root) -> (Vec<f32>, json::Value, json::Value) {
+        let input = root.animations[0].samplers[0].input;
+        let times = test_rom::accessor_elements(root, input)
+            .iter()
+            .map(|bytes| f32::from_le_bytes(bytes[..].try_into().unwrap()))
+            .collect();
+        let accessor = &root.accessors[input.value()];
+        (
+            times,
+            accessor.min.clone().unwrap(),
+            accessor.max.clone().unwrap(),
+        )
+    }
+
+    #[test]
+    fn shifts_every_time_sample_by_the_time_offset() {
+        let reader = animated_skeleton(1, 3, |_, _, frame| frame as i16 * 0x100);
+        let options = AnimationOptions {
+            frame_duration: 0.5,
+            ..Default::default()
+        };
+        let (times_unshifted, min, max) = times(&write_animation(&reader, &options));
+        assert_eq!(times_unshifted, [0.0, 0.5, 1.0]);
+        assert_eq!(
+            (min, max),
+            (json::Value::from(vec![0.0]), json::Value::from(vec![1.0]))
+        );
+
+        let options = AnimationOptions {
+            time_offset: 1.0,
+            ..options
+        };
+        let (times_shifted, min, max) = times(&write_animation(&reader, &options));
+        assert_eq!(times_shifted, [1.0, 1.5, 2.0]);
+        assert_eq!(
+            (min, max),
+            (json::Value::from(vec![1.0]), json::Value::from(vec![2.0]))
+        );
+    }
+
+    #[test]
+    fn writes_channels_only_for_limbs_that_move() {
+        // Limb 1 turns, limb 2 holds its rotation and limb 0 is the root
+        let reader = animated_skeleton(3, 4, |joint, _, frame| match joint {
+            2 => frame as i16 * 0x400,
+            _ => 0x100,
+        });
+        let options = AnimationOptions {
+            skip_constant_channels: true,
+            ..Default::default()
+        };
+        let root = write_animation(&reader, &options);
+
+        let paths = root.animations[0]
+            .channels
+            .iter()
+            .map(|channel| (channel.target.node.value(), channel.target.path))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                (0, Valid(json::animation::Property::Translation)),
+                (0, Valid(json::animation::Property::Rotation)),
+                (1, Valid(json::animation::Property::Rotation)),
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_a_slow_drift_from_a_constant() {
+        let step = CONSTANT_EPSILON * 0.9;
+        let drift = (0..10).map(|i| [i as f32 * step]).collect::<Vec<_>>();
+        assert!(!is_constant(&drift));
+
+        let jitter = (0..10).map(|i| [(i % 2) as f32 * step]).collect::<Vec<_>>();
+        assert!(is_constant(&jitter));
+    }
+
+    #[test]
+    fn hints_whether_animations_loop() {
+        let reader = animated_skeleton(1, 2, |_, _, frame| frame as i16);
+        let looping = |name, looping| {
+            let options = AnimationOptions {
+                looping,
+                ..Default::default()
+            };
+            let root = write_named_animation(&reader, name, &options);
+            let extras = root.animations[0].extras.as_ref().unwrap();
+            json::deserialize::from_str::<json::Value>(extras.get()).unwrap()["loop"].clone()
+        };
+
+        assert_eq!(looping("walk", None), false);
+        assert_eq!(looping("Idle_Look", None), true);
+        assert_eq!(looping("walk", Some(true)), true);
+        assert_eq!(looping("idle", Some(false)), false);
+    }
+
+    #[test]
+    fn spaces_frames_by_the_frame_duration() {
+        let reader = animated_skeleton(1, 3, |_, _, frame| frame as i16 * 0x100);
+
+        // The game runs at 20 frames per second
+        let (times_default, min, max) = times(&write_animation(&reader, &Default::default()));
+        assert_eq!(times_default, [0.0, 1.0 / 20.0, 2.0 / 20.0]);
+        assert_eq!(
+            (min, max),
+            (
+                json::Value::from(vec![0.0]),
+                json::Value::from(vec![2.0f32 / 20.0])
+            )
+        );
+
+        let options = AnimationOptions {
+            frame_duration: 1.0 / 30.0,
+            ..Default::default()
+        };
+        let (times_30, _, max) = times(&write_animation(&reader, &options));
+        assert_eq!(times_30, [0.0, 1.0 / 30.0, 2.0 / 30.0]);
+        assert_eq!(max, json::Value::from(vec![2.0f32 / 30.0]));
+    }
+
+    #[test]
+    fn resolves_static_and_dynamic_joint_indices() {
+        // Two static values, then a run of three frames
+        let table = [10, 20, 100, 101, 102];
+        let frame_data = |i: i32| {
+            table
+                .get(i as usize)
+                .copied()
+                .with_context(|| format!("Index {} out of the table", i))
+        };
+        let value = |joint_index, frame_index| {
+            resolve_frame_value(2, frame_index, joint_index, frame_data).unwrap()
+        };
+
+        assert_eq!([0, 1, 2].map(|frame| value(1, frame)), [20, 20, 20]);
+        assert_eq!([0, 1, 2].map(|frame| value(2, frame)), [100, 101, 102]);
+        assert!(resolve_frame_value(2, 3, 2, frame_data).is_err());
+
+        // Indices past i16::MAX are still compared unsigned
+        let far = |i: i32| Ok(if i == 0x8000 { 1 } else { 2 });
+        assert_eq!(resolve_frame_value(0x9000, 5, 0x8000, far).unwrap(), 1);
+        assert_eq!(resolve_frame_value(0x7000, 0, 0x8000, far).unwrap(), 1);
+        assert_eq!(resolve_frame_value(0x7000, 1, 0x8000, far).unwrap(), 2);
+    }
+
+    #[test]
+    fn samples_every_channel_once_per_time() {
+        let reader = animated_skeleton(3, 5, |joint, axis, frame| {
+            (joint * 0x1000 + axis * 0x100 + frame * 0x10) as i16
+        });
+        let root = write_animation(&reader, &Default::default());
+
+        let animation = &root.animations[0];
+        assert_eq!(animation.channels.len(), 4);
+        for sampler in &animation.samplers {
+            assert_eq!(root.accessors[sampler.input.value()].count, 5);
+            assert_eq!(root.accessors[sampler.output.value()].count, 5);
+        }
+    }
+
+    #[test]
+    fn reads_static_and_dynamic_values() {
+        // Index 0 holds a static value, the others runs of one value per frame
+        let reader = SegmentBuilder::new()
+            .animation_header(0x00, 2, object_addr(0x20), object_addr(0x40), 1)
+            .i16s(0x20, &[7, 10, 11, 20, 21, 30, 31])
+            .u16s(0x40, &[0, 1, 3, 0, 0, 5])
+            .into_object_reader();
+
+        assert_eq!(
+            frames(&reader, object_addr(0), 2, 1),
+            [
+                [(0, 7, 10, 20), (1, 7, 7, 30)],
+                [(0, 7, 11, 21), (1, 7, 7, 31)],
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Segments of big-endian structs at known offsets, for unit tests of the
+//! parsers without a ROM
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use gltf::json::{self, validation::Checked::Valid};
+
+use crate::{
+    buffer,
+    rom::{Reader, Segment},
+};
+
+/// The segmented address of an offset into the object segment
+pub fn object_addr(offset: usize) -> u32 {
+    0x06000000 | offset as u32
+}
+
+/// The data of one segment, written struct by struct at the given offsets.
+/// The segment grows as needed, with zeros in between.
+#[derive(Default, Clone)]
+pub struct SegmentBuilder {
+    data: Vec<u8>,
+}
+impl SegmentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(mut self, offset: usize, bytes: &[u8]) -> Self {
+        if self.data.len() < offset + bytes.len() {
+            self.data.resize(offset + bytes.len(), 0);
+        }
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub fn u16s(self, offset: usize, values: &[u16]) -> Self {
+        let bytes = values
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>();
+        self.bytes(offset, &bytes)
+    }
+
+    pub fn i16s(self, offset: usize, values: &[i16]) -> Self {
+        let bytes = values
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>();
+        self.bytes(offset, &bytes)
+    }
+
+    pub fn u32s(self, offset: usize, values: &[u32]) -> Self {
+        let bytes = values
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>();
+        self.bytes(offset, &bytes)
+    }
+
+    /// Display list commands, see the `g_*` functions
+    pub fn commands(self, offset: usize, commands: &[u64]) -> Self {
+        let bytes = commands
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>();
+        self.bytes(offset, &bytes)
+    }
+
+    /// A `SkeletonHeader` whose limb pointers are at `limbs`
+    pub fn skeleton_header(self, offset: usize, limbs: u32, limb_count: u8) -> Self {
+        self.u32s(offset, &[limbs]).bytes(offset + 4, &[limb_count])
+    }
+
+    /// A `SkinLimb` of the given type, 11 for a limb drawing the display list
+    /// at `segment`
+    pub fn skin_limb(
+        self,
+        offset: usize,
+        joint_pos: [i16; 3],
+        [child, sibling]: [u8; 2],
+        segment_type: u32,
+        segment: u32,
+    ) -> Self {
+        self.i16s(offset, &joint_pos)
+            .bytes(offset + 6, &[child, sibling])
+            .u32s(offset + 8, &[segment_type, segment])
+    }
+
+    /// A `CurveLimb` drawing an opaque and a translucent display list
+    pub fn curve_limb(self, offset: usize, [child, sibling]: [u8; 2], dlists: [u32; 2]) -> Self {
+        self.bytes(offset, &[child, sibling])
+            .u32s(offset + 4, &dlists)
+    }
+
+    /// An `AnimationHeader`
+    pub fn animation_header(
+        self,
+        offset: usize,
+        frame_count: i16,
+        frame_data: u32,
+        joint_indices: u32,
+        static_index_max: u16,
+    ) -> Self {
+        self.i16s(offset, &[frame_count])
+            .u32s(offset + 4, &[frame_data, joint_indices])
+            .u16s(offset + 12, &[static_index_max])
+    }
+
+    /// `Vtx` of the given positions, texture coordinates and colors or normals
+    pub fn vertices(self, offset: usize, vertices: &[([i16; 3], [i16; 2], [u8; 4])]) -> Self {
+        vertices
+            .iter()
+            .enumerate()
+            .fold(self, |builder, (i, (pos, tpos, cn))| {
+                let offset = offset + i * 0x10;
+                builder
+                    .i16s(offset, pos)
+                    .i16s(offset + 8, tpos)
+                    .bytes(offset + 12, cn)
+            })
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// A reader with the data loaded into `segment`
+    pub fn into_reader(self, segment: Segment) -> Reader {
+        let mut reader = Reader::new();
+        reader.set_segment(segment, Some(self.data));
+        reader
+    }
+
+    /// A reader with the data loaded into the object segment
+    pub fn into_object_reader(self) -> Reader {
+        self.into_reader(Segment::Object)
+    }
+}
+
+/// `gsSPVertex`, loading `count` vertices into the vertex cache from `dest`
+pub fn g_vtx(addr: u32, count: u32, dest: u32) -> u64 {
+    0x01 << 56 | (count as u64) << 44 | (((dest + count) * 2) as u64) << 32 | addr as u64
+}
+
+/// `gsSP1Triangle`
+pub fn g_tri1(a: u32, b: u32, c: u32) -> u64 {
+    0x05 << 56 | ((a * 2) as u64) << 48 | ((b * 2) as u64) << 40 | ((c * 2) as u64) << 32
+}
+
+/// `gsSP2Triangles`
+pub fn g_tri2([a, b, c]: [u32; 3], [d, e, f]: [u32; 3]) -> u64 {
+    0x06 << 56
+        | ((a * 2) as u64) << 48
+        | ((b * 2) as u64) << 40
+        | ((c * 2) as u64) << 32
+        | ((d * 2) as u64) << 16
+        | ((e * 2) as u64) << 8
+        | (f * 2) as u64
+}
+
+/// `gsSPDisplayList`, calling the list at `addr`
+pub fn g_dl(addr: u32) -> u64 {
+    0xDE << 56 | addr as u64
+}
+
+/// `gsSPBranchList`, continuing with the list at `addr` without returning
+pub fn g_branch_list(addr: u32) -> u64 {
+    0xDE01 << 48 | addr as u64
+}
+
+/// `gsDPSetTileSize` of a tile of `width` by `height` texels at 0, 0
+pub fn g_settilesize(tile: u32, width: u32, height: u32) -> u64 {
+    0xF2 << 56
+        | (tile as u64) << 24
+        | (((width - 1) << 2) as u64) << 12
+        | ((height - 1) << 2) as u64
+}
+
+/// `gsSPEndDisplayList`
+pub fn g_enddl() -> u64 {
+    0xDF << 56
+}
+
+/// Draws a triangle of three vertices at `vertices`, as the display list at
+/// `offset` of `builder`
+pub fn triangle_list(builder: SegmentBuilder, offset: usize, vertices: usize) -> SegmentBuilder {
+    builder
+        .vertices(
+            vertices,
+            &[
+                ([0, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ([100, 0, 0], [0, 0], [0, 0, 127, 0xFF]),
+                ([0, 100, 0], [0, 0], [0, 0, 127, 0xFF]),
+            ],
+        )
+        .commands(
+            offset,
+            &[
+                g_vtx(object_addr(vertices), 3, 0),
+                g_tri1(0, 1, 2),
+                g_enddl(),
+            ],
+        )
+}
+
+/// The size of the first file of every ROM, holding the header and boot code
+const MAKEROM_SIZE: usize = 0x1060;
+
+/// A big-endian ROM of the given files, uncompressed and after dmadata.
+/// dmadata is file 1, so the given files start at index 2.
+pub fn rom(game_code: &[u8; 4], files: &[&[u8]]) -> Vec<u8> {
+    let table_size = (files.len() + 3) * 0x10;
+    let mut starts = vec![0, MAKEROM_SIZE];
+    let mut end = MAKEROM_SIZE + table_size;
+    for file in files {
+        starts.push(end);
+        end = (end + file.len()).next_multiple_of(0x10);
+    }
+    let mut sizes = vec![MAKEROM_SIZE, table_size];
+    sizes.extend(files.iter().map(|file| file.len()));
+
+    let table = starts
+        .iter()
+        .zip(&sizes)
+        .flat_map(|(start, size)| [*start as u32, (start + size) as u32, *start as u32, 0])
+        .collect::<Vec<_>>();
+
+    let mut builder = SegmentBuilder::new()
+        .u32s(0, &[0x80371240])
+        .bytes(0x3B, game_code)
+        .u32s(MAKEROM_SIZE, &table);
+    for (start, file) in starts[2..].iter().zip(files) {
+        builder = builder.bytes(*start, file);
+    }
+    builder.bytes(end, &[]).data
+}
+
+/// A ROM written to a temporary file, removed when dropped
+pub struct TempRom(PathBuf);
+impl TempRom {
+    /// Writes `data` to a file whose name is unique to `name`
+    pub fn new(name: &str, data: &[u8]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("armos-test-{}-{}.z64", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TempRom {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Compresses `data` as Yaz0, taking the longest back reference at every
+/// byte. Slow, but enough for the small data of tests.
+pub fn yaz0(data: &[u8]) -> Vec<u8> {
+    let mut out = b"Yaz0".to_vec();
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend([0; 8]);
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let code = out.len();
+        out.push(0);
+        for bit in (0..8).rev() {
+            if pos >= data.len() {
+                break;
+            }
+
+            // The reference may overlap the bytes it produces
+            let (distance, count) = (1..=pos.min(0x1000))
+                .map(|distance| {
+                    let count = (0..0x111.min(data.len() - pos))
+                        .take_while(|i| data[pos + i] == data[pos - distance + i])
+                        .count();
+                    (distance, count)
+                })
+                .max_by_key(|(_, count)| *count)
+                .unwrap_or_default();
+
+            let d = distance.saturating_sub(1);
+            match count {
+                0..=2 => {
+                    out[code] |= 1 << bit;
+                    out.push(data[pos]);
+                    pos += 1;
+                    continue;
+                }
+                3..=0x11 => out.extend([((count - 2) << 4 | d >> 8) as u8, d as u8]),
+                _ => out.extend([(d >> 8) as u8, d as u8, (count - 0x12) as u8]),
+            }
+            pos += count;
+        }
+    }
+    out
+}
+
+/// The bytes of every element of an accessor of `root`
+pub fn accessor_elements(root: &json::Root, index: json::Index<json::Accessor>) -> Vec<Vec<u8>> {
+    let mut root = root.clone();
+    let buffers = buffer::take_buffers(&mut root).unwrap();
+    let accessor = &root.accessors[index.value()];
+    let view = &root.buffer_views[accessor.buffer_view.unwrap().value()];
+    let (Valid(component_type), Valid(type_)) = (&accessor.component_type, &accessor.type_) else {
+        panic!("Invalid accessor {}", index);
+    };
+
+    let size = component_type.0.size() * type_.multiplicity();
+    let stride = view.byte_stride.map_or(size, |stride| stride as usize);
+    let start = view.byte_offset.unwrap_or(0) as usize + accessor.byte_offset as usize;
+    let data = &buffers[view.buffer.value()];
+    (0..accessor.count as usize)
+        .map(|i| data[start + i * stride..][..size].to_vec())
+        .collect()
+}
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps the warnings logged by each test to the test's thread
+struct WarningLogger;
+impl log::Log for WarningLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            WARNINGS.with(|warnings| warnings.borrow_mut().push(record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Runs `f`, returning the warnings it logged
+pub fn logged_warnings(f: impl FnOnce()) -> Vec<String> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&WarningLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Warn);
+    });
+
+    WARNINGS.with(|warnings| warnings.borrow_mut().clear());
+    f();
+    WARNINGS.with(|warnings| warnings.take())
+}
@@ -0,0 +1,229 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Context, Result};
+use num_derive::FromPrimitive;
+
+use crate::{addr::RawVirtAddr, rom};
+
+/// The `G_IM_FMT_*` image formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum Format {
+    Rgba = 0,
+    Yuv = 1,
+    Ci = 2,
+    Ia = 3,
+    I = 4,
+}
+
+/// The texel sizes of `G_IM_SIZ_*`, in bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive)]
+pub enum Size {
+    Bits4 = 0,
+    Bits8 = 1,
+    Bits16 = 2,
+    Bits32 = 3,
+}
+impl Size {
+    pub fn bits(&self) -> usize {
+        4 << *self as usize
+    }
+}
+
+/// A texture drawn with: where its texels are and how they are decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Texture {
+    pub addr: RawVirtAddr,
+    pub format: Format,
+    pub size: Size,
+    pub width: u32,
+    pub height: u32,
+
+    /// The address of the RGBA16 palette entry of color index 0, for color
+    /// indexed textures
+    pub palette: Option<RawVirtAddr>,
+
+    /// How many palette entries were loaded from `palette` on, which texels
+    /// may index
+    pub palette_len: u32,
+}
+impl Texture {
+    /// Reads and decodes the texels to RGBA8
+    pub fn decode(&self, reader: &rom::Reader) -> Result<Vec<u8>> {
+        let texel_count = (self.width * self.height) as usize;
+        let data = reader
+            .slice_from(self.addr)?
+            .get(..(texel_count * self.size.bits()).div_ceil(8))
+            .with_context(|| format!("Texture {} is truncated", self))?;
+
+        // Texels of 4 bits are packed two to a byte, the first in the high bits
+        let texels = (0..texel_count).map(|i| match self.size {
+            Size::Bits4 => ((data[i / 2] >> (4 - (i % 2) * 4)) & 0xF) as u32,
+            Size::Bits8 => data[i] as u32,
+            Size::Bits16 => u16::from_be_bytes([data[i * 2], data[i * 2 + 1]]) as u32,
+            Size::Bits32 => u32::from_be_bytes(data[i * 4..i * 4 + 4].try_into().unwrap()),
+        });
+
+        let palette = match (self.format, self.palette) {
+            (Format::Ci, Some(palette)) => {
+                let count = self.palette_len.min(1 << self.size.bits().min(8)) as usize;
+                let palette = reader
+                    .slice_from(palette)?
+                    .get(..count * 2)
+                    .with_context(|| format!("Palette of texture {} is truncated", self))?;
+                palette
+                    .chunks_exact(2)
+                    .map(|color| rgba16(u16::from_be_bytes([color[0], color[1]])))
+                    .collect()
+            }
+            (Format::Ci, None) => bail!("Texture {} has no palette", self),
+            _ => Vec::new(),
+        };
+
+        let mut rgba = Vec::with_capacity(texel_count * 4);
+        for texel in texels {
+            let color = match (self.format, self.size) {
+                (Format::Rgba, Size::Bits16) => rgba16(texel as u16),
+                (Format::Rgba, Size::Bits32) => texel.to_be_bytes(),
+                (Format::Ci, Size::Bits4 | Size::Bits8) => {
+                    *palette.get(texel as usize).with_context(|| {
+                        format!(
+                            "Texel of texture {} indexes {} of {} palette entries",
+                            self,
+                            texel,
+                            palette.len()
+                        )
+                    })?
+                }
+                (Format::Ia, Size::Bits4) => {
+                    let i = expand((texel >> 1) as u8, 3);
+                    [i, i, i, if texel & 1 != 0 { 0xFF } else { 0 }]
+                }
+                (Format::Ia, Size::Bits8) => {
+                    let i = expand((texel >> 4) as u8, 4);
+                    [i, i, i, expand((texel & 0xF) as u8, 4)]
+                }
+                (Format::Ia, Size::Bits16) => {
+                    let i = (texel >> 8) as u8;
+                    [i, i, i, texel as u8]
+                }
+                (Format::I, Size::Bits4) => [expand(texel as u8, 4); 4],
+                (Format::I, Size::Bits8) => [texel as u8; 4],
+                _ => bail!("Unsupported texture format {}", self),
+            };
+            rgba.extend_from_slice(&color);
+        }
+
+        Ok(rgba)
+    }
+
+    /// Decodes the texels into a PNG image
+    pub fn to_png(&self, reader: &rom::Reader) -> Result<Vec<u8>> {
+        let rgba = self.decode(reader)?;
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&rgba)?;
+
+        Ok(png)
+    }
+}
+impl Display for Texture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{:?}{}_{}x{}",
+            self.addr,
+            self.format,
+            self.size.bits(),
+            self.width,
+            self.height
+        )?;
+        // The same texels drawn with another palette make another image
+        if let Some(palette) = self.palette {
+            write!(f, "_palette_{}", palette)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a 5-5-5-1 color to RGBA8
+fn rgba16(color: u16) -> [u8; 4] {
+    [
+        expand((color >> 11) as u8 & 0x1F, 5),
+        expand((color >> 6) as u8 & 0x1F, 5),
+        expand((color >> 1) as u8 & 0x1F, 5),
+        if color & 1 != 0 { 0xFF } else { 0 },
+    ]
+}
+
+/// Scales a value of the given number of bits to 8 bits
+fn expand(value: u8, bits: u32) -> u8 {
+    ((value as u32 * 0xFF) / ((1 << bits) - 1)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{object_addr, SegmentBuilder};
+
+    #[test]
+    fn decodes_rgba16_texels() {
+        let reader = SegmentBuilder::new()
+            .u16s(0, &[0xF801, 0x07C0])
+            .into_object_reader();
+        let texture = Texture {
+            addr: RawVirtAddr::new(object_addr(0)),
+            format: Format::Rgba,
+            size: Size::Bits16,
+            width: 2,
+            height: 1,
+            palette: None,
+            palette_len: 0,
+        };
+
+        assert_eq!(
+            texture.decode(&reader).unwrap(),
+            [0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0]
+        );
+        assert!(texture.to_png(&reader).unwrap().starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn decodes_color_indexed_texels_through_the_palette() {
+        let reader = SegmentBuilder::new()
+            .bytes(0, &[0x01, 0x20])
+            .u16s(0x10, &[0x0001, 0xF801, 0x003F])
+            .into_object_reader();
+        let texture = Texture {
+            addr: RawVirtAddr::new(object_addr(0)),
+            format: Format::Ci,
+            size: Size::Bits4,
+            width: 2,
+            height: 2,
+            palette: Some(RawVirtAddr::new(object_addr(0x10))),
+            palette_len: 3,
+        };
+
+        assert_eq!(
+            texture
+                .decode(&reader)
+                .unwrap()
+                .chunks(4)
+                .collect::<Vec<_>>(),
+            [
+                [0, 0, 0, 0xFF],
+                [0xFF, 0, 0, 0xFF],
+                [0, 0, 0xFF, 0xFF],
+                [0, 0, 0, 0xFF],
+            ]
+        );
+
+        let texture = Texture {
+            palette_len: 2,
+            ..texture
+        };
+        assert!(texture.decode(&reader).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fmt::Display, ops::Range};
+
+use anyhow::{bail, Context, Result};
+
+use crate::rom;
+
+/// The cartridge releases of Ocarina of Time, which differ in where files
+/// are in the ROM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomVersion {
+    NtscJ10,
+    NtscJ11,
+    NtscJ12,
+    NtscU10,
+    NtscU11,
+    NtscU12,
+    Pal10,
+    Pal11,
+}
+impl Display for RomVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RomVersion::NtscJ10 => "NTSC-J 1.0",
+            RomVersion::NtscJ11 => "NTSC-J 1.1",
+            RomVersion::NtscJ12 => "NTSC-J 1.2",
+            RomVersion::NtscU10 => "NTSC-U 1.0",
+            RomVersion::NtscU11 => "NTSC-U 1.1",
+            RomVersion::NtscU12 => "NTSC-U 1.2",
+            RomVersion::Pal10 => "PAL 1.0",
+            RomVersion::Pal11 => "PAL 1.1",
+        };
+        f.write_str(name)
+    }
+}
+
+impl RomVersion {
+    /// The virtual ROM address of the object table, in the `code` file, for
+    /// the versions it is known for
+    pub fn object_table_vrom(&self) -> Option<u32> {
+        match self {
+            RomVersion::NtscU10 => Some(0x00B6EF58),
+            _ => None,
+        }
+    }
+
+    /// The CRC words of the header of the unmodified release. NTSC-J and
+    /// NTSC-U only differ in their header, so they checksum the same.
+    pub fn header_crcs(&self) -> [u32; 2] {
+        match self {
+            RomVersion::NtscJ10 | RomVersion::NtscU10 => [0xEC7011B7, 0x7616D72B],
+            RomVersion::NtscJ11 | RomVersion::NtscU11 => [0xD43DA81F, 0x021E1E19],
+            RomVersion::NtscJ12 | RomVersion::NtscU12 => [0x693BA2AE, 0xB7F14E9F],
+            RomVersion::Pal10 => [0xB044B569, 0x373C1985],
+            RomVersion::Pal11 => [0xB2055FBD, 0x0BAB4E0C],
+        }
+    }
+}
+
+/// The game code and revision in the header of each known release
+const KNOWN_VERSIONS: [(&[u8; 4], u8, RomVersion); 8] = [
+    (b"NZLJ", 0, RomVersion::NtscJ10),
+    (b"NZLJ", 1, RomVersion::NtscJ11),
+    (b"NZLJ", 2, RomVersion::NtscJ12),
+    (b"NZLE", 0, RomVersion::NtscU10),
+    (b"NZLE", 1, RomVersion::NtscU11),
+    (b"NZLE", 2, RomVersion::NtscU12),
+    (b"NZLP", 0, RomVersion::Pal10),
+    (b"NZLP", 1, RomVersion::Pal11),
+];
+
+/// Identifies the release of the loaded ROM from its header
+pub fn detect_version(reader: &rom::Reader) -> Result<RomVersion> {
+    let header = reader
+        .rom_header()
+        .context("No ROM was loaded, or it is too short to have a header")?;
+    let game_code = String::from_utf8_lossy(&header.game_code);
+
+    log::debug!(
+        "ROM header: game code {}, revision {}, CRC {:08X} {:08X}",
+        game_code,
+        header.revision,
+        header.crc1.get(),
+        header.crc2.get()
+    );
+
+    if let Some((_, _, version)) = KNOWN_VERSIONS
+        .iter()
+        .find(|(code, revision, _)| **code == header.game_code && *revision == header.revision)
+    {
+        return Ok(*version);
+    }
+
+    match &header.game_code[1..3] {
+        b"ZS" => bail!("Majora's Mask ROMs ({}) are not supported", game_code),
+        b"ZL" => bail!(
+            "Unknown revision {} of Ocarina of Time ({})",
+            header.revision,
+            game_code
+        ),
+        _ => bail!(
+            "Not an Ocarina of Time ROM (game code {:?}), or it is not in big-endian .z64 order",
+            game_code
+        ),
+    }
+}
+
+/// The seed of the checksum of the CIC-NUS-6105, the boot chip of every
+/// Ocarina of Time cartridge
+const CIC_6105_SEED: u32 = 0xDF26F436;
+
+/// The checksummed part of the ROM, the first MiB after the boot code
+const CHECKSUM_RANGE: Range<usize> = 0x1000..0x101000;
+
+/// Recomputes the checksum the boot code verifies and compares it to the CRC
+/// words of the header, catching truncated and patched ROMs
+pub fn verify_checksum(reader: &rom::Reader) -> Result<()> {
+    let data = reader.rom_data().context("No ROM was loaded")?;
+    let header = reader
+        .rom_header()
+        .context("The ROM is too short to have a header")?;
+    if data.len() < CHECKSUM_RANGE.end {
+        bail!(
+            "The ROM is truncated, it is {:#X} bytes but the checksum covers {:#X}",
+            data.len(),
+            CHECKSUM_RANGE.end
+        );
+    }
+
+    let word = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+    let [mut t1, mut t2, mut t3, mut t4, mut t5, mut t6] = [CIC_6105_SEED; 6];
+    for offset in CHECKSUM_RANGE.step_by(4) {
+        let d = word(offset);
+        if t6.wrapping_add(d) < t6 {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = t6.wrapping_add(d);
+        t3 ^= d;
+        let r = d.rotate_left(d & 0x1F);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        // The 6105 mixes in the words of the boot code instead of t5
+        t1 = t1.wrapping_add(word(0x750 + (offset & 0xFF)) ^ d);
+    }
+    let crc = [t6 ^ t4 ^ t3, t5 ^ t2 ^ t1];
+
+    let expected = [header.crc1.get(), header.crc2.get()];
+    if crc != expected {
+        bail!(
+            "The ROM checksum is {:08X} {:08X} but its header says {:08X} {:08X}, the ROM is corrupt or modified",
+            crc[0],
+            crc[1],
+            expected[0],
+            expected[1]
+        );
+    }
+    Ok(())
+}
+
+/// Compares the CRC words of the header to those of the release `version`,
+/// which differ for ROMs that were patched and had their checksum fixed up
+pub fn verify_header_crcs(reader: &rom::Reader, version: RomVersion) -> Result<()> {
+    let header = reader
+        .rom_header()
+        .context("The ROM is too short to have a header")?;
+    let crcs = [header.crc1.get(), header.crc2.get()];
+    let expected = version.header_crcs();
+    if crcs != expected {
+        bail!(
+            "The ROM header CRCs are {:08X} {:08X} but those of {} are {:08X} {:08X}, the ROM is modified",
+            crcs[0],
+            crcs[1],
+            version,
+            expected[0],
+            expected[1]
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{self, TempRom};
+
+    #[test]
+    fn compares_the_header_crcs_to_those_of_the_release() {
+        let mut data = test_rom::rom(b"NZLP", &[]);
+        let rom = TempRom::new("crcs-modified", &data);
+        let reader = rom::Reader::from_rom(rom.path()).unwrap();
+        let version = detect_version(&reader).unwrap();
+        assert_eq!(version, RomVersion::Pal10);
+        assert_eq!(
+            verify_header_crcs(&reader, version).unwrap_err().to_string(),
+            "The ROM header CRCs are 00000000 00000000 but those of PAL 1.0 are B044B569 373C1985, the ROM is modified"
+        );
+
+        data[0x10..0x18].copy_from_slice(&[0xB0, 0x44, 0xB5, 0x69, 0x37, 0x3C, 0x19, 0x85]);
+        let rom = TempRom::new("crcs-release", &data);
+        let reader = rom::Reader::from_rom(rom.path()).unwrap();
+        verify_header_crcs(&reader, version).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{bail, ensure, Context, Result};
+
+pub const MAGIC: &[u8; 4] = b"Yaz0";
+
+/// Size of the header: the magic, the decompressed size and 8 unused bytes
+const HEADER_SIZE: usize = 0x10;
+
+/// Inflates Yaz0 compressed data, including its header
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    ensure!(data.starts_with(MAGIC), "Missing Yaz0 magic");
+    let size = u32::from_be_bytes(
+        data.get(4..8)
+            .context("Truncated Yaz0 header")?
+            .try_into()?,
+    ) as usize;
+
+    let mut out = Vec::with_capacity(size);
+    let mut input = data.get(HEADER_SIZE..).unwrap_or_default().iter().copied();
+    let mut next = || input.next().context("Yaz0 data ends early");
+
+    while out.len() < size {
+        // Each bit of the code byte, from the highest, tells whether the next
+        // chunk is a literal byte or a back reference
+        let code = next()?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+
+            if code & (1 << bit) != 0 {
+                out.push(next()?);
+                continue;
+            }
+
+            let [b1, b2] = [next()?, next()?];
+            let distance = (((b1 as usize & 0xF) << 8) | b2 as usize) + 1;
+            let count = match b1 >> 4 {
+                0 => next()? as usize + 0x12,
+                n => n as usize + 2,
+            };
+            if distance > out.len() {
+                bail!("Yaz0 back reference before the start of the data");
+            }
+
+            // Byte by byte, as the copied range may overlap what it produces
+            let start = out.len() - distance;
+            for i in 0..count {
+                out.push(out[start + i]);
+            }
+        }
+    }
+
+    out.truncate(size);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom;
+
+    #[test]
+    fn inflates_what_was_compressed() {
+        let text = b"armos armos armos extracts armos".to_vec();
+        let zeros = vec![0; 0x200];
+        let mixed = (0..0x400u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .chain(zeros.iter().copied())
+            .chain(text.iter().copied())
+            .collect::<Vec<_>>();
+
+        for data in [Vec::new(), text, zeros, mixed] {
+            let compressed = test_rom::yaz0(&data);
+            assert!(compressed.starts_with(MAGIC));
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn inflates_literals_and_back_references() {
+        // "ab" as literals, then a 2 byte back reference copying 6 bytes and
+        // a 3 byte one copying 0x12, both overlapping what they produce
+        let data = [
+            b"Yaz0".as_slice(),
+            &[0, 0, 0, 0x1A, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0b1100_0000, b'a', b'b', 0x40, 0x01, 0x00, 0x01, 0x00],
+        ]
+        .concat();
+        let mut expected = b"ababababab".repeat(3);
+        expected.truncate(0x1A);
+        assert_eq!(decompress(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn refuses_broken_data() {
+        assert!(decompress(b"Yaz1\0\0\0\x01").is_err());
+        assert!(decompress(b"Yaz0\0\0").is_err());
+
+        let truncated = test_rom::yaz0(b"armos");
+        assert!(decompress(&truncated[..truncated.len() - 1]).is_err());
+
+        let before_start = [b"Yaz0".as_slice(), &[0, 0, 0, 4], &[0; 8], &[0, 0x10, 0x00]].concat();
+        assert!(decompress(&before_start).is_err());
+    }
+}
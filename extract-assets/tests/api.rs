@@ -0,0 +1,47 @@
+//! Uses nothing but the `api` module, so that changes to its surface break
+//! the build here first
+
+use extract_assets::api::{self, Extraction, MeshOptions, Reader, Segment};
+
+/// A triangle at offset 0x100 of the object segment, drawn by the display
+/// list at 0
+fn triangle_object() -> Reader {
+    let mut data = vec![0u8; 0x130];
+    let commands: [u64; 3] = [
+        0x01003006_06000100,
+        0x05000204_00000000,
+        0xDF000000_00000000,
+    ];
+    for (i, command) in commands.iter().enumerate() {
+        data[i * 8..][..8].copy_from_slice(&command.to_be_bytes());
+    }
+    for (i, pos) in [[0i16, 0, 0], [100, 0, 0], [0, 100, 0]].iter().enumerate() {
+        for (j, n) in pos.iter().enumerate() {
+            data[0x100 + i * 0x10 + j * 2..][..2].copy_from_slice(&n.to_be_bytes());
+        }
+        data[0x100 + i * 0x10 + 12..][..4].copy_from_slice(&[0, 0, 127, 0xFF]);
+    }
+
+    let mut reader = Reader::new();
+    reader.set_segment(Segment::Object, Some(data));
+    reader
+}
+
+#[test]
+fn extracts_a_display_list_through_the_api() {
+    let reader = triangle_object();
+
+    let root = api::extract_display_list_to_gltf(&reader, 0x06000000).unwrap();
+    assert_eq!(root.meshes.len(), 1);
+
+    let root = Extraction::new(&reader)
+        .display_list(0x06000000)
+        .mesh_options(MeshOptions {
+            flat_normals: true,
+            ..Default::default()
+        })
+        .to_gltf()
+        .unwrap();
+    assert_eq!(root.meshes.len(), 1);
+    assert_eq!(root.scenes[0].nodes.len(), 1);
+}